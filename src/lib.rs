@@ -12,18 +12,106 @@ extern crate encoding;
 extern crate byteorder;
 
 mod shn;
+#[macro_use]
+mod shn_record;
 mod shn_reader;
 mod shn_writer;
+mod shn_table;
+mod shn_columnar;
+mod shn_query;
+mod shn_query_parser;
+mod shn_expr;
+mod shn_database;
+mod shn_relation;
+mod shn_join;
+mod shn_metadata;
+mod shn_registry;
+mod shn_codegen;
+mod shn_csv;
+mod shn_definition;
+mod shn_shine;
+mod shn_json;
+#[cfg(test)]
+mod shn_test_util;
 
 pub use shn::{
+    SHN_CRYPT_HEADER_LEN,
+    SHN_ID_COLUMN_NAME,
     ShnDataType,
     ShnCell,
     ShnColumn,
     ShnSchema,
+    ShnHeader,
     ShnRow,
+    ShnKey,
     ShnFile,
+    ContentEqOptions,
     ShnError,
 };
+pub use shn_reader::ReadOptions;
+pub use shn_record::{
+    ShnRecord,
+    ShnColumnDifference,
+};
+#[doc(hidden)]
+pub use shn_record::{ record_field, record_cell };
+pub use shn_columnar::{
+    ShnColumnar,
+    ShnColumnData,
+    ShnColumnType,
+};
+pub use shn_table::{
+    ShnTable,
+    ShnTableRow,
+    ShnTableRows,
+    ShnTableCells,
+    ShnCellRef,
+};
+pub use shn_database::{
+    ShnDatabase,
+    DatabaseOptions,
+};
+pub use shn_join::{
+    ShnJoin,
+    ShnJoinKind,
+    JoinOptions,
+};
+pub use shn_metadata::{
+    ShnMetadata,
+    ShnTableMetadata,
+    ShnColumnMetadata,
+};
+pub use shn_registry::{
+    ShnRegistry,
+    ShnRegistryEntry,
+};
+pub use shn_relation::{
+    ShnRelation,
+    ShnIntegrityIssue,
+    ShnReference,
+};
+pub use shn_expr::{
+    ShnExpr,
+    ShnOperator,
+    ShnFunction,
+    ShnCellChange,
+    ShnUpdateSummary,
+};
+pub use shn_query::{
+    ShnQuery,
+    ShnPredicate,
+    ShnComparison,
+    ShnOrder,
+    ShnResultSet,
+};
+pub use shn_writer::ShnStreamWriter;
+pub use shn_codegen::generate_record_file;
+pub use shn_shine::{
+    ShnShineTable,
+    read_shine_from,
+    write_shine_to,
+    write_shine_file,
+};
 
 /// Reads a `ShnFile` from the provided input, using the given encoding
 /// for any strings
@@ -33,6 +121,34 @@ pub fn read_from<S: std::io::Read>(source: &mut S,
     shn_reader::ShnReader::read_from(source, encoding)
 }
 
+/// Reads a `ShnTable` from the provided input, using the given encoding
+/// for any strings. Unlike `read_from`, this never materializes the rows as
+/// `ShnRow`s.
+pub fn read_table_from<S: std::io::Read>(source: &mut S,
+                                         encoding: &encoding::EncodingRef)
+                                         -> shn::Result<ShnTable> {
+    shn_reader::ShnReader::read_table_from(source, encoding)
+}
+
+/// Reads the parts of a `ShnFile` selected by `options` from the provided
+/// input, using the given encoding for any strings.
+pub fn read_with_options<S>(source: &mut S,
+                            encoding: &encoding::EncodingRef,
+                            options: &ReadOptions)
+                            -> shn::Result<ShnFile>
+    where S: std::io::Read + std::io::Seek {
+    shn_reader::ShnReader::read_with_options(source, encoding, options)
+}
+
+/// Reads only the headers and the schema of a `SHN` file from the provided
+/// input, using the given encoding for column names. None of the rows are
+/// read or decoded.
+pub fn read_header<S: std::io::Read>(source: &mut S,
+                                     encoding: &encoding::EncodingRef)
+                                     -> shn::Result<ShnHeader> {
+    shn_reader::ShnReader::read_header(source, encoding)
+}
+
 /// Writes the `ShnFile` to the provided output, using the given encoding
 /// for any strings.
 pub fn write_to<D: std::io::Write>(dest: &mut D,
@@ -41,3 +157,20 @@ pub fn write_to<D: std::io::Write>(dest: &mut D,
                                    -> shn::Result<()> {
     shn_writer::ShnWriter::write_to(file, encoding, dest)
 }
+
+/// Writes the `ShnFile` to the file at `path`, using the given encoding for
+/// any strings. The file is written to a temporary file next to `path`
+/// first, which then replaces it, so a failure leaves any previous file
+/// intact.
+pub fn write_file<P: AsRef<std::path::Path>>(path: P,
+                                             file: &shn::ShnFile,
+                                             encoding: &encoding::EncodingRef)
+                                             -> shn::Result<()> {
+    shn_database::write_file(path.as_ref(), file, *encoding)
+}
+
+/// Returns the name of the table stored at `path`, which is its file name
+/// without the extension, like `ItemInfo` for `ItemInfo.shn`.
+pub fn table_name<P: AsRef<std::path::Path>>(path: P) -> String {
+    shn_database::table_name(path.as_ref())
+}
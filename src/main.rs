@@ -0,0 +1,383 @@
+//! Command line interface to `shn-rs`.
+
+extern crate shn;
+extern crate encoding;
+
+use std::env;
+use std::fs::{ self, File };
+use std::io::{ self, BufReader, Write };
+use std::path::Path;
+use std::process;
+use std::sync::Arc;
+
+use encoding::EncodingRef;
+use encoding::label::encoding_from_whatwg_label;
+
+use shn::{ ShnFile, ShnError, ShnQuery, ShnPredicate, ShnExpr, ShnMetadata,
+           ShnTableMetadata, ShnRegistry, ShnHeader, ShnSchema,
+           ShnShineTable, SHN_CRYPT_HEADER_LEN };
+
+const USAGE: &'static str = "\
+Usage: shn <command> [options] <arguments>
+
+Commands:
+    query <file> <query>    Runs a query like
+                            \"SELECT ID, Name WHERE Level >= 60 ORDER BY Level\"
+    update <file> <column> <expression>
+                            Sets the column to the expression, like
+                            \"round(AC * 1.1)\", and writes the file back
+    identify <file>         Prints the fingerprint of the file's schema and
+                            the tables and versions known to use it, from
+                            --registry
+    codegen <file>          Prints a Rust record for the file's table, see
+                            `shn_record!`
+    schema <file>           Prints the definition of the file's schema
+    create <schema> <file> [<csv>]
+                            Creates a file from a schema definition, with
+                            the rows of the CSV file if given
+    convert <input> <output>
+                            Converts between `.shn` files and ShineTable
+                            `.txt` files, by their extension
+
+Options:
+    -e, --encoding <label>  Encoding of strings in files, e.g. `euc-kr`
+                            (default: ascii)
+    -f, --format <format>   Output format: table, csv or json (default: table)
+    -w, --where <condition> Only update rows matching the condition, like
+                            \"Level > 50 AND Class = 2\"
+    -o, --output <file>     Write updated files here instead of in place
+    -n, --dry-run           Only print what would be updated
+    -m, --metadata <file>   JSON metadata, whose labels are printed and
+                            accepted in queries and conditions
+    -t, --table <name>      Table of ShineTable files with several tables to
+                            convert
+    -r, --registry <file>   Registry of known schemas for identify, in
+                            addition to the built-in ones, if any
+";
+
+/// Formats query results can be printed in.
+enum Format {
+    Table,
+    Csv,
+    Json,
+}
+
+/// Options shared by all commands.
+struct Options {
+    encoding:   EncodingRef,
+    format:     Format,
+    condition:  Option<String>,
+    output:     Option<String>,
+    dry_run:    bool,
+    metadata:   Option<ShnMetadata>,
+    registry:   ShnRegistry,
+    table:      Option<String>,
+}
+
+fn main() {
+    if let Err(e) = run(env::args().skip(1).collect()) {
+        let _ = writeln!(io::stderr(), "error: {}", e);
+        process::exit(1);
+    }
+}
+
+fn run(args: Vec<String>) -> Result<(), String> {
+    let mut options = Options {
+        encoding:   encoding::all::ASCII,
+        format:     Format::Table,
+        condition:  None,
+        output:     None,
+        dry_run:    false,
+        metadata:   None,
+        registry:   ShnRegistry::new(),
+        table:      None,
+    };
+    let mut positional = Vec::new();
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match &arg[..] {
+            "-e" | "--encoding" => {
+                let label = try!(args.next()
+                                 .ok_or("missing value for --encoding"));
+                options.encoding = try!(encoding_from_whatwg_label(&label)
+                                        .ok_or(format!("unknown encoding `{}`",
+                                                       label)));
+            },
+            "-f" | "--format" => {
+                options.format = match args.next().as_ref().map(|f| &f[..]) {
+                    Some("table")   => Format::Table,
+                    Some("csv")     => Format::Csv,
+                    Some("json")    => Format::Json,
+                    _               => return Err(
+                        "expected table, csv or json for --format".to_owned()),
+                };
+            },
+            "-w" | "--where" => {
+                options.condition = Some(try!(args.next()
+                                              .ok_or("missing value for --where")));
+            },
+            "-o" | "--output" => {
+                options.output = Some(try!(args.next()
+                                           .ok_or("missing value for --output")));
+            },
+            "-n" | "--dry-run" => options.dry_run = true,
+            "-m" | "--metadata" => {
+                let path = try!(args.next()
+                                .ok_or("missing value for --metadata"));
+                let file = try!(File::open(&path).map_err(|e| {
+                    format!("can't open {}: {}", path, e)
+                }));
+                options.metadata = Some(try!(ShnMetadata::read_from(file)
+                                             .map_err(|e| {
+                    format!("can't read {}: {}", path, e)
+                })));
+            },
+            "-t" | "--table" => {
+                options.table = Some(try!(args.next()
+                                          .ok_or("missing value for --table")));
+            },
+            "-r" | "--registry" => {
+                let path = try!(args.next()
+                                .ok_or("missing value for --registry"));
+                let file = try!(File::open(&path).map_err(|e| {
+                    format!("can't open {}: {}", path, e)
+                }));
+                let registry = try!(ShnRegistry::read_from(BufReader::new(file))
+                                    .map_err(|e| {
+                    format!("can't read {}: {}", path, e)
+                }));
+                options.registry.extend(&registry);
+            },
+            "-h" | "--help" => {
+                print!("{}", USAGE);
+                return Ok(());
+            },
+            _ => positional.push(arg),
+        }
+    }
+
+    match positional.first().map(|c| &c[..]) {
+        Some("query")   => query(&options, &positional[1..]),
+        Some("update")  => update(&options, &positional[1..]),
+        Some("identify") => identify(&options, &positional[1..]),
+        Some("codegen") => codegen(&options, &positional[1..]),
+        Some("schema")  => schema(&options, &positional[1..]),
+        Some("create")  => create(&options, &positional[1..]),
+        Some("convert") => convert(&options, &positional[1..]),
+        Some(command)   => Err(format!("unknown command `{}`\n\n{}",
+                                       command, USAGE)),
+        None            => Err(format!("missing command\n\n{}", USAGE)),
+    }
+}
+
+/// Checks that exactly the arguments named in `names` were given.
+fn expect_args(args: &[String], names: &[&str]) -> Result<(), String> {
+    if args.len() == names.len() {
+        Ok(())
+    } else {
+        Err(format!("expected arguments <{}>", names.join("> <")))
+    }
+}
+
+fn read_file(path: &str, options: &Options) -> Result<ShnFile, String> {
+    let file = try!(File::open(path)
+                    .map_err(|e| format!("can't open {}: {}", path, e)));
+    shn::read_from(&mut BufReader::new(file), &options.encoding)
+        .map_err(|e| format!("can't read {}: {}", path, e))
+}
+
+/// Writes `file` to `path`, leaving the previous file intact if that fails.
+fn write_file(path: &str, file: &ShnFile, options: &Options)
+              -> Result<(), String> {
+    shn::write_file(path, file, &options.encoding)
+        .map_err(|e| format!("can't write {}: {}", path, e))
+}
+
+/// Returns the metadata of the table stored at `path`, which is named
+/// after the file.
+fn table_metadata<'a>(path: &str, options: &'a Options)
+                      -> Option<&'a ShnTableMetadata> {
+    let name = Path::new(path).file_stem().map(|s| s.to_string_lossy());
+    match (&options.metadata, name) {
+        (&Some(ref m), Some(name))  => m.table(&name),
+        _                           => None,
+    }
+}
+
+/// Maximum number of row errors printed by `describe`.
+const MAX_ROW_ERRORS: usize = 10;
+
+/// Describes `error`, listing the first rows for errors concerning rows.
+fn describe(error: ShnError) -> String {
+    match error {
+        ShnError::RowErrors(errors) => {
+            let mut text = format!("{} rows failed:", errors.len());
+            for &(row, ref e) in errors.iter().take(MAX_ROW_ERRORS) {
+                text.push_str(&format!("\n    row {}: {}", row, e));
+            }
+            if errors.len() > MAX_ROW_ERRORS {
+                text.push_str(&format!("\n    ... and {} more",
+                                       errors.len() - MAX_ROW_ERRORS));
+            }
+            text
+        },
+        e => e.to_string(),
+    }
+}
+
+fn query(options: &Options, args: &[String]) -> Result<(), String> {
+    try!(expect_args(args, &["file", "query"]));
+    let file = try!(read_file(&args[0], options));
+    let metadata = table_metadata(&args[0], options);
+    let query = try!(ShnQuery::parse_with_metadata(&args[1], &file.schema,
+                                                   metadata)
+                     .map_err(|e| format!("invalid query: {}", e)));
+    let mut result = try!(query.run(&file).map_err(|e| e.to_string()));
+    if let Some(metadata) = metadata {
+        result = result.with_metadata(metadata);
+    }
+
+    let stdout = io::stdout();
+    let mut stdout = stdout.lock();
+    let written = match options.format {
+        Format::Table   => write!(stdout, "{}", result),
+        Format::Csv     => result.write_csv(&mut stdout),
+        Format::Json    => result.write_json(&mut stdout),
+    };
+    written.map_err(|e| e.to_string())
+}
+
+fn update(options: &Options, args: &[String]) -> Result<(), String> {
+    try!(expect_args(args, &["file", "column", "expression"]));
+    let mut file = try!(read_file(&args[0], options));
+    let condition = match options.condition {
+        Some(ref c) => Some(try!(ShnPredicate::parse_with_metadata(
+            c, &file.schema, table_metadata(&args[0], options))
+            .map_err(|e| format!("invalid condition: {}", e)))),
+        None        => None,
+    };
+    let expr = try!(ShnExpr::parse(&args[2], &file.schema)
+                    .map_err(|e| format!("invalid expression: {}", e)));
+    let summary = try!(file.update_where(condition.as_ref(), &args[1], &expr)
+                       .map_err(describe));
+
+    for change in &summary.changes {
+        println!("row {}: {} -> {}", change.row, change.old, change.new);
+    }
+    println!("{} of {} matching rows changed",
+             summary.changes.len(), summary.matched);
+    if options.dry_run || summary.changes.is_empty() {
+        return Ok(());
+    }
+    let path = options.output.as_ref().unwrap_or(&args[0]);
+    write_file(path, &file, options)
+}
+
+fn read_header(path: &str, options: &Options) -> Result<ShnHeader, String> {
+    let file = try!(File::open(path)
+                    .map_err(|e| format!("can't open {}: {}", path, e)));
+    shn::read_header(&mut BufReader::new(file), &options.encoding)
+        .map_err(|e| format!("can't read {}: {}", path, e))
+}
+
+fn identify(options: &Options, args: &[String]) -> Result<(), String> {
+    try!(expect_args(args, &["file"]));
+    let header = try!(read_header(&args[0], options));
+    let fingerprint = header.schema.fingerprint();
+    println!("fingerprint: {:016x}", fingerprint);
+    let entries = options.registry.identify(&header.schema);
+    if options.registry.entries.is_empty() {
+        return Err("unknown schema, no schemas are known without \
+                    --registry".to_owned());
+    }
+    if entries.is_empty() {
+        return Err("unknown schema".to_owned());
+    }
+    for e in entries {
+        if e.version.is_empty() {
+            println!("{}", e.table);
+        } else {
+            println!("{} ({})", e.table, e.version);
+        }
+    }
+    Ok(())
+}
+
+fn codegen(options: &Options, args: &[String]) -> Result<(), String> {
+    try!(expect_args(args, &["file"]));
+    let header = try!(read_header(&args[0], options));
+    print!("{}", header.schema.to_record_source(&shn::table_name(&args[0])));
+    Ok(())
+}
+
+fn schema(options: &Options, args: &[String]) -> Result<(), String> {
+    try!(expect_args(args, &["file"]));
+    let header = try!(read_header(&args[0], options));
+    let definition = try!(header.schema.to_definition().map_err(|e| e.to_string()));
+    print!("{}", definition);
+    Ok(())
+}
+
+fn create(options: &Options, args: &[String]) -> Result<(), String> {
+    if args.len() != 2 && args.len() != 3 {
+        return Err("expected arguments <schema> <file> [csv]".to_owned());
+    }
+    let definition = try!(fs::read_to_string(&args[0])
+                          .map_err(|e| format!("can't read {}: {}", args[0], e)));
+    let schema = try!(ShnSchema::from_definition(&definition)
+                      .map_err(|e| format!("invalid schema: {}", e)));
+    let mut file = ShnFile::new([0; SHN_CRYPT_HEADER_LEN], 0, Arc::new(schema));
+    if let Some(csv) = args.get(2) {
+        let text = try!(fs::read_to_string(csv)
+                        .map_err(|e| format!("can't read {}: {}", csv, e)));
+        let count = try!(file.append_csv(&text, table_metadata(&args[1], options))
+                         .map_err(describe));
+        println!("{} rows read", count);
+    }
+    write_file(&args[1], &file, options)
+}
+
+/// Returns whether `path` is a ShineTable text file, judging by its
+/// extension.
+fn is_shine_table(path: &str) -> bool {
+    Path::new(path).extension()
+        .map_or(false, |e| e.to_string_lossy().eq_ignore_ascii_case("txt"))
+}
+
+fn convert(options: &Options, args: &[String]) -> Result<(), String> {
+    try!(expect_args(args, &["input", "output"]));
+    let (input, output) = (&args[0], &args[1]);
+    let tables = if is_shine_table(input) {
+        let file = try!(File::open(input)
+                        .map_err(|e| format!("can't open {}: {}", input, e)));
+        let tables = try!(shn::read_shine_from(&mut BufReader::new(file),
+                                               &options.encoding)
+                          .map_err(|e| format!("can't read {}: {}", input, e)));
+        match options.table {
+            Some(ref name) => match tables.into_iter().find(|t| t.name == *name) {
+                Some(t) => vec![t],
+                None    => return Err(format!("no table `{}` in {}", name, input)),
+            },
+            None => tables,
+        }
+    } else {
+        vec![ShnShineTable {
+            name:   shn::table_name(input),
+            file:   try!(read_file(input, options)),
+        }]
+    };
+
+    if is_shine_table(output) {
+        return shn::write_shine_file(output, &tables, &options.encoding)
+            .map_err(|e| format!("can't write {}: {}", output, e));
+    }
+    match tables.len() {
+        1 => write_file(output, &tables[0].file, options),
+        0 => Err(format!("no tables in {}", input)),
+        _ => {
+            let names: Vec<&str> = tables.iter().map(|t| &t.name[..]).collect();
+            Err(format!("{} holds several tables, choose one of {} with --table",
+                        input, names.join(", ")))
+        },
+    }
+}
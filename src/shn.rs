@@ -1,9 +1,23 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fmt;
+use std::hash::{ Hash, Hasher };
+use std::io;
+use std::mem;
 use std::sync::Arc;
 use std::num::Wrapping;
 
+use encoding::{ EncoderTrap, EncodingRef };
+
+use super::shn_record::ShnColumnDifference;
+
 /// Length of the crypto header of each file
 pub const SHN_CRYPT_HEADER_LEN: usize = 0x20;
 
+/// Name of the pseudo-column preceding the columns of each file
+pub const SHN_ID_COLUMN_NAME: &'static str = "__ID__";
+
 pub type Result<T> = ::std::result::Result<T, ShnError>;
 pub type Endianess = ::byteorder::LittleEndian;
 
@@ -29,8 +43,91 @@ pub fn decrypt(data: &mut [u8]) {
     }
 }
 
+/// Positional form of the cipher implemented by `decrypt`.
+///
+/// The key of each byte only depends on its position and the total length of
+/// the blob, so the data can be de- or encrypted front to back in chunks of
+/// any size, instead of requiring the whole blob at once.
+#[derive(Clone, Debug)]
+pub struct ShnCipher {
+    key:    u8,
+    pos:    usize,
+}
+
+impl ShnCipher {
+    /// Constructs a cipher positioned at the start of a blob of `len` bytes.
+    pub fn new(len: usize) -> Self {
+        // The key of the last byte is the length of the blob, every step
+        // towards the front is xor'ed with a value depending on the position
+        // only. That value repeats every 256 bytes, so any 512 steps cancel
+        // each other out.
+        let mut key = len as u8;
+        let skip = len.saturating_sub(1) / 512 * 512;
+        for i in (skip + 1)..len {
+            key ^= ShnCipher::step(i);
+        }
+        ShnCipher {
+            key:    key,
+            pos:    0,
+        }
+    }
+
+    /// Constructs a cipher with the given key at position 0, regardless of
+    /// the length of the blob. Used to apply the cipher before the length is
+    /// known, since every key only differs by a constant from this one.
+    pub fn with_key(key: u8) -> Self {
+        ShnCipher {
+            key:    key,
+            pos:    0,
+        }
+    }
+
+    /// Returns the key of the byte at the current position.
+    pub fn key(&self) -> u8 {
+        self.key
+    }
+
+    /// Returns the current position within the blob.
+    pub fn position(&self) -> usize {
+        self.pos
+    }
+
+    /// Moves the cipher `count` bytes further without touching any data.
+    pub fn skip(&mut self, count: usize) {
+        // See `new` on why we can skip multiples of 512 right away.
+        let full = count / 512 * 512;
+        self.pos += full;
+        for _ in full..count {
+            self.advance_one();
+        }
+    }
+
+    /// De- or encrypts the next `data.len()` bytes of the blob in place.
+    pub fn apply(&mut self, data: &mut [u8]) {
+        for b in data.iter_mut() {
+            *b ^= self.key;
+            self.advance_one();
+        }
+    }
+
+    fn advance_one(&mut self) {
+        self.pos += 1;
+        self.key ^= ShnCipher::step(self.pos);
+    }
+
+    fn step(i: usize) -> u8 {
+        let i = Wrapping(i as u8);
+        let Wrapping(x) = ((i & Wrapping(15)) + Wrapping(0x55))
+            ^ (i * Wrapping(11))
+            ^ Wrapping(170);
+        x
+    }
+}
+
 /// Represents a data type within a `SHN` File.
-#[derive(Clone, PartialEq, Debug)]
+///
+/// Data types are ordered by their declaration order.
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
 pub enum ShnDataType {
     /// A string with a fixed length
     StringFixedLen,
@@ -53,7 +150,12 @@ pub enum ShnDataType {
 }
 
 /// Represents a single data cell within the `SHN`-File
-#[derive(Clone, PartialEq, Debug)]
+///
+/// Cells are compared by their type first, in the order of `ShnDataType`,
+/// and by their value second. `SingleFloatingPoint` values are compared by
+/// their bit pattern, so NaNs equal themselves and `0.0` does not equal
+/// `-0.0`; use `approx_eq` to compare floating point values numerically.
+#[derive(Clone, Debug)]
 pub enum ShnCell {
     /// A cell containing a `StringFixedLen` type value
     StringFixedLen(String),
@@ -76,9 +178,18 @@ pub enum ShnCell {
 }
 
 impl ShnDataType {
-    /// Returns the `ShnDataType` matching `id`
+    /// Returns the `ShnDataType` matching `id`. Panics for unknown ids, see
+    /// `try_from_id`.
     pub fn from_id(id: u32) -> ShnDataType {
-	      match id {
+        match ShnDataType::try_from_id(id) {
+            Some(t) => t,
+            None    => unimplemented!(),
+        }
+    }
+
+    /// Returns the `ShnDataType` matching `id`, or `None` for unknown ids.
+    pub fn try_from_id(id: u32) -> Option<ShnDataType> {
+	      Some(match id {
 	          1 | 12 | 16
                 => ShnDataType::Byte,
 	          2
@@ -98,9 +209,22 @@ impl ShnDataType {
 	          26
                 => ShnDataType::StringZeroTerminated,
 
-	          _			=> unimplemented!(),
-	      }
+	          _			=> return None,
+	      })
+    }
+
+    /// Returns the `ShnDataType` named `name`, like `UnsignedInteger`,
+    /// ignoring case. Names are the ones printed by `Debug`.
+    pub fn from_name(name: &str) -> Option<ShnDataType> {
+        [ShnDataType::StringFixedLen, ShnDataType::StringZeroTerminated,
+         ShnDataType::Byte, ShnDataType::SignedByte, ShnDataType::SignedShort,
+         ShnDataType::UnsignedShort, ShnDataType::SignedInteger,
+         ShnDataType::UnsignedInteger, ShnDataType::SingleFloatingPoint]
+            .iter()
+            .find(|t| format!("{:?}", t).eq_ignore_ascii_case(name))
+            .cloned()
     }
+
     /// Returns the lowest `id` matching the data type.
     pub fn to_id(&self) -> u32 {
         match *self {
@@ -131,6 +255,24 @@ impl ShnDataType {
             _                                   => 0,
         }
     }
+
+    /// Returns a cell of this type holding zero or an empty string.
+    pub fn default_cell(&self) -> ShnCell {
+        match *self {
+            ShnDataType::StringFixedLen
+                => ShnCell::StringFixedLen(String::new()),
+            ShnDataType::StringZeroTerminated
+                => ShnCell::StringZeroTerminated(String::new()),
+            ShnDataType::Byte                   => ShnCell::Byte(0),
+            ShnDataType::SignedByte             => ShnCell::SignedByte(0),
+            ShnDataType::SignedShort            => ShnCell::SignedShort(0),
+            ShnDataType::UnsignedShort          => ShnCell::UnsignedShort(0),
+            ShnDataType::SignedInteger          => ShnCell::SignedInteger(0),
+            ShnDataType::UnsignedInteger        => ShnCell::UnsignedInteger(0),
+            ShnDataType::SingleFloatingPoint
+                => ShnCell::SingleFloatingPoint(0.0),
+        }
+    }
 }
 
 impl ShnCell {
@@ -157,6 +299,327 @@ impl ShnCell {
                 => ShnDataType::SingleFloatingPoint,
 	      }
     }
+
+    /// Converts the value to a cell of type `data_type`. Returns `None` if
+    /// the value is out of range for the new type or would lose
+    /// information, like the fractional part of a floating point value.
+    pub fn convert_to(&self, data_type: &ShnDataType) -> Option<ShnCell> {
+        match *self {
+            ShnCell::StringFixedLen(ref s) |
+            ShnCell::StringZeroTerminated(ref s)
+                => ShnCell::parse(s, data_type).ok(),
+            ShnCell::SingleFloatingPoint(v) => match *data_type {
+                ShnDataType::StringFixedLen
+                    => Some(ShnCell::StringFixedLen(v.to_string())),
+                ShnDataType::StringZeroTerminated
+                    => Some(ShnCell::StringZeroTerminated(v.to_string())),
+                ShnDataType::SingleFloatingPoint
+                    => Some(ShnCell::SingleFloatingPoint(v)),
+                ref t => {
+                    if v.fract() != 0.0 || v.abs() > i64::max_value() as f32 {
+                        None
+                    } else {
+                        ShnCell::integer(v as i64, t)
+                    }
+                },
+            },
+            ref cell => {
+                let v = match *cell {
+                    ShnCell::Byte(v)            => v as i64,
+                    ShnCell::SignedByte(v)      => v as i64,
+                    ShnCell::SignedShort(v)     => v as i64,
+                    ShnCell::UnsignedShort(v)   => v as i64,
+                    ShnCell::SignedInteger(v)   => v as i64,
+                    ShnCell::UnsignedInteger(v) => v as i64,
+                    _                           => unreachable!(),
+                };
+                match *data_type {
+                    ShnDataType::StringFixedLen
+                        => Some(ShnCell::StringFixedLen(v.to_string())),
+                    ShnDataType::StringZeroTerminated
+                        => Some(ShnCell::StringZeroTerminated(v.to_string())),
+                    ShnDataType::SingleFloatingPoint => {
+                        // Only integers up to 2^24 are exactly representable
+                        if (v as f32) as i64 == v {
+                            Some(ShnCell::SingleFloatingPoint(v as f32))
+                        } else {
+                            None
+                        }
+                    },
+                    ref t => ShnCell::integer(v, t),
+                }
+            },
+        }
+    }
+
+    /// Compares two cells like `==`, except that `SingleFloatingPoint`
+    /// values are considered equal if they differ by at most `epsilon`.
+    /// Two NaNs are considered equal as well.
+    pub fn approx_eq(&self, other: &ShnCell, epsilon: f32) -> bool {
+        match (self, other) {
+            (&ShnCell::SingleFloatingPoint(a),
+             &ShnCell::SingleFloatingPoint(b))
+                => (a.is_nan() && b.is_nan()) || (a - b).abs() <= epsilon
+                    || a == b,
+            (a, b) => a == b,
+        }
+    }
+
+    /// Returns the value of integer cells.
+    pub fn as_i64(&self) -> Option<i64> {
+        match *self {
+            ShnCell::Byte(v)            => Some(v as i64),
+            ShnCell::SignedByte(v)      => Some(v as i64),
+            ShnCell::SignedShort(v)     => Some(v as i64),
+            ShnCell::UnsignedShort(v)   => Some(v as i64),
+            ShnCell::SignedInteger(v)   => Some(v as i64),
+            ShnCell::UnsignedInteger(v) => Some(v as i64),
+            _                           => None,
+        }
+    }
+
+    /// Returns the value of integer and floating point cells.
+    pub fn as_f64(&self) -> Option<f64> {
+        match *self {
+            ShnCell::SingleFloatingPoint(v) => Some(v as f64),
+            ref cell                        => cell.as_i64().map(|v| v as f64),
+        }
+    }
+
+    /// Returns the value of string cells.
+    pub fn as_str(&self) -> Option<&str> {
+        match *self {
+            ShnCell::StringFixedLen(ref s) |
+            ShnCell::StringZeroTerminated(ref s)    => Some(&s[..]),
+            _                                       => None,
+        }
+    }
+
+    /// Parses `text` into a cell of type `data_type`. Strings are taken
+    /// verbatim, numbers may be surrounded by whitespace and need to be in
+    /// range for the type.
+    pub fn parse(text: &str, data_type: &ShnDataType) -> Result<ShnCell> {
+        let cell = match *data_type {
+            ShnDataType::StringFixedLen
+                => Some(ShnCell::StringFixedLen(text.to_owned())),
+            ShnDataType::StringZeroTerminated
+                => Some(ShnCell::StringZeroTerminated(text.to_owned())),
+            ShnDataType::SingleFloatingPoint
+                => text.trim().parse().ok().map(ShnCell::SingleFloatingPoint),
+            ref t
+                => text.trim().parse().ok().and_then(|v| ShnCell::integer(v, t)),
+        };
+        cell.ok_or_else(|| ShnError::InvalidText(text.to_owned(),
+                                                 data_type.clone()))
+    }
+
+    /// Constructs an integer cell of type `data_type` holding `v`, if it is
+    /// in range.
+    fn integer(v: i64, data_type: &ShnDataType) -> Option<ShnCell> {
+        macro_rules! checked {
+            ($t:ty, $variant:ident) => {
+                if v >= <$t>::min_value() as i64 && v <= <$t>::max_value() as i64 {
+                    Some(ShnCell::$variant(v as $t))
+                } else {
+                    None
+                }
+            }
+        }
+        match *data_type {
+            ShnDataType::Byte               => checked!(u8, Byte),
+            ShnDataType::SignedByte         => checked!(i8, SignedByte),
+            ShnDataType::SignedShort        => checked!(i16, SignedShort),
+            ShnDataType::UnsignedShort      => checked!(u16, UnsignedShort),
+            ShnDataType::SignedInteger      => checked!(i32, SignedInteger),
+            ShnDataType::UnsignedInteger    => checked!(u32, UnsignedInteger),
+            _                               => None,
+        }
+    }
+}
+
+impl PartialEq for ShnCell {
+    fn eq(&self, other: &ShnCell) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for ShnCell {}
+
+impl PartialOrd for ShnCell {
+    fn partial_cmp(&self, other: &ShnCell) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for ShnCell {
+    fn cmp(&self, other: &ShnCell) -> Ordering {
+        match (self, other) {
+            (&ShnCell::StringFixedLen(ref a), &ShnCell::StringFixedLen(ref b)) |
+            (&ShnCell::StringZeroTerminated(ref a),
+             &ShnCell::StringZeroTerminated(ref b))     => a.cmp(b),
+            (&ShnCell::Byte(a), &ShnCell::Byte(b))      => a.cmp(&b),
+            (&ShnCell::SignedByte(a), &ShnCell::SignedByte(b))
+                => a.cmp(&b),
+            (&ShnCell::SignedShort(a), &ShnCell::SignedShort(b))
+                => a.cmp(&b),
+            (&ShnCell::UnsignedShort(a), &ShnCell::UnsignedShort(b))
+                => a.cmp(&b),
+            (&ShnCell::SignedInteger(a), &ShnCell::SignedInteger(b))
+                => a.cmp(&b),
+            (&ShnCell::UnsignedInteger(a), &ShnCell::UnsignedInteger(b))
+                => a.cmp(&b),
+            // Only equal for the same bit pattern, consistent with `Hash`.
+            (&ShnCell::SingleFloatingPoint(a),
+             &ShnCell::SingleFloatingPoint(b))          => a.total_cmp(&b),
+            (a, b) => a.data_type().cmp(&b.data_type()),
+        }
+    }
+}
+
+impl Hash for ShnCell {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.data_type().hash(state);
+        match *self {
+            ShnCell::StringFixedLen(ref v) |
+            ShnCell::StringZeroTerminated(ref v)    => v.hash(state),
+            ShnCell::Byte(v)                        => v.hash(state),
+            ShnCell::SignedByte(v)                  => v.hash(state),
+            ShnCell::SignedShort(v)                 => v.hash(state),
+            ShnCell::UnsignedShort(v)               => v.hash(state),
+            ShnCell::SignedInteger(v)               => v.hash(state),
+            ShnCell::UnsignedInteger(v)             => v.hash(state),
+            ShnCell::SingleFloatingPoint(v)         => v.to_bits().hash(state),
+        }
+    }
+}
+
+impl fmt::Display for ShnCell {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ShnCell::StringFixedLen(ref v) |
+            ShnCell::StringZeroTerminated(ref v)    => v.fmt(f),
+            ShnCell::Byte(v)                        => v.fmt(f),
+            ShnCell::SignedByte(v)                  => v.fmt(f),
+            ShnCell::SignedShort(v)                 => v.fmt(f),
+            ShnCell::UnsignedShort(v)               => v.fmt(f),
+            ShnCell::SignedInteger(v)               => v.fmt(f),
+            ShnCell::UnsignedInteger(v)             => v.fmt(f),
+            ShnCell::SingleFloatingPoint(v)         => v.fmt(f),
+        }
+    }
+}
+
+macro_rules! impl_cell_from {
+    ($($t:ty => $variant:ident),+) => {
+        $(impl From<$t> for ShnCell {
+            fn from(v: $t) -> ShnCell {
+                ShnCell::$variant(v)
+            }
+        })+
+    }
+}
+
+impl_cell_from!(u8 => Byte,
+                i8 => SignedByte,
+                i16 => SignedShort,
+                u16 => UnsignedShort,
+                i32 => SignedInteger,
+                u32 => UnsignedInteger,
+                f32 => SingleFloatingPoint,
+                String => StringFixedLen);
+
+/// Strings are converted to `StringFixedLen` cells, being the more common
+/// string type. Use `ShnCell::StringZeroTerminated` directly for the other.
+impl<'a> From<&'a str> for ShnCell {
+    fn from(v: &'a str) -> ShnCell {
+        ShnCell::StringFixedLen(v.to_owned())
+    }
+}
+
+macro_rules! impl_integer_try_from_cell {
+    ($($t:ty),+) => {
+        $(impl<'a> TryFrom<&'a ShnCell> for $t {
+            type Error = ShnError;
+
+            fn try_from(cell: &'a ShnCell) -> Result<$t> {
+                match cell.as_i64() {
+                    Some(v) if v >= <$t>::min_value() as i64
+                        && (v < 0 || v as u64 <= <$t>::max_value() as u64)
+                        => Ok(v as $t),
+                    _   => Err(ShnError::InvalidValue(cell.clone())),
+                }
+            }
+        }
+
+        impl TryFrom<ShnCell> for $t {
+            type Error = ShnError;
+
+            fn try_from(cell: ShnCell) -> Result<$t> {
+                <$t>::try_from(&cell)
+            }
+        })+
+    }
+}
+
+impl_integer_try_from_cell!(u8, i8, u16, i16, u32, i32, u64, i64);
+
+/// Integer cells are accepted as long as they are exactly representable.
+impl<'a> TryFrom<&'a ShnCell> for f32 {
+    type Error = ShnError;
+
+    fn try_from(cell: &'a ShnCell) -> Result<f32> {
+        match (cell, cell.as_i64()) {
+            (&ShnCell::SingleFloatingPoint(v), _)       => Ok(v),
+            (_, Some(v)) if (v as f32) as i64 == v      => Ok(v as f32),
+            _ => Err(ShnError::InvalidValue(cell.clone())),
+        }
+    }
+}
+
+impl TryFrom<ShnCell> for f32 {
+    type Error = ShnError;
+
+    fn try_from(cell: ShnCell) -> Result<f32> {
+        f32::try_from(&cell)
+    }
+}
+
+impl<'a> TryFrom<&'a ShnCell> for f64 {
+    type Error = ShnError;
+
+    fn try_from(cell: &'a ShnCell) -> Result<f64> {
+        cell.as_f64().ok_or_else(|| ShnError::InvalidValue(cell.clone()))
+    }
+}
+
+impl TryFrom<ShnCell> for f64 {
+    type Error = ShnError;
+
+    fn try_from(cell: ShnCell) -> Result<f64> {
+        f64::try_from(&cell)
+    }
+}
+
+impl<'a> TryFrom<&'a ShnCell> for String {
+    type Error = ShnError;
+
+    fn try_from(cell: &'a ShnCell) -> Result<String> {
+        cell.as_str()
+            .map(|s| s.to_owned())
+            .ok_or_else(|| ShnError::InvalidValue(cell.clone()))
+    }
+}
+
+impl TryFrom<ShnCell> for String {
+    type Error = ShnError;
+
+    fn try_from(cell: ShnCell) -> Result<String> {
+        match cell {
+            ShnCell::StringFixedLen(s) |
+            ShnCell::StringZeroTerminated(s)    => Ok(s),
+            cell                                => Err(ShnError::InvalidValue(cell)),
+        }
+    }
 }
 
 #[derive(Clone, PartialEq, Debug)]
@@ -172,6 +635,18 @@ pub struct ShnColumn {
 }
 
 impl ShnColumn {
+    /// Constructs the `__ID__` pseudo-column. Every row in a file starts
+    /// with it, but it is not part of the columns declared by the file.
+    pub fn new_id() -> Self {
+        ShnColumn::new_unsigned_short(SHN_ID_COLUMN_NAME)
+    }
+
+    /// Returns whether this is the `__ID__` pseudo-column.
+    pub fn is_id(&self) -> bool {
+        self.name == SHN_ID_COLUMN_NAME
+            && self.data_type == ShnDataType::UnsignedShort
+    }
+
     /// Constructs a new column with the `StringFixedLen` type
     pub fn new_string_fixed_len(name: &str, len: i32) -> Self {
 	      ShnColumn {
@@ -252,6 +727,50 @@ impl ShnColumn {
 	          data_length:	4,
 	      }
     }
+
+    /// Returns the number of bytes each cell of this column takes up, or
+    /// `None` if it differs between rows (`StringZeroTerminated`).
+    pub fn fixed_length(&self) -> Option<usize> {
+        match self.data_type {
+            ShnDataType::StringZeroTerminated   => None,
+            ShnDataType::StringFixedLen         => Some(self.data_length as usize),
+            ref t                               => Some(t.default_length()),
+        }
+    }
+
+    /// Checks that `cell` fits into this column, failing with
+    /// `ShnError::StringTooLong` for strings of `StringFixedLen` columns
+    /// holding more than `data_length` characters. As the encoding is only
+    /// known when writing, strings may still turn out too long once encoded,
+    /// which `ShnWriter` reports.
+    pub fn check_length(&self, cell: &ShnCell) -> Result<()> {
+        match *cell {
+            ShnCell::StringFixedLen(ref st)
+                if st.chars().count() > ::std::cmp::max(self.data_length, 0) as usize
+                => Err(ShnError::StringTooLong(st.clone(), self.data_length)),
+            _   => Ok(()),
+        }
+    }
+
+    /// Returns the number of bytes `cell` takes up when written to a file.
+    pub fn cell_length(&self, cell: &ShnCell, enc: &EncodingRef)
+                       -> Result<usize> {
+        match *cell {
+            ShnCell::StringZeroTerminated(ref st) => {
+                let mut buf = Vec::new();
+                try!(enc.encode_to(st, EncoderTrap::Strict, &mut buf)
+                     .map_err(|_| ShnError::InvalidEncoding));
+                // The terminating `00`-byte, which `ShnWriter` does not add
+                // to strings already ending in one
+                if buf.last() == Some(&0) {
+                    Ok(buf.len())
+                } else {
+                    Ok(buf.len() + 1)
+                }
+            },
+            _ => self.fixed_length().ok_or(ShnError::InvalidSchema),
+        }
+    }
 }
 
 /// Represents the `schema` of an shn file, which is defined by a
@@ -270,15 +789,81 @@ impl ShnSchema {
 	      }
     }
 
-    /// Calculates the default length in bytes of each row.
+    /// Constructs a new `ShnSchema` containing only the `__ID__`
+    /// pseudo-column, which every schema written to a file needs to start
+    /// with.
+    pub fn with_id_column() -> Self {
+        ShnSchema {
+            columns:    vec![ShnColumn::new_id()],
+        }
+    }
+
+    /// Returns the `__ID__` pseudo-column, if the schema starts with it.
+    pub fn id_column(&self) -> Option<&ShnColumn> {
+        self.columns.first().and_then(|c| if c.is_id() { Some(c) } else { None })
+    }
+
+    /// Returns the columns declared in the file, that is all columns except
+    /// for the `__ID__` pseudo-column.
+    pub fn data_columns(&self) -> &[ShnColumn] {
+        match self.id_column() {
+            Some(_) => &self.columns[1..],
+            None    => &self.columns[..],
+        }
+    }
+
+    /// Checks whether the schema can be written to a file, which requires
+    /// it to start with the `__ID__` pseudo-column.
+    pub fn validate(&self) -> Result<()> {
+        match self.id_column() {
+            Some(_) => Ok(()),
+            None    => Err(ShnError::MissingIdColumn),
+        }
+    }
+
+    /// Calculates the default length in bytes of each row, as declared in
+    /// the header of a file.
+    ///
+    /// This is the sum of the declared `data_length`s, which is what the
+    /// client expects, but does not need to match the actual size of rows
+    /// containing `StringZeroTerminated` columns. See `fixed_record_length`
+    /// and `ShnRow::byte_len` for those.
     pub fn calculate_record_length(&self) -> i32 {
         self.columns.iter()
             .map(|c| c.data_length)
             .fold(0, |a, b| a + b)
     }
+
+    /// Returns the default length in bytes of each row as written to the
+    /// header of a file, see `calculate_record_length`. Fails with
+    /// `ShnError::InvalidSchema` if a column has a negative length or the
+    /// sum does not fit into the header.
+    pub fn declared_record_length(&self) -> Result<u32> {
+        let mut len: u32 = 0;
+        for c in &self.columns {
+            if c.data_length < 0 {
+                return Err(ShnError::InvalidSchema);
+            }
+            len = try!(len.checked_add(c.data_length as u32)
+                       .ok_or(ShnError::InvalidSchema));
+        }
+        Ok(len)
+    }
+
+    /// Returns the actual length in bytes of every row if all columns have
+    /// a fixed length, `None` otherwise.
+    pub fn fixed_record_length(&self) -> Option<usize> {
+        self.columns.iter()
+            .fold(Some(0), |a, c| a.and_then(|a| c.fixed_length()
+                                             .map(|l| a + l)))
+    }
 }
 
 /// Represents a single row of data within a file
+///
+/// Rows compare equal if both their schemas and their cells do. The `Debug`
+/// output leaves out the schema, as it is shared by all rows of a file.
+#[derive(Clone, PartialEq)]
 pub struct ShnRow {
     /// Reference to the schema defining the file
     pub schema:       Arc<ShnSchema>,
@@ -286,7 +871,154 @@ pub struct ShnRow {
     pub data:	        Vec<ShnCell>
 }
 
+impl fmt::Debug for ShnRow {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ShnRow")
+            .field("data", &self.data)
+            .finish()
+    }
+}
+
+impl ShnRow {
+    /// Returns the value of the `__ID__` pseudo-column, if the schema has
+    /// one.
+    pub fn id(&self) -> Option<u16> {
+        match (self.schema.id_column(), self.data.first()) {
+            (Some(_), Some(&ShnCell::UnsignedShort(id)))  => Some(id),
+            _                                           => None,
+        }
+    }
+
+    /// Sets the value of the `__ID__` pseudo-column.
+    pub fn set_id(&mut self, id: u16) -> Result<()> {
+        if self.schema.id_column().is_none() {
+            return Err(ShnError::MissingIdColumn);
+        }
+        match self.data.first_mut() {
+            Some(cell) => {
+                *cell = ShnCell::UnsignedShort(id);
+                Ok(())
+            },
+            None => Err(ShnError::InvalidSchema),
+        }
+    }
+
+    /// Returns the number of bytes this row takes up when written to a file.
+    pub fn byte_len(&self, enc: &EncodingRef) -> Result<usize> {
+        if self.data.len() != self.schema.columns.len() {
+            return Err(ShnError::InvalidSchema);
+        }
+        let mut len = 0;
+        for (cell, column) in self.data.iter().zip(self.schema.columns.iter()) {
+            if cell.data_type() != column.data_type {
+                return Err(ShnError::InvalidSchema);
+            }
+            len += try!(column.cell_length(cell, enc));
+        }
+        Ok(len)
+    }
+}
+
+/// Represents everything within a `SHN` file preceding the rows
+#[derive(Clone, PartialEq, Debug)]
+pub struct ShnHeader {
+    /// The cryptographic header
+    pub crypt_header:           [u8; SHN_CRYPT_HEADER_LEN],
+    /// The header (unknown purpose)
+    pub header:                 u32,
+    /// The number of rows in the file
+    pub record_count:           u32,
+    /// The default length of each row, as declared by the file
+    pub default_record_length:  u32,
+    /// The schema defining the file
+    pub schema:                 Arc<ShnSchema>,
+}
+
+/// A value rows can be looked up by, see `ShnFile::index_by`.
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub enum ShnKey {
+    /// The value of any integer cell
+    Integer(i64),
+    /// The value of any string cell
+    String(String),
+}
+
+impl ShnKey {
+    /// Returns the key matching the value of `cell`, or `None` for floating
+    /// point cells.
+    pub fn from_cell(cell: &ShnCell) -> Option<ShnKey> {
+        match *cell {
+            ShnCell::StringFixedLen(ref s) |
+            ShnCell::StringZeroTerminated(ref s)
+                => Some(ShnKey::String(s.clone())),
+            ShnCell::Byte(v)            => Some(ShnKey::Integer(v as i64)),
+            ShnCell::SignedByte(v)      => Some(ShnKey::Integer(v as i64)),
+            ShnCell::SignedShort(v)     => Some(ShnKey::Integer(v as i64)),
+            ShnCell::UnsignedShort(v)   => Some(ShnKey::Integer(v as i64)),
+            ShnCell::SignedInteger(v)   => Some(ShnKey::Integer(v as i64)),
+            ShnCell::UnsignedInteger(v) => Some(ShnKey::Integer(v as i64)),
+            ShnCell::SingleFloatingPoint(_) => None,
+        }
+    }
+}
+
+macro_rules! impl_key_from_integer {
+    ($($t:ty),+) => {
+        $(impl From<$t> for ShnKey {
+            fn from(v: $t) -> ShnKey {
+                ShnKey::Integer(v as i64)
+            }
+        })+
+    }
+}
+
+impl_key_from_integer!(u8, i8, u16, i16, u32, i32, i64);
+
+impl<'a> From<&'a str> for ShnKey {
+    fn from(v: &'a str) -> ShnKey {
+        ShnKey::String(v.to_owned())
+    }
+}
+
+impl From<String> for ShnKey {
+    fn from(v: String) -> ShnKey {
+        ShnKey::String(v)
+    }
+}
+
+/// Maps the keys of a single column to the position of their rows.
+#[derive(Clone, Debug)]
+struct ShnIndex {
+    column:     usize,
+    rows:       HashMap<ShnKey, usize>,
+    /// Whether the index could not be rebuilt after a change, in which case
+    /// `rows` is empty
+    outdated:   bool,
+}
+
+/// Options for comparing the contents of two files, see
+/// `ShnFile::content_eq_with`.
+#[derive(Clone, Debug, Default)]
+pub struct ContentEqOptions {
+    /// Whether to ignore `crypt_header` and `header`
+    pub ignore_headers:     bool,
+    /// If set, floating point cells are compared with `ShnCell::approx_eq`
+    /// using this epsilon instead of by their bit pattern
+    pub float_epsilon:      Option<f32>,
+}
+
+impl ContentEqOptions {
+    /// Constructs new `ContentEqOptions`, which compare everything exactly.
+    pub fn new() -> Self {
+        ContentEqOptions::default()
+    }
+}
+
 /// Represents a `SHN` file
+///
+/// Files compare equal if their headers, schemas and rows do. Indexes and
+/// other settings are neither compared nor part of the `Debug` output.
+#[derive(Clone)]
 pub struct ShnFile {
     /// The cryptographic header
     pub crypt_header:	[u8; SHN_CRYPT_HEADER_LEN],
@@ -294,19 +1026,588 @@ pub struct ShnFile {
     pub header:		u32, // or was it u16?
     /// The schema defining the file
     pub schema:	  Arc<ShnSchema>,
-    /// The data held in the file
-    pub data:     Vec<ShnRow>
+    /// The data held in the file. When modifying it directly instead of
+    /// through the methods of `ShnFile`, call `reindex` afterwards.
+    pub data:     Vec<ShnRow>,
+    /// Indexes built by `index_by`, keyed by column name
+    indexes:      HashMap<String, ShnIndex>,
+    /// Whether to renumber the `__ID__` pseudo-column after structural
+    /// changes, see `set_auto_renumber`
+    auto_renumber: bool,
+}
+
+impl fmt::Debug for ShnFile {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ShnFile")
+            .field("crypt_header", &&self.crypt_header[..])
+            .field("header", &self.header)
+            .field("schema", &self.schema)
+            .field("data", &self.data)
+            .finish()
+    }
+}
+
+impl PartialEq for ShnFile {
+    fn eq(&self, other: &ShnFile) -> bool {
+        self.content_eq(other)
+    }
 }
 
 impl ShnFile {
+    /// Compares the headers, the schema and the cells of all rows of both
+    /// files structurally, regardless of whether the schemas are shared.
+    pub fn content_eq(&self, other: &ShnFile) -> bool {
+        self.content_eq_with(other, &ContentEqOptions::new())
+    }
+
+    /// Compares both files like `content_eq`, as configured by `options`.
+    pub fn content_eq_with(&self, other: &ShnFile, options: &ContentEqOptions)
+                           -> bool {
+        if !options.ignore_headers
+            && (self.crypt_header != other.crypt_header
+                || self.header != other.header) {
+            return false;
+        }
+        if self.schema != other.schema || self.data.len() != other.data.len() {
+            return false;
+        }
+        self.data.iter().zip(other.data.iter()).all(|(a, b)| {
+            a.data.len() == b.data.len()
+                && a.data.iter().zip(b.data.iter()).all(|(a, b)| {
+                    match options.float_epsilon {
+                        Some(epsilon)   => a.approx_eq(b, epsilon),
+                        None            => a == b,
+                    }
+                })
+        })
+    }
+
+    /// Constructs a new `ShnFile` without any rows.
+    pub fn new(crypt_header: [u8; SHN_CRYPT_HEADER_LEN],
+               header: u32,
+               schema: Arc<ShnSchema>)
+               -> Self {
+        ShnFile {
+            crypt_header:   crypt_header,
+            header:         header,
+            schema:         schema,
+            data:           Vec::new(),
+            indexes:        HashMap::new(),
+            auto_renumber:  false,
+        }
+    }
+
+    /// Sets whether the `__ID__` pseudo-column of every row is set to its
+    /// position after inserting, removing or reordering rows.
+    pub fn set_auto_renumber(&mut self, auto_renumber: bool) {
+        self.auto_renumber = auto_renumber;
+    }
+
     /// Appends a row to the file (and checks it to be conform to the schema)
-    pub fn append_row(&mut self, row: ShnRow) -> Result<()> {
+    pub fn append_row(&mut self, mut row: ShnRow) -> Result<()> {
 	      if row.schema != self.schema {
-	          Err(ShnError::InvalidSchema)
-	      } else {
-	          self.data.push(row);
-	          Ok(())
+	          return Err(ShnError::InvalidSchema);
 	      }
+        if self.auto_renumber && self.schema.id_column().is_some() {
+            try!(self.check_renumber(self.data.len() + 1));
+            try!(row.set_id(self.data.len() as u16));
+        }
+        let keys = try!(self.index_keys(&row, None));
+        let position = self.data.len();
+        for (column, key) in keys {
+            self.indexes.get_mut(&column).unwrap().rows.insert(key, position);
+        }
+        self.data.push(row);
+        Ok(())
+    }
+
+    /// Inserts a row at position `at`, shifting all rows after it (and checks
+    /// it to be conform to the schema)
+    pub fn insert_row(&mut self, at: usize, row: ShnRow) -> Result<()> {
+        if at > self.data.len() || row.schema != self.schema {
+            return Err(ShnError::InvalidSchema);
+        }
+        try!(self.check_renumber(self.data.len() + 1));
+        try!(self.index_keys(&row, None));
+        self.data.insert(at, row);
+        self.refresh();
+        Ok(())
+    }
+
+    /// Removes the row at position `index` and returns it.
+    pub fn remove_row(&mut self, index: usize) -> Option<ShnRow> {
+        if index >= self.data.len() {
+            return None;
+        }
+        let row = self.data.remove(index);
+        self.refresh();
+        Some(row)
+    }
+
+    /// Removes all rows from position `len` on.
+    pub fn truncate(&mut self, len: usize) {
+        self.data.truncate(len);
+        self.refresh();
+    }
+
+    /// Removes all rows for which `f` returns `false`.
+    pub fn retain<F>(&mut self, f: F) where F: FnMut(&ShnRow) -> bool {
+        self.data.retain(f);
+        self.refresh();
+    }
+
+    /// Replaces the cell of row `row` in the column named `column` with
+    /// `value`, which needs to match the type of the column and fit into its
+    /// length, and returns the previous value.
+    pub fn set_cell(&mut self, row: usize, column: &str, value: ShnCell)
+                    -> Result<ShnCell> {
+        let position = match self.schema.columns.iter()
+            .position(|c| c.name == column) {
+                Some(p) => p,
+                None    => return Err(ShnError::UnknownColumn(column.to_owned())),
+            };
+        if row >= self.data.len()
+            || self.schema.columns[position].data_type != value.data_type() {
+            return Err(ShnError::InvalidSchema);
+        }
+        try!(self.schema.columns[position].check_length(&value));
+
+        let mut updates = Vec::new();
+        for (name, index) in &self.indexes {
+            if index.column != position {
+                continue;
+            }
+            let key = try!(ShnKey::from_cell(&value)
+                           .ok_or(ShnError::InvalidSchema));
+            match index.rows.get(&key) {
+                Some(p) if *p != row
+                    => return Err(ShnError::DuplicateKey(key)),
+                _   => {},
+            }
+            updates.push((name.clone(), key));
+        }
+
+        let old = mem::replace(&mut self.data[row].data[position], value);
+        for (name, key) in updates {
+            let index = self.indexes.get_mut(&name).unwrap();
+            if let Some(old_key) = ShnKey::from_cell(&old) {
+                index.rows.remove(&old_key);
+            }
+            index.rows.insert(key, row);
+        }
+        Ok(old)
+    }
+
+    /// Sorts the rows by their cells in the column named `column`, using the
+    /// ordering of `ShnCell`. The sort is stable.
+    pub fn sort_by_column(&mut self, column: &str) -> Result<()> {
+        let position = match self.schema.columns.iter()
+            .position(|c| c.name == column) {
+                Some(p) => p,
+                None    => return Err(ShnError::UnknownColumn(column.to_owned())),
+            };
+        self.data.sort_by(|a, b| a.data[position].cmp(&b.data[position]));
+        self.refresh();
+        Ok(())
+    }
+
+    /// Sets the `__ID__` pseudo-column of every row to its position.
+    pub fn renumber_ids(&mut self) -> Result<()> {
+        if self.schema.id_column().is_none() {
+            return Err(ShnError::MissingIdColumn);
+        }
+        if self.data.len() > u16::max_value() as usize + 1 {
+            return Err(ShnError::InvalidSchema);
+        }
+        for (id, row) in self.data.iter_mut().enumerate() {
+            try!(row.set_id(id as u16));
+        }
+        self.refresh_indexes();
+        Ok(())
+    }
+
+    /// Appends a column to the schema, with every row holding `default`.
+    pub fn add_column(&mut self, column: ShnColumn, default: ShnCell)
+                      -> Result<()> {
+        if column.data_type != default.data_type() || column.is_id() {
+            return Err(ShnError::InvalidSchema);
+        }
+        try!(column.check_length(&default));
+        if self.schema.columns.iter().any(|c| c.name == column.name) {
+            return Err(ShnError::DuplicateColumn(column.name));
+        }
+        let mut schema = (*self.schema).clone();
+        schema.columns.push(column);
+        self.replace_schema(schema, |cells| {
+            cells.push(default.clone());
+            Ok(())
+        })
+    }
+
+    /// Removes the column named `name` and its cells from every row.
+    pub fn remove_column(&mut self, name: &str) -> Result<()> {
+        let position = try!(self.data_column_position(name));
+        let mut schema = (*self.schema).clone();
+        schema.columns.remove(position);
+        self.replace_schema(schema, |cells| {
+            cells.remove(position);
+            Ok(())
+        })
+    }
+
+    /// Renames the column named `name` to `new_name`.
+    pub fn rename_column(&mut self, name: &str, new_name: &str) -> Result<()> {
+        let position = try!(self.data_column_position(name));
+        if self.schema.columns.iter().any(|c| c.name == new_name)
+            || new_name == SHN_ID_COLUMN_NAME {
+            return Err(ShnError::DuplicateColumn(new_name.to_owned()));
+        }
+        let mut schema = (*self.schema).clone();
+        schema.columns[position].name = new_name.to_owned();
+        if let Some(index) = self.indexes.remove(name) {
+            self.indexes.insert(new_name.to_owned(), index);
+        }
+        let result = self.replace_schema(schema, |_| Ok(()));
+        if result.is_err() {
+            if let Some(index) = self.indexes.remove(new_name) {
+                self.indexes.insert(name.to_owned(), index);
+            }
+        }
+        result
+    }
+
+    /// Moves the column named `name` to position `to` within the schema.
+    /// The `__ID__` pseudo-column has to stay in front of all others.
+    pub fn move_column(&mut self, name: &str, to: usize) -> Result<()> {
+        let from = try!(self.data_column_position(name));
+        if to >= self.schema.columns.len()
+            || (to == 0 && self.schema.id_column().is_some()) {
+            return Err(ShnError::InvalidSchema);
+        }
+        let mut schema = (*self.schema).clone();
+        let column = schema.columns.remove(from);
+        schema.columns.insert(to, column);
+        self.replace_schema(schema, |cells| {
+            let cell = cells.remove(from);
+            cells.insert(to, cell);
+            Ok(())
+        })
+    }
+
+    /// Changes the type of the column named `name`, converting the cell of
+    /// every row. `data_length` is only used for string types, all other
+    /// types use their default length. `StringFixedLen` columns need a
+    /// positive length, and every string needs to fit into it, see
+    /// `ShnColumn::check_length`.
+    ///
+    /// If any of the cells can not be converted, nothing is changed and
+    /// the returned error lists every failed row.
+    pub fn retype_column(&mut self,
+                         name: &str,
+                         data_type: ShnDataType,
+                         data_length: i32)
+                         -> Result<()> {
+        let position = try!(self.data_column_position(name));
+        let valid = match data_type {
+            ShnDataType::StringFixedLen         => data_length > 0,
+            ShnDataType::StringZeroTerminated   => data_length >= 0,
+            _                                   => true,
+        };
+        if !valid {
+            return Err(ShnError::InvalidSchema);
+        }
+        let mut schema = (*self.schema).clone();
+        {
+            let column = &mut schema.columns[position];
+            column.data_length = match data_type {
+                ShnDataType::StringFixedLen |
+                ShnDataType::StringZeroTerminated => data_length,
+                ref t => t.default_length() as i32,
+            };
+            column.data_type = data_type.clone();
+        }
+        let column = schema.columns[position].clone();
+        self.replace_schema(schema, |cells| {
+            let cell = &mut cells[position];
+            match cell.convert_to(&data_type) {
+                Some(c) => {
+                    try!(column.check_length(&c));
+                    *cell = c;
+                    Ok(())
+                },
+                None => Err(ShnError::InvalidConversion(cell.clone(),
+                                                        data_type.clone())),
+            }
+        })
+    }
+
+    /// Returns the position of the column named `name`, which may not be the
+    /// `__ID__` pseudo-column.
+    fn data_column_position(&self, name: &str) -> Result<usize> {
+        match self.schema.columns.iter().position(|c| c.name == name) {
+            Some(p) if !self.schema.columns[p].is_id() => Ok(p),
+            Some(_) => Err(ShnError::InvalidSchema),
+            None    => Err(ShnError::UnknownColumn(name.to_owned())),
+        }
+    }
+
+    /// Replaces the schema of the file and of every row, after applying
+    /// `f` to the cells of every row. If `f` fails for any row, nothing is
+    /// changed and `ShnError::RowErrors` lists the errors of all failed
+    /// rows. The same goes for indexes which can not be rebuilt, which fail
+    /// with `ShnError::InvalidIndex`. Indexes over removed columns are
+    /// dropped.
+    fn replace_schema<F>(&mut self, schema: ShnSchema, mut f: F) -> Result<()>
+        where F: FnMut(&mut Vec<ShnCell>) -> Result<()> {
+        let mut data = Vec::with_capacity(self.data.len());
+        let mut errors = Vec::new();
+        for (i, row) in self.data.iter().enumerate() {
+            let mut cells = row.data.clone();
+            match f(&mut cells) {
+                Ok(())  => data.push(cells),
+                Err(e)  => errors.push((i, e)),
+            }
+        }
+        if !errors.is_empty() {
+            return Err(ShnError::RowErrors(errors));
+        }
+
+        // Columns may have moved, changed their type or disappeared.
+        let mut indexes = HashMap::with_capacity(self.indexes.len());
+        for (name, index) in &self.indexes {
+            let position = match schema.columns.iter().position(|c| c.name == *name) {
+                Some(p) => p,
+                None    => continue,
+            };
+            let index = match ShnFile::index_rows(&data, position) {
+                Ok(index)   => index,
+                // Still outdated, see `refresh_indexes`
+                Err(_) if index.outdated => ShnIndex {
+                    column:     position,
+                    rows:       HashMap::new(),
+                    outdated:   true,
+                },
+                Err(e)  => return Err(ShnError::InvalidIndex(name.clone(),
+                                                             Box::new(e))),
+            };
+            indexes.insert(name.clone(), index);
+        }
+
+        let schema = Arc::new(schema);
+        for (row, cells) in self.data.iter_mut().zip(data.into_iter()) {
+            row.schema = schema.clone();
+            row.data = cells;
+        }
+        self.schema = schema;
+        self.indexes = indexes;
+        Ok(())
+    }
+
+    /// Builds a hash index over the column named `column`, which is then
+    /// used by `get_by_key` and kept up to date by the methods of `ShnFile`
+    /// modifying rows. Fails if two rows share the same key, or the column
+    /// holds floating point values.
+    pub fn index_by(&mut self, column: &str) -> Result<()> {
+        let position = match self.schema.columns.iter()
+            .position(|c| c.name == column) {
+                Some(p) => p,
+                None    => return Err(ShnError::UnknownColumn(column.to_owned())),
+            };
+        let index = try!(self.build_index(position));
+        self.indexes.insert(column.to_owned(), index);
+        Ok(())
+    }
+
+    /// Removes the index over the column named `column`, if there is one.
+    pub fn drop_index(&mut self, column: &str) {
+        self.indexes.remove(column);
+    }
+
+    /// Returns whether there is an index over the column named `column`.
+    pub fn is_indexed(&self, column: &str) -> bool {
+        self.indexes.contains_key(column)
+    }
+
+    /// Rebuilds all indexes, which is required after modifying `data`
+    /// directly. Fails with `ShnError::InvalidIndex` naming the first index
+    /// which can not be rebuilt, in which case all indexes are left as they
+    /// were.
+    pub fn reindex(&mut self) -> Result<()> {
+        let mut indexes = HashMap::new();
+        for (name, index) in &self.indexes {
+            let index = try!(self.build_index(index.column).map_err(|e| {
+                ShnError::InvalidIndex(name.clone(), Box::new(e))
+            }));
+            indexes.insert(name.clone(), index);
+        }
+        self.indexes = indexes;
+        Ok(())
+    }
+
+    /// Returns the names of the columns whose index could not be rebuilt
+    /// after inserting, removing or reordering rows, because `data` has been
+    /// modified directly. Lookups through those indexes scan all rows until
+    /// `reindex` succeeds.
+    pub fn outdated_indexes(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = self.indexes.iter()
+            .filter(|&(_, index)| index.outdated)
+            .map(|(name, _)| &name[..])
+            .collect();
+        names.sort();
+        names
+    }
+
+    /// Returns the position of the row whose cell in the column named
+    /// `column` matches `key`. Uses the index over the column if there is
+    /// one, otherwise falls back to scanning all rows. Rows found through
+    /// the index are checked to still hold the key, and searched for by
+    /// scanning if they do not, as `data` may have been modified directly.
+    pub fn position_by_key<K: Into<ShnKey>>(&self, column: &str, key: K)
+                                            -> Option<usize> {
+        let key = key.into();
+        let position = match self.schema.columns.iter()
+            .position(|c| c.name == column) {
+                Some(p) => p,
+                None    => return None,
+            };
+        let matches = |row: &ShnRow| {
+            row.data.get(position).and_then(ShnKey::from_cell).as_ref()
+                == Some(&key)
+        };
+        let index = self.indexes.get(column).filter(|i| !i.outdated);
+        match index.map(|i| i.rows.get(&key)) {
+            Some(Some(&p)) if self.data.get(p).map_or(false, &matches)
+                => Some(p),
+            Some(None)  => None,
+            // Not indexed, or the index is outdated
+            _           => self.data.iter().position(matches),
+        }
+    }
+
+    /// Returns the row whose cell in the column named `column` matches
+    /// `key`, see `position_by_key`.
+    pub fn get_by_key<K: Into<ShnKey>>(&self, column: &str, key: K)
+                                       -> Option<&ShnRow> {
+        self.position_by_key(column, key).and_then(|p| self.data.get(p))
+    }
+
+    /// Returns the name of the first data column, which holds the ID of
+    /// each row in most files.
+    pub fn key_column(&self) -> Option<&str> {
+        self.schema.data_columns().first().map(|c| &c.name[..])
+    }
+
+    /// Returns the row whose cell in the key column matches `key`, see
+    /// `key_column` and `get_by_key`.
+    pub fn by_key<K: Into<ShnKey>>(&self, key: K) -> Option<&ShnRow> {
+        self.key_column().and_then(|column| self.get_by_key(column, key))
+    }
+
+    /// Fails if renumbering is enabled and `len` rows can not be numbered.
+    fn check_renumber(&self, len: usize) -> Result<()> {
+        if self.auto_renumber && len > u16::max_value() as usize + 1 {
+            Err(ShnError::InvalidSchema)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Brings the `__ID__` pseudo-column and the indexes up to date after a
+    /// structural change.
+    fn refresh(&mut self) {
+        if self.auto_renumber && self.schema.id_column().is_some() {
+            for (id, row) in self.data.iter_mut().enumerate() {
+                let _ = row.set_id(id as u16);
+            }
+        }
+        self.refresh_indexes();
+    }
+
+    /// Rebuilds all indexes after a change. Indexes which can not be
+    /// rebuilt, because `data` has been modified directly, are kept but
+    /// marked as outdated, see `outdated_indexes`.
+    fn refresh_indexes(&mut self) {
+        let indexes = mem::replace(&mut self.indexes, HashMap::new());
+        for (name, index) in indexes {
+            let index = match self.build_index(index.column) {
+                Ok(index)   => index,
+                Err(_)      => ShnIndex {
+                    column:     index.column,
+                    rows:       HashMap::new(),
+                    outdated:   true,
+                },
+            };
+            self.indexes.insert(name, index);
+        }
+    }
+
+    fn build_index(&self, column: usize) -> Result<ShnIndex> {
+        ShnFile::index_rows(self.data.iter().map(|r| &r.data), column)
+    }
+
+    /// Builds an index over the column at `column` of rows holding the
+    /// cells yielded by `data`.
+    fn index_rows<'a, I>(data: I, column: usize) -> Result<ShnIndex>
+        where I: IntoIterator<Item = &'a Vec<ShnCell>> {
+        let mut rows = HashMap::new();
+        for (position, cells) in data.into_iter().enumerate() {
+            let key = try!(cells.get(column)
+                           .and_then(ShnKey::from_cell)
+                           .ok_or(ShnError::InvalidSchema));
+            if rows.contains_key(&key) {
+                return Err(ShnError::DuplicateKey(key));
+            }
+            rows.insert(key, position);
+        }
+        Ok(ShnIndex {
+            column:     column,
+            rows:       rows,
+            outdated:   false,
+        })
+    }
+
+    /// Returns the key `row` has in each index, and checks none of them is
+    /// taken by another row than the one at `ignore`.
+    fn index_keys(&self, row: &ShnRow, ignore: Option<usize>)
+                  -> Result<Vec<(String, ShnKey)>> {
+        let mut keys = Vec::with_capacity(self.indexes.len());
+        for (name, index) in &self.indexes {
+            let key = try!(row.data.get(index.column)
+                           .and_then(ShnKey::from_cell)
+                           .ok_or(ShnError::InvalidSchema));
+            match index.rows.get(&key) {
+                Some(p) if Some(*p) != ignore
+                    => return Err(ShnError::DuplicateKey(key)),
+                _   => {},
+            }
+            keys.push((name.clone(), key));
+        }
+        Ok(keys)
+    }
+
+    /// Appends a row to the file like `append_row`, but sets its `__ID__`
+    /// pseudo-column to the position of the row within the file first.
+    pub fn append_row_numbered(&mut self, mut row: ShnRow) -> Result<()> {
+        let id = self.data.len();
+        if id > u16::max_value() as usize {
+            return Err(ShnError::InvalidSchema);
+        }
+        try!(row.set_id(id as u16));
+        self.append_row(row)
+    }
+
+    /// Returns the offset of every row relative to the first one, as they
+    /// would be laid out when writing the file, followed by the offset just
+    /// past the last row.
+    pub fn row_offsets(&self, enc: &EncodingRef) -> Result<Vec<usize>> {
+        let mut offsets = Vec::with_capacity(self.data.len() + 1);
+        let mut offset = 0;
+        offsets.push(offset);
+        for row in &self.data {
+            offset += try!(row.byte_len(enc));
+            offsets.push(offset);
+        }
+        Ok(offsets)
     }
 }
 
@@ -317,4 +1618,757 @@ pub enum ShnError {
     InvalidSchema,
     InvalidFile,
     InvalidEncoding,
+    MissingIdColumn,
+    UnknownColumn(String),
+    DuplicateKey(ShnKey),
+    DuplicateColumn(String),
+    InvalidConversion(ShnCell, ShnDataType),
+    RowErrors(Vec<(usize, ShnError)>),
+    InvalidValue(ShnCell),
+    InvalidText(String, ShnDataType),
+    /// A query or expression could not be parsed, with the byte offset of
+    /// the error within the text
+    InvalidQuery(usize, String),
+    UnknownTable(String),
+    DuplicateTable(String),
+    InvalidMetadata(String),
+    /// The schema of a table differs from the expected one
+    SchemaMismatch(String, Vec<ShnColumnDifference>),
+    /// The fingerprint of a table's schema is not registered for it
+    UnknownSchema(String, u64),
+    /// A registry could not be read, with the line of the error
+    InvalidRegistry(usize, String),
+    /// A schema definition could not be read, with the line of the error
+    InvalidDefinition(usize, String),
+    /// CSV text could not be read, with the line of the error
+    InvalidCsv(usize, String),
+    /// A ShineTable text file could not be read, with the line of the error
+    InvalidShineTable(usize, String),
+    /// The record length declared by a file differs from the sum of its
+    /// column lengths, with the declared length first
+    RecordLengthMismatch(u32, u32),
+    /// Memory for the requested number of rows can not be allocated
+    CapacityOverflow,
+    /// The index over a column can not be built
+    InvalidIndex(String, Box<ShnError>),
+    /// A string does not fit into the length of its column
+    StringTooLong(String, i32),
+    /// A column of a file has a type id unknown to `ShnDataType`, with the
+    /// name of the column
+    UnknownDataType(String, u32),
+    Io(io::Error),
+}
+
+impl fmt::Display for ShnError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ShnError::InvalidSchema     => write!(f, "invalid schema"),
+            ShnError::InvalidFile       => write!(f, "invalid or truncated file"),
+            ShnError::InvalidEncoding
+                => write!(f, "text not representable in the encoding"),
+            ShnError::MissingIdColumn
+                => write!(f, "schema lacks the {} column", SHN_ID_COLUMN_NAME),
+            ShnError::UnknownColumn(ref name)
+                => write!(f, "unknown column `{}`", name),
+            ShnError::DuplicateKey(ref key)
+                => write!(f, "duplicate key {:?}", key),
+            ShnError::DuplicateColumn(ref name)
+                => write!(f, "duplicate column `{}`", name),
+            ShnError::InvalidConversion(ref cell, ref data_type)
+                => write!(f, "{:?} is not compatible with {:?}", cell, data_type),
+            ShnError::RowErrors(ref errors) => {
+                try!(write!(f, "{} rows failed", errors.len()));
+                match errors.first() {
+                    Some(&(row, ref e)) => write!(f, ", first row {}: {}", row, e),
+                    None                => Ok(()),
+                }
+            },
+            ShnError::InvalidValue(ref cell)
+                => write!(f, "invalid value {:?}", cell),
+            ShnError::InvalidText(ref text, ref data_type)
+                => write!(f, "`{}` is not a valid {:?}", text, data_type),
+            ShnError::InvalidQuery(offset, ref message)
+                => write!(f, "{} at offset {}", message, offset),
+            ShnError::UnknownTable(ref name)
+                => write!(f, "unknown table `{}`", name),
+            ShnError::DuplicateTable(ref name)
+                => write!(f, "duplicate table `{}`", name),
+            ShnError::InvalidMetadata(ref message)
+                => write!(f, "invalid metadata: {}", message),
+            ShnError::SchemaMismatch(ref table, ref differences) => {
+                try!(write!(f, "schema of `{}` differs", table));
+                for (i, d) in differences.iter().enumerate() {
+                    try!(write!(f, "{} {}", if i == 0 { ":" } else { "," }, d));
+                }
+                Ok(())
+            },
+            ShnError::UnknownSchema(ref table, fingerprint)
+                => write!(f, "unknown schema {:016x} for `{}`", fingerprint,
+                          table),
+            ShnError::InvalidRegistry(line, ref message)
+                => write!(f, "{} in line {} of registry", message, line),
+            ShnError::InvalidDefinition(line, ref message)
+                => write!(f, "{} in line {} of schema definition", message,
+                          line),
+            ShnError::InvalidCsv(line, ref message)
+                => write!(f, "{} in line {} of CSV", message, line),
+            ShnError::InvalidShineTable(line, ref message)
+                => write!(f, "{} in line {}", message, line),
+            ShnError::RecordLengthMismatch(declared, columns)
+                => write!(f, "declared record length {} differs from the \
+                              column lengths {}", declared, columns),
+            ShnError::CapacityOverflow  => write!(f, "capacity overflow"),
+            ShnError::InvalidIndex(ref column, ref e)
+                => write!(f, "index over `{}` can not be built: {}", column, e),
+            ShnError::StringTooLong(ref st, length)
+                => write!(f, "`{}` does not fit into {} bytes", st, length),
+            ShnError::UnknownDataType(ref column, id)
+                => write!(f, "column `{}` has unknown type id {}", column, id),
+            ShnError::Io(ref e) => e.fmt(f),
+        }
+    }
+}
+
+impl ::std::error::Error for ShnError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::encoding::all::ASCII;
+    use super::super::shn_reader::ShnReader;
+    use super::super::shn_writer::ShnWriter;
+
+    fn schema(desc_length: i32) -> Arc<ShnSchema> {
+        let mut schema = ShnSchema::with_id_column();
+        schema.columns.push(ShnColumn::new_unsigned_integer("ID"));
+        schema.columns.push(ShnColumn::new_string_fixed_len("Name", 8));
+        schema.columns.push(ShnColumn {
+            name:           "Desc".to_owned(),
+            data_type:      ShnDataType::StringZeroTerminated,
+            data_length:    desc_length,
+        });
+        Arc::new(schema)
+    }
+
+    fn file(schema: &Arc<ShnSchema>, descs: &[&str]) -> ShnFile {
+        let mut file = ShnFile::new([0; SHN_CRYPT_HEADER_LEN], 0, schema.clone());
+        for (i, desc) in descs.iter().enumerate() {
+            file.append_row(ShnRow {
+                schema: schema.clone(),
+                data:   vec![ShnCell::UnsignedShort(i as u16),
+                             ShnCell::UnsignedInteger(i as u32),
+                             ShnCell::StringFixedLen("name".to_owned()),
+                             ShnCell::StringZeroTerminated(desc.to_string())],
+            }).unwrap();
+        }
+        file
+    }
+
+    fn write(file: &ShnFile) -> Vec<u8> {
+        let enc: EncodingRef = ASCII;
+        let mut buf = Vec::new();
+        ShnWriter::write_to(file, &enc, &mut buf).unwrap();
+        buf
+    }
+
+    #[test]
+    fn declared_record_length_round_trip() {
+        let enc: EncodingRef = ASCII;
+        for &desc_length in &[0, 64] {
+            let schema = schema(desc_length);
+            let expected = 2 + 4 + 8 + desc_length as u32;
+            assert_eq!(schema.declared_record_length().unwrap(), expected);
+            let written = write(&file(&schema, &["", "some text"]));
+            let header = ShnReader::read_header(&written[..], &enc).unwrap();
+            assert_eq!(header.default_record_length, expected);
+            assert_eq!(header.schema, schema);
+        }
+    }
+
+    #[test]
+    fn declared_record_length_invalid() {
+        let mut negative = (*schema(0)).clone();
+        negative.columns[3].data_length = -1;
+        assert!(negative.declared_record_length().is_err());
+        let mut overflow = (*schema(0)).clone();
+        overflow.columns[2].data_length = i32::max_value();
+        overflow.columns[3].data_length = i32::max_value();
+        overflow.columns.push(ShnColumn::new_string_fixed_len("More", i32::max_value()));
+        assert!(overflow.declared_record_length().is_err());
+
+        let enc: EncodingRef = ASCII;
+        let file = ShnFile::new([0; SHN_CRYPT_HEADER_LEN], 0, Arc::new(negative));
+        match ShnWriter::write_to(&file, &enc, &mut Vec::new()) {
+            Err(ShnError::InvalidSchema) => {},
+            r => panic!("{:?}", r),
+        }
+    }
+
+    #[test]
+    fn record_length_mismatch() {
+        let enc: EncodingRef = ASCII;
+        let mut written = write(&file(&schema(0), &["a"]));
+        // The record length is the third field of the encrypted data
+        decrypt(&mut written[0x24..]);
+        written[0x24 + 8] += 1;
+        decrypt(&mut written[0x24..]);
+        match ShnReader::read_from(&written[..], &enc) {
+            Err(ShnError::RecordLengthMismatch(15, 14)) => {},
+            r => panic!("{:?}", r),
+        }
+    }
+
+    #[test]
+    fn row_sizes_match_written_rows() {
+        let enc: EncodingRef = ASCII;
+        let schema = schema(0);
+        // A trailing `00`-byte is taken as the terminator
+        let file = file(&schema, &["", "abc", "abc\u{0}", "longer text"]);
+        let offsets = file.row_offsets(&enc).unwrap();
+        assert_eq!(offsets, vec![0, 15, 33, 51, 77]);
+        let preamble = 0x24 + 16 + 56 * schema.data_columns().len();
+        assert_eq!(write(&file).len(), preamble + offsets[4]);
+
+        let read = ShnReader::read_from(&write(&file)[..], &enc).unwrap();
+        assert_eq!(read.data[2].data[3],
+                   ShnCell::StringZeroTerminated("abc".to_owned()));
+        assert_eq!(read.row_offsets(&enc).unwrap(), offsets);
+    }
+
+    #[test]
+    fn fixed_record_length() {
+        let mut schema = ShnSchema::with_id_column();
+        schema.columns.push(ShnColumn::new_byte("A"));
+        schema.columns.push(ShnColumn::new_string_fixed_len("B", 5));
+        assert_eq!(schema.fixed_record_length(), Some(8));
+        schema.columns.push(ShnColumn::new_string_terminated("C"));
+        assert_eq!(schema.fixed_record_length(), None);
+    }
+
+    fn keyed_file(ids: &[u32]) -> ShnFile {
+        let mut schema = ShnSchema::with_id_column();
+        schema.columns.push(ShnColumn::new_unsigned_integer("ID"));
+        schema.columns.push(ShnColumn::new_string_fixed_len("Name", 8));
+        schema.columns.push(ShnColumn::new_single_floating_point("Rate"));
+        let schema = Arc::new(schema);
+        let mut file = ShnFile::new([0; SHN_CRYPT_HEADER_LEN], 0, schema.clone());
+        for (i, &id) in ids.iter().enumerate() {
+            file.append_row(ShnRow {
+                schema: schema.clone(),
+                data:   vec![ShnCell::UnsignedShort(i as u16),
+                             ShnCell::UnsignedInteger(id),
+                             ShnCell::StringFixedLen(format!("n{}", id)),
+                             ShnCell::SingleFloatingPoint(id as f32)],
+            }).unwrap();
+        }
+        file
+    }
+
+    fn name(row: Option<&ShnRow>) -> Option<String> {
+        row.and_then(|r| r.data[2].as_str().map(|s| s.to_owned()))
+    }
+
+    #[test]
+    fn index_lookup() {
+        let mut file = keyed_file(&[10, 20, 30]);
+        file.index_by("ID").unwrap();
+        file.index_by("Name").unwrap();
+        assert!(file.is_indexed("ID"));
+        assert_eq!(file.position_by_key("ID", 20u32), Some(1));
+        assert_eq!(name(file.get_by_key("Name", "n30")), Some("n30".to_owned()));
+        assert_eq!(name(file.by_key(10u32)), Some("n10".to_owned()));
+        assert!(file.get_by_key("ID", 40u32).is_none());
+        assert!(file.get_by_key("Nope", 10u32).is_none());
+        file.drop_index("ID");
+        assert!(!file.is_indexed("ID"));
+        assert_eq!(file.position_by_key("ID", 30u32), Some(2));
+    }
+
+    #[test]
+    fn index_errors() {
+        let mut file = keyed_file(&[10, 20, 10]);
+        match file.index_by("ID") {
+            Err(ShnError::DuplicateKey(ShnKey::Integer(10))) => {},
+            r => panic!("{:?}", r),
+        }
+        assert!(file.index_by("Rate").is_err());
+        assert!(file.index_by("Nope").is_err());
+        assert!(!file.is_indexed("ID"));
+    }
+
+    #[test]
+    fn index_follows_appends_and_updates() {
+        let mut file = keyed_file(&[10, 20]);
+        file.index_by("ID").unwrap();
+        let mut row = file.data[0].clone();
+        assert!(file.append_row(row.clone()).is_err());
+        row.data[1] = ShnCell::UnsignedInteger(30);
+        file.append_row(row).unwrap();
+        assert_eq!(file.position_by_key("ID", 30u32), Some(2));
+
+        match file.set_cell(0, "ID", ShnCell::UnsignedInteger(20)) {
+            Err(ShnError::DuplicateKey(_)) => {},
+            r => panic!("{:?}", r),
+        }
+        file.set_cell(0, "ID", ShnCell::UnsignedInteger(11)).unwrap();
+        assert_eq!(file.position_by_key("ID", 11u32), Some(0));
+        assert_eq!(file.position_by_key("ID", 10u32), None);
+    }
+
+    #[test]
+    fn outdated_index() {
+        let mut file = keyed_file(&[10, 20, 30]);
+        file.index_by("ID").unwrap();
+        // Modifying the rows directly leaves the index outdated
+        file.data.truncate(1);
+        assert!(file.get_by_key("ID", 30u32).is_none());
+        file.data.insert(0, file.data[0].clone());
+        file.data[0].data[1] = ShnCell::UnsignedInteger(5);
+        assert_eq!(file.position_by_key("ID", 10u32), Some(1));
+        assert_eq!(name(file.get_by_key("ID", 10u32)), Some("n10".to_owned()));
+        file.reindex().unwrap();
+        assert_eq!(file.position_by_key("ID", 5u32), Some(0));
+    }
+
+    fn assert_index_consistent(file: &ShnFile) {
+        for (i, row) in file.data.iter().enumerate() {
+            let key = ShnKey::from_cell(&row.data[1]).unwrap();
+            assert_eq!(file.position_by_key("ID", key.clone()), Some(i));
+            assert_eq!(file.indexes["ID"].rows.get(&key), Some(&i));
+        }
+        assert_eq!(file.indexes["ID"].rows.len(), file.data.len());
+    }
+
+    fn ids(file: &ShnFile) -> Vec<(Option<u16>, u32)> {
+        file.data.iter()
+            .map(|r| (r.id(), u32::try_from(&r.data[1]).unwrap()))
+            .collect()
+    }
+
+    #[test]
+    fn index_follows_mutations() {
+        let mut file = keyed_file(&[30, 10, 20]);
+        file.index_by("ID").unwrap();
+
+        let mut row = file.data[0].clone();
+        row.data[1] = ShnCell::UnsignedInteger(40);
+        file.insert_row(1, row.clone()).unwrap();
+        assert_index_consistent(&file);
+        assert!(file.insert_row(0, row).is_err());
+        assert_eq!(file.data.len(), 4);
+
+        assert_eq!(u32::try_from(&file.remove_row(0).unwrap().data[1]).unwrap(), 30);
+        assert!(file.remove_row(3).is_none());
+        assert_index_consistent(&file);
+
+        file.sort_by_column("ID").unwrap();
+        assert_eq!(ids(&file).iter().map(|&(_, id)| id).collect::<Vec<_>>(),
+                   vec![10, 20, 40]);
+        assert_index_consistent(&file);
+
+        file.retain(|r| r.data[1] != ShnCell::UnsignedInteger(20));
+        assert_index_consistent(&file);
+        assert_eq!(file.position_by_key("ID", 20u32), None);
+        assert!(file.sort_by_column("Nope").is_err());
+    }
+
+    #[test]
+    fn auto_renumber() {
+        let mut file = keyed_file(&[30, 10, 20]);
+        file.index_by("__ID__").unwrap();
+        file.set_auto_renumber(true);
+        file.sort_by_column("ID").unwrap();
+        assert_eq!(ids(&file), vec![(Some(0), 10), (Some(1), 20), (Some(2), 30)]);
+        file.remove_row(0);
+        assert_eq!(ids(&file), vec![(Some(0), 20), (Some(1), 30)]);
+        let mut row = file.data[0].clone();
+        row.data[1] = ShnCell::UnsignedInteger(5);
+        file.append_row(row).unwrap();
+        assert_eq!(ids(&file), vec![(Some(0), 20), (Some(1), 30), (Some(2), 5)]);
+        assert_eq!(file.position_by_key("__ID__", 2u16), Some(2));
+    }
+
+    #[test]
+    fn outdated_index_after_mutation() {
+        let mut file = keyed_file(&[10, 20, 30]);
+        file.index_by("ID").unwrap();
+        file.index_by("Name").unwrap();
+        // Duplicate keys introduced by modifying the rows directly
+        file.data[2].data[1] = ShnCell::UnsignedInteger(10);
+        file.remove_row(1);
+        assert_eq!(file.outdated_indexes(), vec!["ID"]);
+        assert!(file.is_indexed("ID"));
+        assert_eq!(file.position_by_key("ID", 10u32), Some(0));
+        assert_eq!(file.position_by_key("Name", "n30"), Some(1));
+
+        match file.reindex() {
+            Err(ShnError::InvalidIndex(ref column, ref e)) if column == "ID" => {
+                match **e {
+                    ShnError::DuplicateKey(ShnKey::Integer(10)) => {},
+                    ref e => panic!("{:?}", e),
+                }
+            },
+            r => panic!("{:?}", r),
+        }
+        file.data[1].data[1] = ShnCell::UnsignedInteger(30);
+        file.reindex().unwrap();
+        assert!(file.outdated_indexes().is_empty());
+        assert_index_consistent(&file);
+    }
+
+    #[test]
+    fn set_cell_errors() {
+        let mut file = keyed_file(&[10]);
+        assert!(file.set_cell(0, "Nope", ShnCell::Byte(0)).is_err());
+        assert!(file.set_cell(1, "ID", ShnCell::UnsignedInteger(0)).is_err());
+        assert!(file.set_cell(0, "ID", ShnCell::SignedInteger(0)).is_err());
+        assert_eq!(file.set_cell(0, "ID", ShnCell::UnsignedInteger(3)).unwrap(),
+                   ShnCell::UnsignedInteger(10));
+
+        // Strings need to fit into their column
+        match file.set_cell(0, "Name", ShnCell::StringFixedLen("123456789".to_owned())) {
+            Err(ShnError::StringTooLong(ref s, 8)) => assert_eq!(s, "123456789"),
+            r => panic!("{:?}", r),
+        }
+        assert_eq!(file.data[0].data[2], ShnCell::StringFixedLen("n10".to_owned()));
+        file.set_cell(0, "Name", ShnCell::StringFixedLen("12345678".to_owned()))
+            .unwrap();
+    }
+
+    fn column_names(file: &ShnFile) -> Vec<&str> {
+        file.schema.columns.iter().map(|c| &c.name[..]).collect()
+    }
+
+    fn assert_rows_share_schema(file: &ShnFile) {
+        for row in &file.data {
+            assert!(Arc::ptr_eq(&row.schema, &file.schema));
+            assert_eq!(row.data.len(), file.schema.columns.len());
+        }
+    }
+
+    #[test]
+    fn schema_evolution() {
+        let mut file = keyed_file(&[10, 20, 300]);
+        file.index_by("ID").unwrap();
+
+        file.add_column(ShnColumn::new_byte("Level"), ShnCell::Byte(1)).unwrap();
+        assert_eq!(column_names(&file), vec!["__ID__", "ID", "Name", "Rate", "Level"]);
+        assert_rows_share_schema(&file);
+        assert!(file.data.iter().all(|r| r.data[4] == ShnCell::Byte(1)));
+
+        file.move_column("Level", 1).unwrap();
+        assert_eq!(column_names(&file), vec!["__ID__", "Level", "ID", "Name", "Rate"]);
+        assert_eq!(file.position_by_key("ID", 300u32), Some(2));
+        assert_index_consistent_at(&file, 2);
+
+        file.rename_column("ID", "ItemID").unwrap();
+        assert!(file.is_indexed("ItemID") && !file.is_indexed("ID"));
+        assert_eq!(file.position_by_key("ItemID", 20u32), Some(1));
+
+        file.remove_column("Rate").unwrap();
+        file.retype_column("ItemID", ShnDataType::SignedInteger, 0).unwrap();
+        assert_eq!(file.schema.columns[2].data_type, ShnDataType::SignedInteger);
+        assert_eq!(file.data[2].data[2], ShnCell::SignedInteger(300));
+        assert_eq!(file.position_by_key("ItemID", 300), Some(2));
+        assert_rows_share_schema(&file);
+
+        file.remove_column("ItemID").unwrap();
+        assert!(!file.is_indexed("ItemID"));
+        assert_eq!(column_names(&file), vec!["__ID__", "Level", "Name"]);
+    }
+
+    fn assert_index_consistent_at(file: &ShnFile, column: usize) {
+        for (i, row) in file.data.iter().enumerate() {
+            let key = ShnKey::from_cell(&row.data[column]).unwrap();
+            assert_eq!(file.position_by_key(&file.schema.columns[column].name[..],
+                                            key), Some(i));
+        }
+    }
+
+    #[test]
+    fn schema_evolution_errors() {
+        let mut file = keyed_file(&[10, 20]);
+        assert!(file.add_column(ShnColumn::new_byte("ID"), ShnCell::Byte(0)).is_err());
+        assert!(file.add_column(ShnColumn::new_byte("A"), ShnCell::SignedByte(0)).is_err());
+        assert!(file.add_column(ShnColumn::new_string_fixed_len("B", 2),
+                                ShnCell::StringFixedLen("abc".to_owned())).is_err());
+        assert!(file.remove_column("__ID__").is_err());
+        assert!(file.remove_column("Nope").is_err());
+        assert!(file.rename_column("ID", "Name").is_err());
+        assert!(file.rename_column("ID", "__ID__").is_err());
+        assert!(file.move_column("ID", 0).is_err());
+        assert!(file.move_column("ID", 4).is_err());
+        assert_eq!(column_names(&file), vec!["__ID__", "ID", "Name", "Rate"]);
+    }
+
+    #[test]
+    fn retype_reports_rows() {
+        let mut file = keyed_file(&[10, 300, 20, 256]);
+        match file.retype_column("ID", ShnDataType::Byte, 0) {
+            Err(ShnError::RowErrors(ref errors)) => {
+                let rows: Vec<usize> = errors.iter().map(|&(r, _)| r).collect();
+                assert_eq!(rows, vec![1, 3]);
+            },
+            r => panic!("{:?}", r),
+        }
+        assert_eq!(file.schema.columns[1].data_type, ShnDataType::UnsignedInteger);
+        assert_eq!(file.data[1].data[1], ShnCell::UnsignedInteger(300));
+    }
+
+    #[test]
+    fn retype_to_fixed_length_string() {
+        let mut file = keyed_file(&[1, 22, 333]);
+        for &length in &[0, -1] {
+            match file.retype_column("ID", ShnDataType::StringFixedLen, length) {
+                Err(ShnError::InvalidSchema) => {},
+                r => panic!("{:?}", r),
+            }
+        }
+        match file.retype_column("ID", ShnDataType::StringFixedLen, 2) {
+            Err(ShnError::RowErrors(ref errors)) => {
+                assert_eq!(errors.len(), 1);
+                match errors[0] {
+                    (2, ShnError::StringTooLong(ref st, 2)) if st == "333" => {},
+                    ref e => panic!("{:?}", e),
+                }
+            },
+            r => panic!("{:?}", r),
+        }
+        file.retype_column("ID", ShnDataType::StringFixedLen, 3).unwrap();
+        assert_eq!(file.data[2].data[1], ShnCell::StringFixedLen("333".to_owned()));
+    }
+
+    #[test]
+    fn retype_keeps_index_or_fails() {
+        let mut file = keyed_file(&[10, 20]);
+        file.index_by("ID").unwrap();
+        // Floating point columns can not be indexed
+        match file.retype_column("ID", ShnDataType::SingleFloatingPoint, 0) {
+            Err(ShnError::InvalidIndex(ref column, _)) if column == "ID" => {},
+            r => panic!("{:?}", r),
+        }
+        assert_eq!(file.schema.columns[1].data_type, ShnDataType::UnsignedInteger);
+        assert!(file.is_indexed("ID"));
+        assert_eq!(file.position_by_key("ID", 20u32), Some(1));
+    }
+
+    #[test]
+    fn cell_conversions() {
+        assert_eq!(ShnCell::from(7u8), ShnCell::Byte(7));
+        assert_eq!(ShnCell::from(-7i16), ShnCell::SignedShort(-7));
+        assert_eq!(ShnCell::from(7u32), ShnCell::UnsignedInteger(7));
+        assert_eq!(ShnCell::from(0.5f32), ShnCell::SingleFloatingPoint(0.5));
+        assert_eq!(ShnCell::from("a"), ShnCell::StringFixedLen("a".to_owned()));
+        assert_eq!(ShnCell::from("a".to_owned()),
+                   ShnCell::StringFixedLen("a".to_owned()));
+
+        assert_eq!(u8::try_from(&ShnCell::UnsignedInteger(255)).unwrap(), 255);
+        assert_eq!(i64::try_from(ShnCell::SignedByte(-1)).unwrap(), -1);
+        assert_eq!(u64::try_from(ShnCell::UnsignedInteger(u32::max_value())).unwrap(),
+                   u32::max_value() as u64);
+        assert_eq!(f32::try_from(ShnCell::SignedInteger(-3)).unwrap(), -3.0);
+        assert_eq!(f64::try_from(&ShnCell::SingleFloatingPoint(0.5)).unwrap(), 0.5);
+        assert_eq!(String::try_from(ShnCell::StringZeroTerminated("b".to_owned()))
+                   .unwrap(), "b");
+        assert_eq!(ShnCell::UnsignedShort(3).as_f64(), Some(3.0));
+        assert_eq!(ShnCell::UnsignedShort(3).as_str(), None);
+    }
+
+    #[test]
+    fn cell_conversion_errors() {
+        fn invalid<T: ::std::fmt::Debug>(result: Result<T>, cell: ShnCell) {
+            match result {
+                Err(ShnError::InvalidValue(ref c)) => assert_eq!(*c, cell),
+                r => panic!("{:?}", r),
+            }
+        }
+        let cell = ShnCell::UnsignedInteger(256);
+        invalid(u8::try_from(&cell), cell.clone());
+        let cell = ShnCell::SignedShort(-1);
+        invalid(u32::try_from(cell.clone()), cell.clone());
+        invalid(u64::try_from(&cell), cell);
+        let cell = ShnCell::SingleFloatingPoint(1.0);
+        invalid(i32::try_from(&cell), cell.clone());
+        invalid(String::try_from(cell.clone()), cell);
+        // Not exactly representable as `f32`
+        let cell = ShnCell::UnsignedInteger(16777217);
+        invalid(f32::try_from(&cell), cell.clone());
+        assert_eq!(f64::try_from(cell).unwrap(), 16777217.0);
+        let cell = ShnCell::StringFixedLen("1".to_owned());
+        invalid(f64::try_from(&cell), cell.clone());
+        invalid(u8::try_from(cell.clone()), cell);
+    }
+
+    #[test]
+    fn cell_ordering() {
+        use ::std::collections::HashSet;
+        use ::std::f32;
+
+        let mut cells = vec![ShnCell::SignedByte(3), ShnCell::SignedByte(-3),
+                             ShnCell::StringFixedLen("b".to_owned()),
+                             ShnCell::StringFixedLen("a".to_owned())];
+        cells.sort();
+        assert_eq!(cells, vec![ShnCell::StringFixedLen("a".to_owned()),
+                               ShnCell::StringFixedLen("b".to_owned()),
+                               ShnCell::SignedByte(-3), ShnCell::SignedByte(3)]);
+        // Cells of different types are never equal
+        assert!(ShnCell::Byte(1) != ShnCell::SignedByte(1));
+        assert!(ShnCell::StringFixedLen("a".to_owned())
+                != ShnCell::StringZeroTerminated("a".to_owned()));
+
+        // Floats compare by their bits, so NaN equals itself and -0.0 is not 0.0
+        let nan = ShnCell::SingleFloatingPoint(f32::NAN);
+        let zero = ShnCell::SingleFloatingPoint(0.0);
+        let negative_zero = ShnCell::SingleFloatingPoint(-0.0);
+        assert_eq!(nan, nan.clone());
+        assert!(zero != negative_zero);
+        assert!(negative_zero < zero);
+        assert!(zero < nan);
+        assert!(ShnCell::SingleFloatingPoint(f32::NEG_INFINITY) < negative_zero);
+
+        let set: HashSet<ShnCell> = vec![nan.clone(), nan, zero.clone(), zero,
+                                         negative_zero, ShnCell::Byte(0),
+                                         ShnCell::SignedByte(0)]
+            .into_iter().collect();
+        assert_eq!(set.len(), 5);
+    }
+
+    #[test]
+    fn row_and_file_equality() {
+        let original = file(&schema(0), &["a", "b"]);
+        let mut copy = original.clone();
+        assert_eq!(copy, original);
+        assert_eq!(copy.data[0], original.data[0]);
+
+        // Structurally equal schemas need not be shared
+        copy.schema = schema(0);
+        for row in &mut copy.data {
+            row.schema = copy.schema.clone();
+        }
+        assert!(!Arc::ptr_eq(&copy.schema, &original.schema));
+        assert_eq!(copy, original);
+        assert_eq!(copy.data[1], original.data[1]);
+
+        // Indexes are not compared
+        copy.index_by("ID").unwrap();
+        assert_eq!(copy, original);
+
+        let other = file(&schema(4), &["a", "b"]);
+        assert!(other != original);
+        assert!(other.data[0] != original.data[0]);
+        copy.header = 1;
+        assert!(copy != original);
+        copy.data[0].data[3] = ShnCell::StringZeroTerminated("c".to_owned());
+        assert!(copy.data[0] != original.data[0]);
+    }
+
+    #[test]
+    fn content_eq_options() {
+        let mut schema = ShnSchema::with_id_column();
+        schema.columns.push(ShnColumn::new_single_floating_point("Rate"));
+        let schema = Arc::new(schema);
+        let file = |header: u32, rate: f32| {
+            let mut file = ShnFile::new([0; SHN_CRYPT_HEADER_LEN], header,
+                                        schema.clone());
+            file.data.push(ShnRow {
+                schema: schema.clone(),
+                data:   vec![ShnCell::UnsignedShort(0),
+                             ShnCell::SingleFloatingPoint(rate)],
+            });
+            file
+        };
+        let (a, b) = (file(0, 0.1), file(1, 0.1 + 1e-6));
+        assert!(!a.content_eq(&b));
+
+        let mut options = ContentEqOptions::new();
+        options.ignore_headers = true;
+        assert!(a.content_eq_with(&file(1, 0.1), &options));
+        assert!(!a.content_eq_with(&b, &options));
+        options.float_epsilon = Some(1e-5);
+        assert!(a.content_eq_with(&b, &options));
+        assert!(!a.content_eq_with(&file(1, 0.2), &options));
+        assert!(file(0, ::std::f32::NAN).content_eq(&file(0, ::std::f32::NAN)));
+    }
+
+    #[test]
+    fn debug_output() {
+        let file = file(&schema(0), &["a"]);
+        let row = format!("{:?}", file.data[0]);
+        assert_eq!(row, "ShnRow { data: [UnsignedShort(0), UnsignedInteger(0), \
+                         StringFixedLen(\"name\"), StringZeroTerminated(\"a\")] }");
+        let debug = format!("{:?}", file);
+        assert!(debug.starts_with("ShnFile { crypt_header: [0, 0, "));
+        assert!(debug.contains(", header: 0, schema: ShnSchema { "));
+        assert!(debug.ends_with(&format!("data: [{}] }}", row)));
+        assert!(!debug.contains("indexes"));
+    }
+
+    #[test]
+    fn id_column() {
+        let schema = schema(0);
+        assert!(schema.id_column().unwrap().is_id());
+        assert_eq!(schema.data_columns().len(), 3);
+        assert_eq!(schema.data_columns()[0].name, "ID");
+        assert!(schema.validate().is_ok());
+
+        // Only a leading `__ID__` of the right type is the pseudo-column
+        let mut moved = ShnSchema::new();
+        moved.columns.push(ShnColumn::new_byte("A"));
+        moved.columns.push(ShnColumn::new_id());
+        assert!(moved.id_column().is_none());
+        assert_eq!(moved.data_columns().len(), 2);
+        let mut retyped = ShnSchema::new();
+        retyped.columns.push(ShnColumn::new_byte(SHN_ID_COLUMN_NAME));
+        assert!(!retyped.columns[0].is_id());
+        assert_eq!(retyped.data_columns().len(), 1);
+        for schema in &[moved, retyped, ShnSchema::new()] {
+            match schema.validate() {
+                Err(ShnError::MissingIdColumn) => {},
+                r => panic!("{:?}", r),
+            }
+        }
+    }
+
+    #[test]
+    fn row_ids() {
+        let file = file(&schema(0), &["a"]);
+        let mut row = file.data[0].clone();
+        assert_eq!(row.id(), Some(0));
+        row.set_id(513).unwrap();
+        assert_eq!(row.id(), Some(513));
+        assert_eq!(row.data[0], ShnCell::UnsignedShort(513));
+        assert_eq!(row.data[1..], file.data[0].data[1..]);
+
+        let mut schema = ShnSchema::new();
+        schema.columns.push(ShnColumn::new_unsigned_short("A"));
+        let mut row = ShnRow {
+            schema: Arc::new(schema),
+            data:   vec![ShnCell::UnsignedShort(1)],
+        };
+        assert_eq!(row.id(), None);
+        match row.set_id(2) {
+            Err(ShnError::MissingIdColumn) => {},
+            r => panic!("{:?}", r),
+        }
+        assert_eq!(row.data[0], ShnCell::UnsignedShort(1));
+    }
+
+    #[test]
+    fn append_row_numbered() {
+        let schema = schema(0);
+        let mut file = ShnFile::new([0; SHN_CRYPT_HEADER_LEN], 0, schema.clone());
+        for desc in &["a", "b", "c"] {
+            file.append_row_numbered(ShnRow {
+                schema: schema.clone(),
+                data:   vec![ShnCell::UnsignedShort(77),
+                             ShnCell::UnsignedInteger(0),
+                             ShnCell::StringFixedLen(String::new()),
+                             ShnCell::StringZeroTerminated(desc.to_string())],
+            }).unwrap();
+        }
+        let ids: Vec<_> = file.data.iter().map(|r| r.id()).collect();
+        assert_eq!(ids, vec![Some(0), Some(1), Some(2)]);
+        assert_eq!(file.data[2].data[3],
+                   ShnCell::StringZeroTerminated("c".to_owned()));
+    }
 }
@@ -0,0 +1,261 @@
+use ::std::collections::HashSet;
+use ::std::fs::{ self, File };
+use ::std::io::BufReader;
+use ::std::path::Path;
+
+use ::encoding::types::EncodingRef;
+
+use super::shn::{ ShnDataType, ShnSchema, ShnError, Result };
+use super::shn_database::table_name;
+use super::shn_reader::ShnReader;
+
+/// Words which can not be used as field names.
+const KEYWORDS: &'static [&'static str] = &[
+    "abstract", "as", "async", "await", "become", "box", "break", "const",
+    "continue", "crate", "do", "dyn", "else", "enum", "extern", "false",
+    "final", "fn", "for", "if", "impl", "in", "let", "loop", "macro", "match",
+    "mod", "move", "mut", "override", "priv", "pub", "ref", "return", "self",
+    "static", "struct", "super", "trait", "true", "try", "type", "typeof",
+    "union", "unsafe", "unsized", "use", "virtual", "where", "while", "yield",
+];
+
+/// Returns the Rust type of fields holding cells of `data_type`.
+fn field_type(data_type: &ShnDataType) -> &'static str {
+    match *data_type {
+        ShnDataType::StringFixedLen |
+        ShnDataType::StringZeroTerminated   => "String",
+        ShnDataType::Byte                   => "u8",
+        ShnDataType::SignedByte             => "i8",
+        ShnDataType::SignedShort            => "i16",
+        ShnDataType::UnsignedShort          => "u16",
+        ShnDataType::SignedInteger          => "i32",
+        ShnDataType::UnsignedInteger        => "u32",
+        ShnDataType::SingleFloatingPoint    => "f32",
+    }
+}
+
+/// Converts a column name like `MaxHP` or `IsWMLink` into a field name like
+/// `max_hp` or `is_wm_link`.
+fn field_name(column: &str) -> String {
+    let chars: Vec<char> = column.chars().collect();
+    let mut name = String::new();
+    for (i, &c) in chars.iter().enumerate() {
+        if !c.is_ascii_alphanumeric() {
+            name.push('_');
+            continue;
+        }
+        if c.is_ascii_uppercase() && i > 0 {
+            let previous = chars[i - 1];
+            let next_lower = chars.get(i + 1).map_or(false, |n| n.is_ascii_lowercase());
+            if previous.is_ascii_lowercase() || previous.is_ascii_digit()
+                || (previous.is_ascii_uppercase() && next_lower) {
+                name.push('_');
+            }
+        }
+        name.push(c.to_ascii_lowercase());
+    }
+    let mut name = name.split('_')
+        .filter(|p| !p.is_empty())
+        .collect::<Vec<_>>()
+        .join("_");
+    if name.is_empty() {
+        name.push_str("column");
+    }
+    if name.starts_with(|c: char| c.is_ascii_digit()) {
+        name.insert(0, '_');
+    } else if KEYWORDS.contains(&&name[..]) {
+        name.push('_');
+    }
+    name
+}
+
+/// Converts a table name into a type name, keeping its case. Names which
+/// are not valid, like `Self` for a table named `self`, are prefixed with
+/// `Table`.
+fn type_name(table: &str) -> String {
+    let mut name = String::new();
+    let mut upper = true;
+    for c in table.chars() {
+        if c.is_ascii_alphanumeric() {
+            name.push(if upper { c.to_ascii_uppercase() } else { c });
+            upper = false;
+        } else {
+            upper = true;
+        }
+    }
+    if !name.starts_with(|c: char| c.is_ascii_alphabetic()) || name == "Self" {
+        name = format!("Table{}", name);
+    }
+    name
+}
+
+impl ShnSchema {
+    /// Returns Rust source defining a record of the table `table` with this
+    /// schema through `shn_record!`, which needs to be in scope wherever the
+    /// source is included. The struct is named after the table, its fields
+    /// after the columns in snake case, like `max_hp` for `MaxHP`.
+    pub fn to_record_source(&self, table: &str) -> String {
+        let mut source = format!(
+            "// Generated from the schema of `{}` (fingerprint {:016x}).\n\
+             // Regenerate it instead of editing it.\n\n\
+             shn_record! {{\n    /// A row of `{}`\n    pub struct {} = {:?} {{\n",
+            table, self.fingerprint(), table, type_name(table), table);
+        let mut names = HashSet::new();
+        for c in self.data_columns() {
+            let base = field_name(&c.name);
+            let mut name = base.clone();
+            let mut n = 1;
+            while !names.insert(name.clone()) {
+                n += 1;
+                name = format!("{}_{}", base, n);
+            }
+            source.push_str(&format!("        {}: {} = {:?}, {:?}({}),\n",
+                                     name, field_type(&c.data_type), c.name,
+                                     c.data_type, c.data_length));
+        }
+        source.push_str("    }\n}\n");
+        source
+    }
+}
+
+/// Generates a record for the table stored at `source`, named after the
+/// file, and writes it to `dest` unless it is up to date already. Only the
+/// schema is read, using `encoding` for the column names. Meant for build
+/// scripts:
+///
+/// ```no_run
+/// extern crate shn;
+/// extern crate encoding;
+///
+/// use encoding::EncodingRef;
+///
+/// fn main() {
+///     let dest = std::env::var("OUT_DIR").unwrap() + "/item_info.rs";
+///     let encoding: EncodingRef = encoding::all::ASCII;
+///     shn::generate_record_file("data/ItemInfo.shn", &encoding, &dest)
+///         .unwrap();
+///     println!("cargo:rerun-if-changed=data/ItemInfo.shn");
+/// }
+/// ```
+///
+/// The crate then includes it with
+/// `include!(concat!(env!("OUT_DIR"), "/item_info.rs"));`.
+pub fn generate_record_file<P, Q>(source: P, encoding: &EncodingRef, dest: Q)
+                                  -> Result<()>
+    where P: AsRef<Path>, Q: AsRef<Path> {
+    let source = source.as_ref();
+    let file = try!(File::open(source).map_err(ShnError::Io));
+    let header = try!(ShnReader::read_header(&mut BufReader::new(file), encoding));
+    let code = header.schema.to_record_source(&table_name(source));
+    // Keeps the modification time, so dependent crates are not rebuilt
+    if fs::read_to_string(dest.as_ref()).ok().as_ref() == Some(&code) {
+        return Ok(());
+    }
+    fs::write(dest, code).map_err(ShnError::Io)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::shn::ShnColumn;
+
+    /// A schema with column names which are no valid field names as they
+    /// are.
+    fn awkward_schema() -> ShnSchema {
+        let mut schema = ShnSchema::with_id_column();
+        schema.columns.push(ShnColumn::new_unsigned_short("ID"));
+        schema.columns.push(ShnColumn::new_string_fixed_len("InxName", 32));
+        schema.columns.push(ShnColumn::new_unsigned_integer("type"));
+        schema.columns.push(ShnColumn::new_byte("Self"));
+        schema.columns.push(ShnColumn::new_signed_short("2ndClass"));
+        schema.columns.push(ShnColumn::new_unsigned_integer("MaxHP"));
+        schema.columns.push(ShnColumn::new_unsigned_integer("max_hp"));
+        schema.columns.push(ShnColumn::new_unsigned_integer("Max HP"));
+        schema.columns.push(ShnColumn::new_signed_byte("IsWMLink"));
+        schema.columns.push(ShnColumn::new_signed_integer("--"));
+        schema.columns.push(ShnColumn::new_single_floating_point("Rate \"%\""));
+        schema.columns.push(ShnColumn::new_string_terminated("Desc"));
+        schema
+    }
+
+    // Generated by `to_record_source`, which `generated_source` checks
+    include!("testdata/awkward_record.rs");
+
+    #[test]
+    fn names() {
+        assert_eq!(field_name("MaxHP"), "max_hp");
+        assert_eq!(field_name("IsWMLink"), "is_wm_link");
+        assert_eq!(field_name("ItemID2Level"), "item_id2_level");
+        assert_eq!(field_name("__Odd  Name__"), "odd_name");
+        assert_eq!(field_name("Type"), "type_");
+        assert_eq!(field_name("3"), "_3");
+        assert_eq!(field_name("???"), "column");
+        assert_eq!(type_name("ItemInfo"), "ItemInfo");
+        assert_eq!(type_name("item_info server"), "ItemInfoServer");
+        assert_eq!(type_name("2nd"), "Table2nd");
+        assert_eq!(type_name(""), "Table");
+        assert_eq!(type_name("self"), "TableSelf");
+        assert_eq!(type_name("Self"), "TableSelf");
+    }
+
+    #[test]
+    fn generated_source() {
+        assert_eq!(awkward_schema().to_record_source("2nd-Table"),
+                   include_str!("testdata/awkward_record.rs"));
+    }
+
+    #[test]
+    fn generated_record_round_trip() {
+        use ::std::sync::Arc;
+        use super::super::shn::ShnCell;
+        use super::super::shn_record::ShnRecord;
+
+        let schema = Arc::new(awkward_schema());
+        assert!(Table2ndTable::check_schema(&schema).is_ok());
+        assert_eq!(Table2ndTable::table_name(), "2nd-Table");
+        let record = Table2ndTable {
+            id:         7,
+            inx_name:   "Name".to_owned(),
+            type_:      70_000,
+            self_:      1,
+            _2nd_class: -2,
+            max_hp:     10,
+            max_hp_2:   20,
+            max_hp_3:   30,
+            is_wm_link: -1,
+            column:     -100_000,
+            rate:       0.5,
+            desc:       "text".to_owned(),
+        };
+        let row = record.to_row(&schema, 3).unwrap();
+        assert_eq!(row.data[0], ShnCell::UnsignedShort(3));
+        assert_eq!(row.data[5], ShnCell::SignedShort(-2));
+        assert_eq!(Table2ndTable::from_row(&row).unwrap(), record);
+    }
+
+    #[test]
+    fn generate_file() {
+        use ::std::{ env, process };
+        use ::encoding::all::ASCII;
+        use ::std::sync::Arc;
+        use super::super::shn::{ SHN_CRYPT_HEADER_LEN, ShnFile };
+        use super::super::shn_writer::ShnWriter;
+
+        let dir = env::temp_dir().join(format!("shn-codegen-{}", process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let source = dir.join("2nd-Table.shn");
+        let dest = dir.join("record.rs");
+        let encoding: EncodingRef = ASCII;
+        let file = ShnFile::new([0; SHN_CRYPT_HEADER_LEN], 0,
+                                Arc::new(awkward_schema()));
+        ShnWriter::write_to(&file, &encoding, &mut File::create(&source).unwrap())
+            .unwrap();
+        generate_record_file(&source, &encoding, &dest).unwrap();
+        assert_eq!(fs::read_to_string(&dest).unwrap(),
+                   include_str!("testdata/awkward_record.rs"));
+        assert!(generate_record_file(dir.join("missing.shn"), &encoding, &dest)
+                .is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
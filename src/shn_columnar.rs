@@ -0,0 +1,443 @@
+use super::shn::{
+    SHN_CRYPT_HEADER_LEN,
+    Result,
+    ShnSchema,
+    ShnFile,
+    ShnRow,
+    ShnCell,
+    ShnDataType,
+    ShnError,
+};
+
+use ::std::slice;
+use ::std::sync::Arc;
+
+/// The cells of a single column, stored as a vector of their native type.
+#[derive(Clone, PartialEq, Debug)]
+pub enum ShnColumnData {
+    /// The cells of a `StringFixedLen` column
+    StringFixedLen(Vec<String>),
+    /// The cells of a `StringZeroTerminated` column
+    StringZeroTerminated(Vec<String>),
+    /// The cells of a `Byte` column
+    Byte(Vec<u8>),
+    /// The cells of a `SignedByte` column
+    SignedByte(Vec<i8>),
+    /// The cells of a `SignedShort` column
+    SignedShort(Vec<i16>),
+    /// The cells of a `UnsignedShort` column
+    UnsignedShort(Vec<u16>),
+    /// The cells of a `SignedInteger` column
+    SignedInteger(Vec<i32>),
+    /// The cells of a `UnsignedInteger` column
+    UnsignedInteger(Vec<u32>),
+    /// The cells of a `SingleFloatingPoint` column
+    SingleFloatingPoint(Vec<f32>),
+}
+
+impl ShnColumnData {
+    /// Constructs an empty column of the given type.
+    pub fn new(data_type: &ShnDataType) -> Self {
+        match *data_type {
+            ShnDataType::StringFixedLen
+                => ShnColumnData::StringFixedLen(Vec::new()),
+            ShnDataType::StringZeroTerminated
+                => ShnColumnData::StringZeroTerminated(Vec::new()),
+            ShnDataType::Byte           => ShnColumnData::Byte(Vec::new()),
+            ShnDataType::SignedByte     => ShnColumnData::SignedByte(Vec::new()),
+            ShnDataType::SignedShort    => ShnColumnData::SignedShort(Vec::new()),
+            ShnDataType::UnsignedShort
+                => ShnColumnData::UnsignedShort(Vec::new()),
+            ShnDataType::SignedInteger
+                => ShnColumnData::SignedInteger(Vec::new()),
+            ShnDataType::UnsignedInteger
+                => ShnColumnData::UnsignedInteger(Vec::new()),
+            ShnDataType::SingleFloatingPoint
+                => ShnColumnData::SingleFloatingPoint(Vec::new()),
+        }
+    }
+
+    /// Returns the matching `ShnDataType`
+    pub fn data_type(&self) -> ShnDataType {
+        match *self {
+            ShnColumnData::StringFixedLen(_)
+                => ShnDataType::StringFixedLen,
+            ShnColumnData::StringZeroTerminated(_)
+                => ShnDataType::StringZeroTerminated,
+            ShnColumnData::Byte(_)              => ShnDataType::Byte,
+            ShnColumnData::SignedByte(_)        => ShnDataType::SignedByte,
+            ShnColumnData::SignedShort(_)       => ShnDataType::SignedShort,
+            ShnColumnData::UnsignedShort(_)     => ShnDataType::UnsignedShort,
+            ShnColumnData::SignedInteger(_)     => ShnDataType::SignedInteger,
+            ShnColumnData::UnsignedInteger(_)   => ShnDataType::UnsignedInteger,
+            ShnColumnData::SingleFloatingPoint(_)
+                => ShnDataType::SingleFloatingPoint,
+        }
+    }
+
+    /// Returns the number of cells in the column.
+    pub fn len(&self) -> usize {
+        match *self {
+            ShnColumnData::StringFixedLen(ref v) |
+            ShnColumnData::StringZeroTerminated(ref v)  => v.len(),
+            ShnColumnData::Byte(ref v)                  => v.len(),
+            ShnColumnData::SignedByte(ref v)            => v.len(),
+            ShnColumnData::SignedShort(ref v)           => v.len(),
+            ShnColumnData::UnsignedShort(ref v)         => v.len(),
+            ShnColumnData::SignedInteger(ref v)         => v.len(),
+            ShnColumnData::UnsignedInteger(ref v)       => v.len(),
+            ShnColumnData::SingleFloatingPoint(ref v)   => v.len(),
+        }
+    }
+
+    /// Returns whether the column contains no cells.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the cell at `index` as a `ShnCell`.
+    pub fn get(&self, index: usize) -> Option<ShnCell> {
+        match *self {
+            ShnColumnData::StringFixedLen(ref v)
+                => v.get(index).map(|c| ShnCell::StringFixedLen(c.clone())),
+            ShnColumnData::StringZeroTerminated(ref v)
+                => v.get(index).map(|c| ShnCell::StringZeroTerminated(c.clone())),
+            ShnColumnData::Byte(ref v)
+                => v.get(index).map(|c| ShnCell::Byte(*c)),
+            ShnColumnData::SignedByte(ref v)
+                => v.get(index).map(|c| ShnCell::SignedByte(*c)),
+            ShnColumnData::SignedShort(ref v)
+                => v.get(index).map(|c| ShnCell::SignedShort(*c)),
+            ShnColumnData::UnsignedShort(ref v)
+                => v.get(index).map(|c| ShnCell::UnsignedShort(*c)),
+            ShnColumnData::SignedInteger(ref v)
+                => v.get(index).map(|c| ShnCell::SignedInteger(*c)),
+            ShnColumnData::UnsignedInteger(ref v)
+                => v.get(index).map(|c| ShnCell::UnsignedInteger(*c)),
+            ShnColumnData::SingleFloatingPoint(ref v)
+                => v.get(index).map(|c| ShnCell::SingleFloatingPoint(*c)),
+        }
+    }
+
+    /// Appends a cell, which needs to match the type of the column.
+    pub fn push(&mut self, cell: ShnCell) -> Result<()> {
+        match (self, cell) {
+            (&mut ShnColumnData::StringFixedLen(ref mut v),
+             ShnCell::StringFixedLen(c))        => v.push(c),
+            (&mut ShnColumnData::StringZeroTerminated(ref mut v),
+             ShnCell::StringZeroTerminated(c))  => v.push(c),
+            (&mut ShnColumnData::Byte(ref mut v),
+             ShnCell::Byte(c))                  => v.push(c),
+            (&mut ShnColumnData::SignedByte(ref mut v),
+             ShnCell::SignedByte(c))            => v.push(c),
+            (&mut ShnColumnData::SignedShort(ref mut v),
+             ShnCell::SignedShort(c))           => v.push(c),
+            (&mut ShnColumnData::UnsignedShort(ref mut v),
+             ShnCell::UnsignedShort(c))         => v.push(c),
+            (&mut ShnColumnData::SignedInteger(ref mut v),
+             ShnCell::SignedInteger(c))         => v.push(c),
+            (&mut ShnColumnData::UnsignedInteger(ref mut v),
+             ShnCell::UnsignedInteger(c))       => v.push(c),
+            (&mut ShnColumnData::SingleFloatingPoint(ref mut v),
+             ShnCell::SingleFloatingPoint(c))   => v.push(c),
+            _ => return Err(ShnError::InvalidSchema),
+        }
+        Ok(())
+    }
+
+    /// Returns the cells as `f64`s, or `None` for string columns.
+    fn numbers<'a>(&'a self) -> Option<Box<Iterator<Item = f64> + 'a>> {
+        match *self {
+            ShnColumnData::StringFixedLen(_) |
+            ShnColumnData::StringZeroTerminated(_) => None,
+            ShnColumnData::Byte(ref v)
+                => Some(Box::new(v.iter().map(|c| *c as f64))),
+            ShnColumnData::SignedByte(ref v)
+                => Some(Box::new(v.iter().map(|c| *c as f64))),
+            ShnColumnData::SignedShort(ref v)
+                => Some(Box::new(v.iter().map(|c| *c as f64))),
+            ShnColumnData::UnsignedShort(ref v)
+                => Some(Box::new(v.iter().map(|c| *c as f64))),
+            ShnColumnData::SignedInteger(ref v)
+                => Some(Box::new(v.iter().map(|c| *c as f64))),
+            ShnColumnData::UnsignedInteger(ref v)
+                => Some(Box::new(v.iter().map(|c| *c as f64))),
+            ShnColumnData::SingleFloatingPoint(ref v)
+                => Some(Box::new(v.iter()
+                                 .filter(|c| !c.is_nan())
+                                 .map(|c| *c as f64))),
+        }
+    }
+
+    /// Returns the smallest value of a numeric column. `None` for string
+    /// columns and empty columns. NaNs are ignored.
+    pub fn min(&self) -> Option<f64> {
+        self.numbers().and_then(|n| n.fold(None, |a: Option<f64>, b| {
+            Some(a.map_or(b, |a| a.min(b)))
+        }))
+    }
+
+    /// Returns the largest value of a numeric column. `None` for string
+    /// columns and empty columns. NaNs are ignored.
+    pub fn max(&self) -> Option<f64> {
+        self.numbers().and_then(|n| n.fold(None, |a: Option<f64>, b| {
+            Some(a.map_or(b, |a| a.max(b)))
+        }))
+    }
+
+    /// Returns the sum of all values of a numeric column, or `None` for
+    /// string columns. NaNs are ignored.
+    pub fn sum(&self) -> Option<f64> {
+        self.numbers().map(|n| n.fold(0.0, |a, b| a + b))
+    }
+}
+
+/// Types the cells of a column can be accessed as, see
+/// `ShnColumnar::values`.
+pub trait ShnColumnType: Sized {
+    /// Returns the cells of `data` if they are of type `Self`.
+    fn values(data: &ShnColumnData) -> Option<&[Self]>;
+}
+
+macro_rules! impl_column_type {
+    ($t:ty, $($variant:ident),+) => {
+        impl ShnColumnType for $t {
+            fn values(data: &ShnColumnData) -> Option<&[$t]> {
+                match *data {
+                    $(ShnColumnData::$variant(ref v) => Some(&v[..]),)+
+                    _ => None,
+                }
+            }
+        }
+    }
+}
+
+impl_column_type!(String, StringFixedLen, StringZeroTerminated);
+impl_column_type!(u8, Byte);
+impl_column_type!(i8, SignedByte);
+impl_column_type!(i16, SignedShort);
+impl_column_type!(u16, UnsignedShort);
+impl_column_type!(i32, SignedInteger);
+impl_column_type!(u32, UnsignedInteger);
+impl_column_type!(f32, SingleFloatingPoint);
+
+/// A columnar representation of a `SHN` file, storing each column as a
+/// vector of its native type. Useful to process a column across all rows.
+#[derive(Clone, Debug)]
+pub struct ShnColumnar {
+    /// The cryptographic header
+    pub crypt_header:   [u8; SHN_CRYPT_HEADER_LEN],
+    /// The header (unknown purpose)
+    pub header:         u32,
+    schema:             Arc<ShnSchema>,
+    columns:            Vec<ShnColumnData>,
+    len:                usize,
+}
+
+impl ShnColumnar {
+    /// Constructs a new, empty `ShnColumnar`.
+    pub fn new(crypt_header: [u8; SHN_CRYPT_HEADER_LEN],
+               header: u32,
+               schema: Arc<ShnSchema>)
+               -> Self {
+        let columns = schema.columns.iter()
+            .map(|c| ShnColumnData::new(&c.data_type))
+            .collect();
+        ShnColumnar {
+            crypt_header:   crypt_header,
+            header:         header,
+            schema:         schema,
+            columns:        columns,
+            len:            0,
+        }
+    }
+
+    /// Constructs a `ShnColumnar` holding the same data as `file`.
+    pub fn from_file(file: &ShnFile) -> Result<Self> {
+        let mut columnar = ShnColumnar::new(file.crypt_header,
+                                            file.header,
+                                            file.schema.clone());
+        for row in &file.data {
+            try!(columnar.push_row(row));
+        }
+        Ok(columnar)
+    }
+
+    /// Constructs a `ShnFile` holding the same data.
+    pub fn to_file(&self) -> ShnFile {
+        let data = (0..self.len).map(|i| ShnRow {
+            schema: self.schema.clone(),
+            data:   self.columns.iter()
+                .map(|c| c.get(i).unwrap())
+                .collect(),
+        }).collect();
+        let mut file = ShnFile::new(self.crypt_header,
+                                    self.header,
+                                    self.schema.clone());
+        file.data = data;
+        file
+    }
+
+    /// Returns the schema defining the data.
+    pub fn schema(&self) -> &Arc<ShnSchema> {
+        &self.schema
+    }
+
+    /// Returns the number of rows.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns whether there are no rows.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Appends a row (and checks it to be conform to the schema)
+    pub fn push_row(&mut self, row: &ShnRow) -> Result<()> {
+        if row.data.len() != self.columns.len()
+            || row.data.iter().zip(self.columns.iter())
+                   .any(|(cell, c)| cell.data_type() != c.data_type()) {
+            return Err(ShnError::InvalidSchema);
+        }
+        for (cell, column) in row.data.iter().zip(self.columns.iter_mut()) {
+            try!(column.push(cell.clone()));
+        }
+        self.len += 1;
+        Ok(())
+    }
+
+    /// Returns the column at `index`.
+    pub fn column(&self, index: usize) -> Option<&ShnColumnData> {
+        self.columns.get(index)
+    }
+
+    /// Returns the column named `name`.
+    pub fn column_by_name(&self, name: &str) -> Option<&ShnColumnData> {
+        self.schema.columns.iter()
+            .position(|c| c.name == name)
+            .and_then(|i| self.column(i))
+    }
+
+    /// Returns an iterator over the values of the column named `name`,
+    /// which needs to be stored as `T`.
+    pub fn values<'a, T>(&'a self, name: &str) -> Result<slice::Iter<'a, T>>
+        where T: ShnColumnType {
+        match self.column_by_name(name) {
+            Some(data) => T::values(data)
+                .map(|v| v.iter())
+                .ok_or(ShnError::InvalidSchema),
+            None => Err(ShnError::UnknownColumn(name.to_owned())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::shn::ShnColumn;
+
+    fn file() -> ShnFile {
+        let mut schema = ShnSchema::with_id_column();
+        schema.columns.push(ShnColumn::new_string_fixed_len("Name", 16));
+        schema.columns.push(ShnColumn::new_signed_integer("Delta"));
+        schema.columns.push(ShnColumn::new_single_floating_point("Rate"));
+        let schema = Arc::new(schema);
+        let mut file = ShnFile::new([7; SHN_CRYPT_HEADER_LEN], 3, schema.clone());
+        let rows = vec![("Sword", -5, 0.5), ("Axe", 10, 2.0), ("Bow", 2, 1.5)];
+        for (i, (name, delta, rate)) in rows.into_iter().enumerate() {
+            file.data.push(ShnRow {
+                schema: schema.clone(),
+                data:   vec![ShnCell::UnsignedShort(i as u16),
+                             ShnCell::StringFixedLen(name.to_owned()),
+                             ShnCell::SignedInteger(delta),
+                             ShnCell::SingleFloatingPoint(rate)],
+            });
+        }
+        file
+    }
+
+    #[test]
+    fn file_round_trip() {
+        let file = file();
+        let columnar = ShnColumnar::from_file(&file).unwrap();
+        assert_eq!(columnar.len(), 3);
+        assert!(!columnar.is_empty());
+        assert_eq!(columnar.column(2),
+                   Some(&ShnColumnData::SignedInteger(vec![-5, 10, 2])));
+        assert_eq!(columnar.column(4), None);
+
+        let back = columnar.to_file();
+        assert_eq!((back.crypt_header, back.header), (file.crypt_header, file.header));
+        assert_eq!(back.schema, file.schema);
+        for (a, b) in back.data.iter().zip(file.data.iter()) {
+            assert_eq!(a.schema, b.schema);
+            assert_eq!(a.data, b.data);
+        }
+        assert_eq!(back.data.len(), file.data.len());
+
+        let empty = ShnColumnar::new(file.crypt_header, file.header,
+                                     file.schema.clone());
+        assert!(empty.is_empty());
+        assert!(empty.to_file().data.is_empty());
+    }
+
+    #[test]
+    fn values() {
+        let columnar = ShnColumnar::from_file(&file()).unwrap();
+        let names: Vec<&String> = columnar.values("Name").unwrap().collect();
+        assert_eq!(names, vec!["Sword", "Axe", "Bow"]);
+        assert_eq!(columnar.values::<i32>("Delta").unwrap().sum::<i32>(), 7);
+        match columnar.values::<u32>("Delta") {
+            Err(ShnError::InvalidSchema) => {},
+            r => panic!("{:?}", r.map(|v| v.collect::<Vec<_>>())),
+        }
+        match columnar.values::<i32>("Nope") {
+            Err(ShnError::UnknownColumn(ref c)) => assert_eq!(c, "Nope"),
+            r => panic!("{:?}", r.map(|v| v.collect::<Vec<_>>())),
+        }
+    }
+
+    #[test]
+    fn aggregates() {
+        let columnar = ShnColumnar::from_file(&file()).unwrap();
+        let delta = columnar.column_by_name("Delta").unwrap();
+        assert_eq!((delta.min(), delta.max(), delta.sum()),
+                   (Some(-5.0), Some(10.0), Some(7.0)));
+        let name = columnar.column_by_name("Name").unwrap();
+        assert_eq!((name.min(), name.max(), name.sum()), (None, None, None));
+        let empty = ShnColumnData::new(&ShnDataType::Byte);
+        assert_eq!((empty.min(), empty.max(), empty.sum()), (None, None, Some(0.0)));
+
+        // NaNs are ignored
+        let rate = ShnColumnData::SingleFloatingPoint(vec![0.5, ::std::f32::NAN, 1.5]);
+        assert_eq!((rate.min(), rate.max(), rate.sum()),
+                   (Some(0.5), Some(1.5), Some(2.0)));
+    }
+
+    #[test]
+    fn push_errors() {
+        let file = file();
+        let mut columnar = ShnColumnar::from_file(&file).unwrap();
+        let mut row = ShnRow {
+            schema: file.schema.clone(),
+            data:   file.data[0].data.clone(),
+        };
+        row.data[2] = ShnCell::UnsignedInteger(1);
+        match columnar.push_row(&row) {
+            Err(ShnError::InvalidSchema) => {},
+            r => panic!("{:?}", r),
+        }
+        row.data.pop();
+        assert!(columnar.push_row(&row).is_err());
+        assert_eq!(columnar.len(), 3);
+        assert_eq!(columnar.column(0).unwrap().len(), 3);
+
+        let mut column = ShnColumnData::new(&ShnDataType::Byte);
+        assert!(column.is_empty());
+        assert!(column.push(ShnCell::SignedByte(1)).is_err());
+        column.push(ShnCell::Byte(1)).unwrap();
+        assert_eq!((column.data_type(), column.get(0), column.get(1)),
+                   (ShnDataType::Byte, Some(ShnCell::Byte(1)), None));
+    }
+}
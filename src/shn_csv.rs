@@ -0,0 +1,297 @@
+use ::std::io::{ self, Write };
+
+use super::shn::{ ShnCell, ShnRow, ShnFile, ShnError, Result };
+use super::shn_metadata::ShnTableMetadata;
+
+/// Writes a single CSV record, quoting fields where necessary.
+pub fn write_record<W, I, S>(writer: &mut W, fields: I) -> io::Result<()>
+    where W: Write, I: IntoIterator<Item = S>, S: AsRef<str> {
+    let mut first = true;
+    for field in fields {
+        if !first {
+            try!(writer.write_all(b","));
+        }
+        first = false;
+        let field = field.as_ref();
+        if field.contains(|c| c == ',' || c == '"' || c == '\n' || c == '\r') {
+            try!(write!(writer, "\"{}\"", field.replace('"', "\"\"")));
+        } else {
+            try!(writer.write_all(field.as_bytes()));
+        }
+    }
+    writer.write_all(b"\r\n")
+}
+
+/// Parses CSV text into records of fields. Fields may be quoted, with quotes
+/// inside doubled, and records end with LF or CRLF. Returns each record
+/// along with the line it starts on, or the line of an unterminated quoted
+/// field.
+pub fn parse_records(text: &str)
+                     -> ::std::result::Result<Vec<(usize, Vec<String>)>, usize> {
+    let mut records = Vec::new();
+    let mut chars = text.chars().peekable();
+    let mut line = 1;
+    while chars.peek().is_some() {
+        let start = line;
+        let mut fields = Vec::new();
+        let mut field = String::new();
+        loop {
+            match chars.next() {
+                Some('"') if field.is_empty() => {
+                    loop {
+                        match chars.next() {
+                            Some('"') if chars.peek() == Some(&'"') => {
+                                chars.next();
+                                field.push('"');
+                            },
+                            Some('"')   => break,
+                            Some(c)     => {
+                                if c == '\n' {
+                                    line += 1;
+                                }
+                                field.push(c);
+                            },
+                            None        => return Err(start),
+                        }
+                    }
+                },
+                Some(',')   => fields.push(::std::mem::replace(&mut field,
+                                                               String::new())),
+                Some('\r') if chars.peek() == Some(&'\n') => {},
+                Some('\n')  => {
+                    line += 1;
+                    break;
+                },
+                Some(c)     => field.push(c),
+                None        => break,
+            }
+        }
+        fields.push(field);
+        records.push((start, fields));
+    }
+    Ok(records)
+}
+
+impl ShnFile {
+    /// Appends the rows of CSV text, whose first record names the columns.
+    /// Columns may be given in any order, and missing ones are filled with
+    /// zero or empty strings. Rows are numbered after their position unless
+    /// the `__ID__` pseudo-column is given. Values may be labels defined by
+    /// `metadata`, see `ShnColumnMetadata::parse_value`. Values which can
+    /// not be parsed or do not fit into their column fail with
+    /// `ShnError::InvalidCsv` naming their line and column.
+    ///
+    /// Either all rows are appended, or none are and the error lists every
+    /// failed row. Returns the number of appended rows.
+    pub fn append_csv(&mut self, text: &str, metadata: Option<&ShnTableMetadata>)
+                      -> Result<usize> {
+        let mut records = try!(parse_records(text).map_err(|line| {
+            ShnError::InvalidCsv(line, "unterminated quoted field".to_owned())
+        })).into_iter();
+        let header = match records.next() {
+            Some((_, header))   => header,
+            None                => return Ok(0),
+        };
+        let mut positions = Vec::with_capacity(header.len());
+        for name in &header {
+            match self.schema.columns.iter().position(|c| c.name == *name) {
+                Some(p) if positions.contains(&p)
+                    => return Err(ShnError::DuplicateColumn(name.clone())),
+                Some(p) => positions.push(p),
+                None    => return Err(ShnError::UnknownColumn(name.clone())),
+            }
+        }
+
+        let mut rows = Vec::new();
+        let mut errors = Vec::new();
+        for (i, (line, fields)) in records.enumerate() {
+            if fields.len() != positions.len() {
+                errors.push((i, ShnError::InvalidCsv(line, format!(
+                    "expected {} fields, found {}", positions.len(),
+                    fields.len()))));
+                continue;
+            }
+            let mut cells: Vec<ShnCell> = self.schema.columns.iter()
+                .map(|c| c.data_type.default_cell())
+                .collect();
+            if self.schema.id_column().is_some() {
+                let id = self.data.len() + i;
+                if id > u16::max_value() as usize {
+                    errors.push((i, ShnError::InvalidSchema));
+                    continue;
+                }
+                cells[0] = ShnCell::UnsignedShort(id as u16);
+            }
+            let parsed: Result<()> = fields.iter().zip(&positions)
+                .map(|(field, &p)| {
+                    let column = &self.schema.columns[p];
+                    let cell = match metadata.and_then(|m| m.column(&column.name)) {
+                        Some(m) => m.parse_value(field, &column.data_type),
+                        None    => ShnCell::parse(field, &column.data_type),
+                    };
+                    cell.and_then(|cell| {
+                        try!(column.check_length(&cell));
+                        cells[p] = cell;
+                        Ok(())
+                    }).map_err(|e| ShnError::InvalidCsv(line, format!(
+                        "{} in column `{}`", e, column.name)))
+                })
+                .collect();
+            match parsed {
+                Ok(())  => rows.push(ShnRow {
+                    schema: self.schema.clone(),
+                    data:   cells,
+                }),
+                Err(e)  => errors.push((i, e)),
+            }
+        }
+        if !errors.is_empty() {
+            return Err(ShnError::RowErrors(errors));
+        }
+
+        let count = rows.len();
+        let len = self.data.len();
+        for row in rows {
+            if let Err(e) = self.append_row(row) {
+                // Rebuilds the indexes, keeping ones which were outdated
+                // before as such
+                self.truncate(len);
+                return Err(e);
+            }
+        }
+        Ok(count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::shn::ShnColumn;
+    use super::super::shn_metadata::ShnColumnMetadata;
+    use super::super::shn_test_util::table;
+
+    fn file() -> ShnFile {
+        table(vec![ShnColumn::new_unsigned_integer("ID"),
+                   ShnColumn::new_string_fixed_len("Name", 8),
+                   ShnColumn::new_byte("Level")],
+              vec![vec![ShnCell::UnsignedInteger(1),
+                        ShnCell::StringFixedLen("Sword".to_owned()),
+                        ShnCell::Byte(10)]])
+    }
+
+    fn record(fields: &[&str]) -> String {
+        let mut out = Vec::new();
+        write_record(&mut out, fields).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    fn row_errors(result: Result<usize>) -> Vec<(usize, String)> {
+        match result {
+            Err(ShnError::RowErrors(errors)) => {
+                errors.into_iter().map(|(i, e)| (i, e.to_string())).collect()
+            },
+            r => panic!("{:?}", r),
+        }
+    }
+
+    #[test]
+    fn write_records() {
+        assert_eq!(record(&["a", "", "b c"]), "a,,b c\r\n");
+        assert_eq!(record(&["a,b", "say \"hi\"", "x\ny", "\r"]),
+                   "\"a,b\",\"say \"\"hi\"\"\",\"x\ny\",\"\r\"\r\n");
+        let text = record(&["a,b", "say \"hi\"", "x\ny"]) + &record(&["", "z"]);
+        assert_eq!(parse_records(&text).unwrap(), vec![
+            (1, vec!["a,b".to_owned(), "say \"hi\"".to_owned(), "x\ny".to_owned()]),
+            (3, vec![String::new(), "z".to_owned()]),
+        ]);
+    }
+
+    #[test]
+    fn parse() {
+        assert_eq!(parse_records("").unwrap(), vec![]);
+        assert_eq!(parse_records("a,b\nc").unwrap(), vec![
+            (1, vec!["a".to_owned(), "b".to_owned()]),
+            (2, vec!["c".to_owned()]),
+        ]);
+        assert_eq!(parse_records("\n\"\"\"\",x\"y\"\r\n").unwrap(), vec![
+            (1, vec![String::new()]),
+            (2, vec!["\"".to_owned(), "x\"y\"".to_owned()]),
+        ]);
+        assert_eq!(parse_records("a\n\"b\n\nc"), Err(2));
+    }
+
+    #[test]
+    fn append() {
+        let mut file = file();
+        let text = "Level,ID,Name\r\n20,2,Axe\r\n30,3,\"Bow, lg\"\r\n";
+        assert_eq!(file.append_csv(text, None).unwrap(), 2);
+        assert_eq!(file.data[2].data,
+                   vec![ShnCell::UnsignedShort(2), ShnCell::UnsignedInteger(3),
+                        ShnCell::StringFixedLen("Bow, lg".to_owned()),
+                        ShnCell::Byte(30)]);
+
+        // Missing columns are zero, the __ID__ pseudo-column may be given
+        assert_eq!(file.append_csv("__ID__,ID\n9,4\n", None).unwrap(), 1);
+        assert_eq!(file.data[3].data,
+                   vec![ShnCell::UnsignedShort(9), ShnCell::UnsignedInteger(4),
+                        ShnCell::StringFixedLen(String::new()), ShnCell::Byte(0)]);
+        assert_eq!(file.append_csv("", None).unwrap(), 0);
+        assert_eq!(file.append_csv("ID,Name\n", None).unwrap(), 0);
+
+        let mut metadata = ShnTableMetadata::default();
+        let mut level = ShnColumnMetadata::default();
+        level.labels.insert(99, "Max".to_owned());
+        metadata.columns.insert("Level".to_owned(), level);
+        assert_eq!(file.append_csv("ID,Level\n5,Max\n", Some(&metadata)).unwrap(), 1);
+        assert_eq!(file.data[4].data[3], ShnCell::Byte(99));
+    }
+
+    #[test]
+    fn append_errors() {
+        let mut file = file();
+        let text = "ID,Name,Level\n\
+                    2,Axe,20\n\
+                    3,Bow\n\
+                    4,\"Long\nBow\",x\n\
+                    5,Crossbow,300\n\
+                    6,Longsword,1\n";
+        assert_eq!(row_errors(file.append_csv(text, None)), vec![
+            (1, "expected 3 fields, found 2 in line 3 of CSV".to_owned()),
+            (2, "`x` is not a valid Byte in column `Level` in line 4 of CSV".to_owned()),
+            (3, "`300` is not a valid Byte in column `Level` in line 6 of CSV"
+                .to_owned()),
+            (4, "`Longsword` does not fit into 8 bytes in column `Name` in line 7 \
+                 of CSV".to_owned()),
+        ]);
+        assert_eq!(file.data.len(), 1);
+
+        match file.append_csv("ID,Nope\n", None) {
+            Err(ShnError::UnknownColumn(ref c)) => assert_eq!(c, "Nope"),
+            r => panic!("{:?}", r),
+        }
+        match file.append_csv("ID,Name,ID\n", None) {
+            Err(ShnError::DuplicateColumn(ref c)) => assert_eq!(c, "ID"),
+            r => panic!("{:?}", r),
+        }
+        match file.append_csv("ID\n\"2\n", None) {
+            Err(ShnError::InvalidCsv(2, _)) => {},
+            r => panic!("{:?}", r),
+        }
+    }
+
+    #[test]
+    fn append_rolls_back() {
+        let mut file = file();
+        file.index_by("ID").unwrap();
+        match file.append_csv("ID\n2\n3\n1\n", None) {
+            Err(ShnError::DuplicateKey(_)) => {},
+            r => panic!("{:?}", r),
+        }
+        assert_eq!(file.data.len(), 1);
+        assert!(file.get_by_key("ID", 2u32).is_none());
+        assert!(file.outdated_indexes().is_empty());
+        assert_eq!(file.append_csv("ID\n2\n", None).unwrap(), 1);
+        assert_eq!(file.get_by_key("ID", 2u32).unwrap().data[0],
+                   ShnCell::UnsignedShort(1));
+    }
+}
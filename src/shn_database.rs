@@ -0,0 +1,553 @@
+use ::std::collections::{ HashMap, HashSet };
+use ::std::collections::hash_map::DefaultHasher;
+use ::std::fs::{ self, File };
+use ::std::hash::{ Hash, Hasher };
+use ::std::io::{ self, BufReader, BufWriter, Write };
+use ::std::path::{ Path, PathBuf };
+use ::std::sync::{ mpsc, Arc, Mutex, PoisonError };
+use ::std::thread;
+
+use ::encoding::types::EncodingRef;
+
+use super::shn::{ ShnFile, ShnError, Result };
+use super::shn_reader::ShnReader;
+use super::shn_relation::ShnRelation;
+use super::shn_writer::ShnWriter;
+
+/// Options for loading a directory into a `ShnDatabase`.
+#[derive(Clone)]
+pub struct DatabaseOptions {
+    /// The encoding of strings in all tables without an override
+    pub encoding:   EncodingRef,
+    /// Encodings overriding `encoding` for single tables, keyed by table
+    /// name
+    pub encodings:  HashMap<String, EncodingRef>,
+    /// The number of threads reading files, `0` uses one per CPU
+    pub threads:    usize,
+    /// Whether to also load files in subdirectories
+    pub recursive:  bool,
+}
+
+impl DatabaseOptions {
+    /// Constructs new `DatabaseOptions`, which read all tables of a single
+    /// directory with `encoding`.
+    pub fn new(encoding: EncodingRef) -> Self {
+        DatabaseOptions {
+            encoding:   encoding,
+            encodings:  HashMap::new(),
+            threads:    0,
+            recursive:  false,
+        }
+    }
+
+    /// Returns the encoding of the table named `name`.
+    pub fn encoding_of(&self, name: &str) -> EncodingRef {
+        self.encodings.get(name).cloned().unwrap_or(self.encoding)
+    }
+}
+
+/// A table of a `ShnDatabase` along with where it came from.
+struct Entry {
+    file:       ShnFile,
+    path:       PathBuf,
+    encoding:   EncodingRef,
+    /// The `content_hash` of the file as last loaded or saved, `None` if it
+    /// was never saved
+    saved:      Option<u64>,
+}
+
+/// A set of `ShnFile`s loaded from a directory, keyed by their file name
+/// without the extension, like `ItemInfo` for `ItemInfo.shn`.
+///
+/// Tables whose contents differ from their files, as last loaded or saved,
+/// are considered modified, and are written back to their files by
+/// `save_all`.
+pub struct ShnDatabase {
+    tables:     HashMap<String, Entry>,
+    relations:  Vec<ShnRelation>,
+}
+
+impl ShnDatabase {
+    /// Constructs an empty database.
+    pub fn new() -> Self {
+        ShnDatabase {
+            tables:     HashMap::new(),
+            relations:  Vec::new(),
+        }
+    }
+
+    /// Loads every `.shn` file in the directory at `path` in parallel.
+    /// Returns the database of all files which could be read, along with the
+    /// errors of those which could not. Only fails if the directory itself
+    /// can not be read. Files which were not read because a reading thread
+    /// panicked are returned as failed as well.
+    ///
+    /// An index is built over the key column of every table, see
+    /// `ShnFile::by_key`, unless the column holds duplicate or floating
+    /// point keys.
+    pub fn load<P: AsRef<Path>>(path: P, options: &DatabaseOptions)
+                                -> Result<(ShnDatabase, Vec<(PathBuf, ShnError)>)> {
+        let mut paths = Vec::new();
+        try!(find_files(path.as_ref(), options.recursive, &mut paths));
+
+        let threads = match options.threads {
+            0 => thread::available_parallelism().map(|n| n.get()).unwrap_or(4),
+            n => n,
+        };
+        let mut unread: HashSet<PathBuf> = paths.iter().cloned().collect();
+        let queue = Arc::new(Mutex::new(paths.into_iter()));
+        let (sender, receiver) = mpsc::channel();
+        let mut workers = Vec::with_capacity(threads);
+        for _ in 0..threads {
+            let queue = queue.clone();
+            let sender = sender.clone();
+            let options = options.clone();
+            workers.push(thread::spawn(move || {
+                loop {
+                    // The iterator stays usable if another thread panicked
+                    // while holding the lock
+                    let next = queue.lock()
+                        .unwrap_or_else(PoisonError::into_inner)
+                        .next();
+                    let path = match next {
+                        Some(path)  => path,
+                        None        => break,
+                    };
+                    let name = table_name(&path);
+                    let encoding = options.encoding_of(&name);
+                    let result = read_file(&path, encoding);
+                    if sender.send((name, path, encoding, result)).is_err() {
+                        break;
+                    }
+                }
+            }));
+        }
+        drop(sender);
+        let mut results: Vec<_> = receiver.iter().collect();
+        let mut panicked = false;
+        for worker in workers {
+            panicked |= worker.join().is_err();
+        }
+        if panicked {
+            for result in &results {
+                unread.remove(&result.1);
+            }
+            for path in unread {
+                let name = table_name(&path);
+                let encoding = options.encoding_of(&name);
+                let error = io::Error::new(io::ErrorKind::Other,
+                                           "reading the file panicked");
+                results.push((name, path, encoding, Err(ShnError::Io(error))));
+            }
+        }
+        // Sorted, so the same file wins if names clash
+        results.sort_by(|a, b| a.1.cmp(&b.1));
+
+        let mut database = ShnDatabase::new();
+        let mut failed = Vec::new();
+        for (name, path, encoding, result) in results {
+            match result {
+                Ok(file) => {
+                    if database.tables.contains_key(&name) {
+                        failed.push((path, ShnError::DuplicateTable(name)));
+                        continue;
+                    }
+                    let saved = content_hash(&file);
+                    database.tables.insert(name, Entry {
+                        file:       file,
+                        path:       path,
+                        encoding:   encoding,
+                        saved:      Some(saved),
+                    });
+                },
+                Err(e) => failed.push((path, e)),
+            }
+        }
+        Ok((database, failed))
+    }
+
+    /// Adds `file` as table `name`, to be saved to `path` using `encoding`.
+    /// The table is considered modified. Replaces and returns any table of
+    /// the same name.
+    pub fn insert<P: Into<PathBuf>>(&mut self,
+                                    name: &str,
+                                    file: ShnFile,
+                                    path: P,
+                                    encoding: EncodingRef)
+                                    -> Option<ShnFile> {
+        self.tables.insert(name.to_owned(), Entry {
+            file:       file,
+            path:       path.into(),
+            encoding:   encoding,
+            saved:      None,
+        }).map(|e| e.file)
+    }
+
+    /// Removes the table `name` from the database, without touching its file.
+    pub fn remove(&mut self, name: &str) -> Option<ShnFile> {
+        self.tables.remove(name).map(|e| e.file)
+    }
+
+    /// Returns the table `name`.
+    pub fn table(&self, name: &str) -> Result<&ShnFile> {
+        self.tables.get(name)
+            .map(|e| &e.file)
+            .ok_or_else(|| ShnError::UnknownTable(name.to_owned()))
+    }
+
+    /// Returns the table `name` for modification. Borrowing the table does
+    /// not mark it as modified, only changing its contents does.
+    pub fn table_mut(&mut self, name: &str) -> Result<&mut ShnFile> {
+        self.tables.get_mut(name)
+            .map(|e| &mut e.file)
+            .ok_or_else(|| ShnError::UnknownTable(name.to_owned()))
+    }
+
+    /// Returns the names of all tables, in no particular order.
+    pub fn names(&self) -> Vec<&str> {
+        self.tables.keys().map(|k| &k[..]).collect()
+    }
+
+    /// Returns the number of tables.
+    pub fn len(&self) -> usize {
+        self.tables.len()
+    }
+
+    /// Returns whether there are no tables.
+    pub fn is_empty(&self) -> bool {
+        self.tables.is_empty()
+    }
+
+    /// Returns the file the table `name` is saved to.
+    pub fn path(&self, name: &str) -> Option<&Path> {
+        self.tables.get(name).map(|e| e.path.as_path())
+    }
+
+    /// Returns whether the contents of the table `name` differ from those
+    /// it was loaded or last saved with. Tables added by `insert` are
+    /// modified until saved. Changes which are undone again do not count.
+    pub fn is_modified(&self, name: &str) -> bool {
+        self.tables.get(name).map_or(false, |e| e.is_modified())
+    }
+
+    /// Declares a relation between two tables, which is checked by
+    /// `check_integrity`, and returns its position in `relations`. The
+    /// tables do not need to exist yet.
+    pub fn add_relation(&mut self, relation: ShnRelation) -> usize {
+        self.relations.push(relation);
+        self.relations.len() - 1
+    }
+
+    /// Returns all declared relations.
+    pub fn relations(&self) -> &[ShnRelation] {
+        &self.relations
+    }
+
+    /// Writes every modified table back to its file, and returns the names
+    /// of the written tables. Each file is written to a temporary file
+    /// first, which then replaces it, so a failure never leaves a partially
+    /// written table behind. Stops at the first failure, with the tables
+    /// written up to then no longer considered modified.
+    pub fn save_all(&mut self) -> Result<Vec<String>> {
+        let mut names: Vec<String> = self.tables.iter()
+            .filter(|&(_, e)| e.is_modified())
+            .map(|(n, _)| n.clone())
+            .collect();
+        names.sort();
+        for name in &names {
+            let entry = self.tables.get_mut(name).unwrap();
+            try!(write_file(&entry.path, &entry.file, entry.encoding));
+            entry.saved = Some(content_hash(&entry.file));
+        }
+        Ok(names)
+    }
+}
+
+impl Entry {
+    fn is_modified(&self) -> bool {
+        self.saved != Some(content_hash(&self.file))
+    }
+}
+
+/// Hashes everything of `file` which is written to its file.
+fn content_hash(file: &ShnFile) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    file.crypt_header.hash(&mut hasher);
+    file.header.hash(&mut hasher);
+    for column in &file.schema.columns {
+        column.name.hash(&mut hasher);
+        column.data_type.hash(&mut hasher);
+        column.data_length.hash(&mut hasher);
+    }
+    for row in &file.data {
+        row.data.hash(&mut hasher);
+    }
+    hasher.finish()
+}
+
+/// Returns the name of the table stored at `path`, its file name without
+/// the extension.
+pub(crate) fn table_name(path: &Path) -> String {
+    path.file_stem()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+/// Collects the paths of all `.shn` files in `dir`, ignoring the case of
+/// the extension.
+fn find_files(dir: &Path, recursive: bool, paths: &mut Vec<PathBuf>)
+              -> Result<()> {
+    for entry in try!(fs::read_dir(dir).map_err(ShnError::Io)) {
+        let path = try!(entry.map_err(ShnError::Io)).path();
+        if path.is_dir() {
+            if recursive {
+                try!(find_files(&path, recursive, paths));
+            }
+        } else if path.extension()
+            .map_or(false, |e| e.to_string_lossy().eq_ignore_ascii_case("shn")) {
+            paths.push(path);
+        }
+    }
+    Ok(())
+}
+
+fn read_file(path: &Path, encoding: EncodingRef) -> Result<ShnFile> {
+    let file = try!(File::open(path).map_err(ShnError::Io));
+    let mut file = try!(ShnReader::read_from(BufReader::new(file), &encoding));
+    if let Some(column) = file.key_column().map(|c| c.to_owned()) {
+        // Tables with duplicate or floating point keys are loaded without an
+        // index, and looked up by scanning them
+        match file.index_by(&column) {
+            Ok(()) | Err(ShnError::DuplicateKey(_))
+                | Err(ShnError::InvalidSchema) => {},
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(file)
+}
+
+/// Writes `file` to a temporary file next to `path`, which then replaces
+/// it, see `replace_file`.
+pub(crate) fn write_file(path: &Path, file: &ShnFile, encoding: EncodingRef)
+                         -> Result<()> {
+    replace_file(path, |dest| ShnWriter::write_to(file, &encoding, dest))
+}
+
+/// Calls `write` with a temporary file next to `path`, which then replaces
+/// it, so a failure never leaves a partially written file behind. The
+/// temporary file is removed if writing fails.
+pub(crate) fn replace_file<F>(path: &Path, write: F) -> Result<()>
+    where F: FnOnce(&mut BufWriter<File>) -> Result<()> {
+    let mut temp = path.as_os_str().to_owned();
+    temp.push(".tmp");
+    let temp = PathBuf::from(temp);
+    let result = write_temp(&temp, write)
+        .and_then(|_| fs::rename(&temp, path).map_err(ShnError::Io));
+    if result.is_err() {
+        let _ = fs::remove_file(&temp);
+    }
+    result
+}
+
+fn write_temp<F>(temp: &Path, write: F) -> Result<()>
+    where F: FnOnce(&mut BufWriter<File>) -> Result<()> {
+    let dest = try!(File::create(temp).map_err(ShnError::Io));
+    let mut dest = BufWriter::new(dest);
+    try!(write(&mut dest));
+    dest.flush().map_err(ShnError::Io)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::std::env;
+    use ::std::process;
+    use ::encoding::all::ASCII;
+    use super::super::shn::{ ShnCell, ShnColumn, decrypt };
+    use super::super::shn_test_util::table;
+
+    /// Returns an empty directory for the test `name`.
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("shn-database-{}-{}", name,
+                                               process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn file(ids: &[u32]) -> ShnFile {
+        table(vec![ShnColumn::new_unsigned_integer("ID"),
+                   ShnColumn::new_string_fixed_len("Name", 8)],
+              ids.iter()
+              .map(|&id| vec![ShnCell::UnsignedInteger(id),
+                              ShnCell::StringFixedLen(format!("n{}", id))])
+              .collect())
+    }
+
+    fn write(path: &Path, file: &ShnFile) {
+        write_file(path, file, ASCII).unwrap();
+    }
+
+    fn load(dir: &Path, recursive: bool)
+            -> (ShnDatabase, Vec<(PathBuf, ShnError)>) {
+        let mut options = DatabaseOptions::new(ASCII);
+        options.threads = 2;
+        options.recursive = recursive;
+        ShnDatabase::load(dir, &options).unwrap()
+    }
+
+    fn sorted_names(database: &ShnDatabase) -> Vec<&str> {
+        let mut names = database.names();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn load_directory() {
+        let dir = temp_dir("load");
+        write(&dir.join("Item.shn"), &file(&[1, 5, 9]));
+        write(&dir.join("Dup.SHN"), &file(&[2, 2]));
+        fs::write(dir.join("Broken.shn"), b"not a table").unwrap();
+        fs::write(dir.join("Notes.txt"), b"ignored").unwrap();
+        let mut unknown = Vec::new();
+        let enc: EncodingRef = ASCII;
+        ShnWriter::write_to(&file(&[1]), &enc, &mut unknown).unwrap();
+        decrypt(&mut unknown[0x24..]);
+        // The type of `Name`, after the header, the record count and length,
+        // the column count and the name, type and length of `ID`
+        unknown[0x24 + 16 + 56 + 48] = 99;
+        decrypt(&mut unknown[0x24..]);
+        fs::write(dir.join("Unknown.shn"), &unknown).unwrap();
+        fs::create_dir(dir.join("sub")).unwrap();
+        write(&dir.join("sub").join("Other.shn"), &file(&[3]));
+        write(&dir.join("sub").join("Item.shn"), &file(&[4]));
+
+        let (database, mut failed) = load(&dir, false);
+        assert_eq!(sorted_names(&database), vec!["Dup", "Item"]);
+        assert!(!database.is_modified("Item"));
+        assert_eq!(database.path("Item"), Some(dir.join("Item.shn").as_path()));
+        let item = database.table("Item").unwrap();
+        assert!(item.is_indexed("ID"));
+        assert_eq!(item.by_key(5u32).unwrap().data[2],
+                   ShnCell::StringFixedLen("n5".to_owned()));
+        let dup = database.table("Dup").unwrap();
+        assert!(!dup.is_indexed("ID"));
+        assert_eq!(dup.by_key(2u32).unwrap().data[0], ShnCell::UnsignedShort(0));
+
+        failed.sort_by(|a, b| a.0.cmp(&b.0));
+        assert_eq!(failed.len(), 2);
+        assert_eq!(failed[0].0, dir.join("Broken.shn"));
+        assert_eq!(failed[1].0, dir.join("Unknown.shn"));
+        match failed[1].1 {
+            ShnError::UnknownDataType(ref column, 99) => assert_eq!(column, "Name"),
+            ref e => panic!("{:?}", e),
+        }
+
+        let (database, failed) = load(&dir, true);
+        assert_eq!(sorted_names(&database), vec!["Dup", "Item", "Other"]);
+        assert_eq!(database.path("Item"), Some(dir.join("Item.shn").as_path()));
+        assert!(failed.iter().any(|&(ref p, ref e)| {
+            *p == dir.join("sub").join("Item.shn")
+                && match *e { ShnError::DuplicateTable(_) => true, _ => false }
+        }));
+
+        assert!(ShnDatabase::load(dir.join("missing"),
+                                  &DatabaseOptions::new(ASCII)).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn modify_and_save() {
+        let dir = temp_dir("save");
+        write(&dir.join("Item.shn"), &file(&[1, 5]));
+        write(&dir.join("Other.shn"), &file(&[3]));
+        let (mut database, failed) = load(&dir, false);
+        assert!(failed.is_empty());
+        assert_eq!(database.save_all().unwrap(), Vec::<String>::new());
+
+        database.table_mut("Item").unwrap()
+            .set_cell(1, "Name", ShnCell::StringFixedLen("renamed".to_owned()))
+            .unwrap();
+        assert!(database.is_modified("Item"));
+        assert!(!database.is_modified("Other"));
+        database.insert("New", file(&[7]), dir.join("New.shn"), ASCII);
+        assert_eq!(database.save_all().unwrap(), vec!["Item", "New"]);
+        assert!(!database.is_modified("Item"));
+        assert!(!dir.join("Item.shn.tmp").exists());
+
+        // Undoing a change leaves the table unmodified
+        database.table_mut("Other").unwrap()
+            .set_cell(0, "Name", ShnCell::StringFixedLen("x".to_owned()))
+            .unwrap();
+        assert!(database.is_modified("Other"));
+        database.table_mut("Other").unwrap()
+            .set_cell(0, "Name", ShnCell::StringFixedLen("n3".to_owned()))
+            .unwrap();
+        assert!(!database.is_modified("Other"));
+
+        let (database, _) = load(&dir, false);
+        assert_eq!(sorted_names(&database), vec!["Item", "New", "Other"]);
+        assert_eq!(database.table("Item").unwrap().data[1].data[2],
+                   ShnCell::StringFixedLen("renamed".to_owned()));
+        assert_eq!(database.table("New").unwrap().data, file(&[7]).data);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn borrow_without_change() {
+        let dir = temp_dir("borrow");
+        let path = dir.join("Item.shn");
+        write(&path, &file(&[1, 5]));
+        let before = fs::metadata(&path).unwrap().modified().unwrap();
+        let (mut database, _) = load(&dir, false);
+        {
+            let item = database.table_mut("Item").unwrap();
+            assert_eq!(item.data.len(), 2);
+        }
+        assert!(!database.is_modified("Item"));
+        assert_eq!(database.save_all().unwrap(), Vec::<String>::new());
+        assert_eq!(fs::metadata(&path).unwrap().modified().unwrap(), before);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn failed_save() {
+        let dir = temp_dir("failed-save");
+        let path = dir.join("Item.shn");
+        write(&path, &file(&[1]));
+        let before = fs::read(&path).unwrap();
+        let mut database = ShnDatabase::new();
+        let mut broken = file(&[1]);
+        broken.data[0].data[2] = ShnCell::StringFixedLen("far too long".to_owned());
+        database.insert("Item", broken, &path, ASCII);
+        assert!(database.save_all().is_err());
+        assert!(database.is_modified("Item"));
+        assert_eq!(fs::read(&path).unwrap(), before);
+        assert!(!dir.join("Item.shn.tmp").exists());
+
+        database.insert("Item", file(&[1]), dir.join("missing").join("Item.shn"),
+                        ASCII);
+        assert!(database.save_all().is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn tables() {
+        let mut database = ShnDatabase::new();
+        assert!(database.is_empty());
+        assert!(database.insert("Item", file(&[1]), "Item.shn", ASCII).is_none());
+        let old = database.insert("Item", file(&[2]), "Item.shn", ASCII).unwrap();
+        assert_eq!(old.data, file(&[1]).data);
+        assert_eq!(database.len(), 1);
+        assert!(database.is_modified("Item"));
+        match database.table("Nope") {
+            Err(ShnError::UnknownTable(ref name)) => assert_eq!(name, "Nope"),
+            r => panic!("{:?}", r.map(|f| f.data.len())),
+        }
+        assert!(database.table_mut("Nope").is_err());
+        assert!(!database.is_modified("Nope"));
+        assert_eq!(database.remove("Item").unwrap().data, file(&[2]).data);
+        assert!(database.remove("Item").is_none());
+        assert!(database.is_empty());
+    }
+}
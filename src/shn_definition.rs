@@ -0,0 +1,192 @@
+use ::std::io::Read;
+
+use super::shn::{ ShnDataType, ShnColumn, ShnSchema, ShnError, Result };
+
+/// Returns an error about line `line` of a definition.
+fn invalid(line: usize, message: String) -> ShnError {
+    ShnError::InvalidDefinition(line, message)
+}
+
+impl ShnSchema {
+    /// Parses a schema definition, which lists the data columns one per
+    /// line, each with its name, its type and its length in bytes,
+    /// separated by whitespace. Types are given by the name of their
+    /// `ShnDataType` or by a raw type id. Lengths are only required for
+    /// `StringFixedLen` columns. Empty lines and lines starting with `#` are
+    /// ignored:
+    ///
+    /// ```text
+    /// # ItemInfo
+    /// ID          UnsignedShort
+    /// InxName     StringFixedLen  32
+    /// Type        3
+    /// ```
+    ///
+    /// The schema starts with the `__ID__` pseudo-column, which is not
+    /// listed.
+    pub fn from_definition(text: &str) -> Result<ShnSchema> {
+        let mut schema = ShnSchema::with_id_column();
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let parts: Vec<&str> = line.split_whitespace().collect();
+            if parts.len() < 2 || parts.len() > 3 {
+                return Err(invalid(i + 1, "expected name, type and length"
+                                   .to_owned()));
+            }
+            let data_type = try!(parts[1].parse()
+                                 .ok()
+                                 .and_then(ShnDataType::try_from_id)
+                                 .or_else(|| ShnDataType::from_name(parts[1]))
+                                 .ok_or_else(|| invalid(i + 1, format!(
+                                     "unknown type `{}`", parts[1]))));
+            let length = match parts.get(2) {
+                Some(l) => try!(l.parse().map_err(|_| invalid(i + 1, format!(
+                    "invalid length `{}`", l)))),
+                None if data_type == ShnDataType::StringFixedLen
+                    => return Err(invalid(i + 1, format!(
+                        "missing length of `{}`", parts[0]))),
+                None    => data_type.default_length() as i32,
+            };
+            let fixed = data_type.default_length() as i32;
+            let valid = match data_type {
+                ShnDataType::StringFixedLen         => length > 0,
+                ShnDataType::StringZeroTerminated   => length >= 0,
+                _                                   => length == fixed,
+            };
+            if !valid {
+                return Err(invalid(i + 1, format!(
+                    "invalid length {} of `{}`", length, parts[0])));
+            }
+            if schema.columns.iter().any(|c| c.name == parts[0]) {
+                return Err(ShnError::DuplicateColumn(parts[0].to_owned()));
+            }
+            schema.columns.push(ShnColumn {
+                name:           parts[0].to_owned(),
+                data_type:      data_type,
+                data_length:    length,
+            });
+        }
+        Ok(schema)
+    }
+
+    /// Reads a schema definition, see `from_definition`.
+    pub fn read_definition<R: Read>(mut source: R) -> Result<ShnSchema> {
+        let mut text = String::new();
+        try!(source.read_to_string(&mut text).map_err(ShnError::Io));
+        ShnSchema::from_definition(&text)
+    }
+
+    /// Returns the definition of the data columns, in the format read by
+    /// `from_definition`. Fails with `ShnError::InvalidDefinition` for
+    /// column names which can not be written, like ones with whitespace.
+    ///
+    /// Types are written by the name of their `ShnDataType`, since columns
+    /// do not keep the raw type id they were read with: a column defined
+    /// with id `12` is written as `Byte`, and written to files with id `1`.
+    /// Reading the definition back yields an equal schema.
+    pub fn to_definition(&self) -> Result<String> {
+        let columns = self.data_columns();
+        let width = columns.iter().map(|c| c.name.len()).max().unwrap_or(0);
+        let mut text = String::new();
+        for (i, c) in columns.iter().enumerate() {
+            if c.name.is_empty() || c.name.starts_with('#')
+                || c.name.contains(char::is_whitespace) {
+                return Err(invalid(i + 1, format!(
+                    "column name `{}` can not be written", c.name)));
+            }
+            let data_type = format!("{:?}", c.data_type);
+            let line = match c.data_type {
+                ShnDataType::StringFixedLen |
+                ShnDataType::StringZeroTerminated
+                    => format!("{:w$}  {:20}  {}", c.name, data_type,
+                               c.data_length, w = width),
+                _   => format!("{:w$}  {}", c.name, data_type, w = width),
+            };
+            text.push_str(line.trim_end());
+            text.push('\n');
+        }
+        Ok(text)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error(text: &str) -> (usize, String) {
+        match ShnSchema::from_definition(text) {
+            Err(ShnError::InvalidDefinition(line, message)) => (line, message),
+            r => panic!("{:?}", r),
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let text = "# ItemInfo\n\
+                    \n\
+                    ID          UnsignedShort\n\
+                    InxName     StringFixedLen  32\n\
+                    Type        3\n\
+                    Flag        12\n\
+                    Desc        StringZeroTerminated\n\
+                    Rate        SingleFloatingPoint 4\n";
+        let schema = ShnSchema::from_definition(text).unwrap();
+        let types: Vec<(ShnDataType, i32)> = schema.columns.iter()
+            .map(|c| (c.data_type.clone(), c.data_length))
+            .collect();
+        assert_eq!(types, vec![(ShnDataType::UnsignedShort, 2),
+                               (ShnDataType::UnsignedShort, 2),
+                               (ShnDataType::StringFixedLen, 32),
+                               (ShnDataType::UnsignedInteger, 4),
+                               (ShnDataType::Byte, 1),
+                               (ShnDataType::StringZeroTerminated, 0),
+                               (ShnDataType::SingleFloatingPoint, 4)]);
+        assert!(schema.columns[0].is_id());
+
+        // Raw type ids are written by name, but read back the same
+        let definition = schema.to_definition().unwrap();
+        assert_eq!(definition,
+                   "ID       UnsignedShort\n\
+                    InxName  StringFixedLen        32\n\
+                    Type     UnsignedInteger\n\
+                    Flag     Byte\n\
+                    Desc     StringZeroTerminated  0\n\
+                    Rate     SingleFloatingPoint\n");
+        assert_eq!(ShnSchema::from_definition(&definition).unwrap(), schema);
+        assert_eq!(ShnSchema::read_definition(definition.as_bytes()).unwrap(),
+                   schema);
+        assert_eq!(ShnSchema::with_id_column().to_definition().unwrap(), "");
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(error("ID"), (1, "expected name, type and length".to_owned()));
+        assert_eq!(error("\nID Byte 1 2"),
+                   (2, "expected name, type and length".to_owned()));
+        assert_eq!(error("ID Word"), (1, "unknown type `Word`".to_owned()));
+        assert_eq!(error("ID 99"), (1, "unknown type `99`".to_owned()));
+        assert_eq!(error("Name StringFixedLen"),
+                   (1, "missing length of `Name`".to_owned()));
+        assert_eq!(error("Name StringFixedLen x"), (1, "invalid length `x`".to_owned()));
+        assert_eq!(error("Name StringFixedLen 0"),
+                   (1, "invalid length 0 of `Name`".to_owned()));
+        assert_eq!(error("ID UnsignedShort 4"),
+                   (1, "invalid length 4 of `ID`".to_owned()));
+        match ShnSchema::from_definition("ID Byte\nID Byte") {
+            Err(ShnError::DuplicateColumn(ref c)) => assert_eq!(c, "ID"),
+            r => panic!("{:?}", r),
+        }
+
+        let mut schema = ShnSchema::with_id_column();
+        schema.columns.push(ShnColumn::new_byte("Two words"));
+        match schema.to_definition() {
+            Err(ShnError::InvalidDefinition(1, _)) => {},
+            r => panic!("{:?}", r),
+        }
+        schema.columns[1].name = "#Hash".to_owned();
+        assert!(schema.to_definition().is_err());
+    }
+}
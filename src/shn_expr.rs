@@ -0,0 +1,839 @@
+use ::std::collections::HashMap;
+use ::std::fmt;
+
+use super::shn::{ ShnDataType, ShnCell, ShnSchema, ShnRow, ShnKey, ShnFile,
+                  ShnError, Result };
+use super::shn_query::ShnPredicate;
+use super::shn_query_parser::{ Parser, Token };
+
+/// Arithmetic operators usable in a `ShnExpr`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ShnOperator {
+    /// `+`
+    Add,
+    /// `-`
+    Subtract,
+    /// `*`
+    Multiply,
+    /// `/`, integer division if both operands are integers
+    Divide,
+    /// `%`
+    Remainder,
+}
+
+/// Functions usable in a `ShnExpr` with numeric arguments and results.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ShnFunction {
+    /// Rounds to the nearest integer, away from zero on ties
+    Round,
+    /// Rounds down to an integer
+    Floor,
+    /// Rounds up to an integer
+    Ceil,
+    /// The smaller one of two values
+    Min,
+    /// The larger one of two values
+    Max,
+}
+
+impl ShnFunction {
+    /// Returns the function called `name`, ignoring case, and the number of
+    /// its arguments.
+    fn from_name(name: &str) -> Option<(ShnFunction, usize)> {
+        match &name.to_lowercase()[..] {
+            "round" => Some((ShnFunction::Round, 1)),
+            "floor" => Some((ShnFunction::Floor, 1)),
+            "ceil"  => Some((ShnFunction::Ceil, 1)),
+            "min"   => Some((ShnFunction::Min, 2)),
+            "max"   => Some((ShnFunction::Max, 2)),
+            _       => None,
+        }
+    }
+}
+
+/// An expression computing a value from the cells of a row, see
+/// `ShnFile::update_where`.
+///
+/// Integers are computed as `i64`, floating point values as `f64`, and the
+/// result is converted to the type of the target column only at the end.
+#[derive(Clone, PartialEq, Debug)]
+pub enum ShnExpr {
+    /// A constant value
+    Literal(ShnCell),
+    /// The cell of the named column
+    Column(String),
+    /// Applies the operator to both values
+    Binary(ShnOperator, Box<ShnExpr>, Box<ShnExpr>),
+    /// Negates the value
+    Negate(Box<ShnExpr>),
+    /// Calls the function with the values
+    Call(ShnFunction, Vec<ShnExpr>),
+    /// Limits the first value to the range given by the second and third
+    Clamp(Box<ShnExpr>, Box<ShnExpr>, Box<ShnExpr>),
+    /// Joins the values as text
+    Concat(Vec<ShnExpr>),
+    /// Maps the value through the table, falling back to the default if
+    /// there is one, and failing otherwise
+    Lookup(Box<ShnExpr>, HashMap<ShnKey, ShnCell>, Option<Box<ShnExpr>>),
+}
+
+/// Intermediate values of an evaluation.
+#[derive(Clone, PartialEq, Debug)]
+enum Value {
+    Integer(i64),
+    Float(f64),
+    Text(String),
+}
+
+impl Value {
+    fn from_cell(cell: &ShnCell) -> Value {
+        match (cell.as_i64(), cell.as_f64(), cell.as_str()) {
+            (Some(v), _, _)         => Value::Integer(v),
+            (_, Some(v), _)         => Value::Float(v),
+            (_, _, Some(s))         => Value::Text(s.to_owned()),
+            _                       => unreachable!(),
+        }
+    }
+
+    /// Returns the most narrow signed cell holding the value, falling back
+    /// to unsigned and floating point cells for large integers.
+    fn to_cell(&self) -> ShnCell {
+        match *self {
+            Value::Integer(v) if v >= i32::min_value() as i64
+                && v <= i32::max_value() as i64
+                => ShnCell::SignedInteger(v as i32),
+            Value::Integer(v) if v >= 0 && v <= u32::max_value() as i64
+                => ShnCell::UnsignedInteger(v as u32),
+            Value::Integer(v)       => ShnCell::SingleFloatingPoint(v as f32),
+            Value::Float(v)         => ShnCell::SingleFloatingPoint(v as f32),
+            Value::Text(ref s)      => ShnCell::StringZeroTerminated(s.clone()),
+        }
+    }
+
+    fn as_f64(&self) -> Option<f64> {
+        match *self {
+            Value::Integer(v)   => Some(v as f64),
+            Value::Float(v)     => Some(v),
+            Value::Text(_)      => None,
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Value::Integer(v)   => v.fmt(f),
+            Value::Float(v)     => v.fmt(f),
+            Value::Text(ref s)  => s.fmt(f),
+        }
+    }
+}
+
+impl ShnExpr {
+    /// Constructs an expression reading the cell of `column`.
+    pub fn column(column: &str) -> Self {
+        ShnExpr::Column(column.to_owned())
+    }
+
+    /// Constructs a constant expression.
+    pub fn literal<V: Into<ShnCell>>(value: V) -> Self {
+        ShnExpr::Literal(value.into())
+    }
+
+    /// Constructs an expression applying `operator` to both expressions.
+    pub fn binary(operator: ShnOperator, left: ShnExpr, right: ShnExpr) -> Self {
+        ShnExpr::Binary(operator, Box::new(left), Box::new(right))
+    }
+
+    /// Evaluates the expression against the cells of `row`, returning the
+    /// result in the most narrow signed type, or as `SingleFloatingPoint` or
+    /// `StringZeroTerminated` cell.
+    pub fn evaluate(&self, row: &ShnRow) -> Result<ShnCell> {
+        self.eval(row).map(|v| v.to_cell())
+    }
+
+    /// Evaluates the expression and converts the result to `data_type`.
+    /// Fails if the result is text but the type is not, or vice versa, or if
+    /// the value is out of range for the type. Floating point results with a
+    /// fractional part need to be rounded explicitly to be stored as
+    /// integers.
+    pub fn evaluate_as(&self, row: &ShnRow, data_type: &ShnDataType)
+                       -> Result<ShnCell> {
+        let value = try!(self.eval(row));
+        let is_text = match *data_type {
+            ShnDataType::StringFixedLen |
+            ShnDataType::StringZeroTerminated   => true,
+            _                                   => false,
+        };
+        let cell = value.to_cell();
+        let converted = match (value, is_text) {
+            (Value::Text(s), true) => Some(match *data_type {
+                ShnDataType::StringFixedLen => ShnCell::StringFixedLen(s),
+                _                           => ShnCell::StringZeroTerminated(s),
+            }),
+            (Value::Float(v), false) if v.is_finite() && !(v as f32).is_finite()
+                => None,
+            (Value::Text(_), _) | (_, true) => None,
+            (_, false) => cell.convert_to(data_type),
+        };
+        converted.ok_or_else(|| ShnError::InvalidConversion(cell,
+                                                            data_type.clone()))
+    }
+
+    fn eval(&self, row: &ShnRow) -> Result<Value> {
+        match *self {
+            ShnExpr::Literal(ref cell) => Ok(Value::from_cell(cell)),
+            ShnExpr::Column(ref name) => {
+                row.schema.columns.iter()
+                    .position(|c| c.name == *name)
+                    .and_then(|i| row.data.get(i))
+                    .map(Value::from_cell)
+                    .ok_or_else(|| ShnError::UnknownColumn(name.clone()))
+            },
+            ShnExpr::Binary(operator, ref left, ref right) => {
+                let left = try!(left.eval(row));
+                let right = try!(right.eval(row));
+                binary(operator, left, right)
+            },
+            ShnExpr::Negate(ref value) => negate(try!(value.eval(row))),
+            ShnExpr::Call(function, ref args) => {
+                let mut values = Vec::with_capacity(args.len());
+                for arg in args {
+                    values.push(try!(arg.eval(row)));
+                }
+                call(function, values)
+            },
+            ShnExpr::Clamp(ref value, ref min, ref max) => {
+                let value = try!(value.eval(row));
+                let min = try!(min.eval(row));
+                let max = try!(max.eval(row));
+                let value = try!(call(ShnFunction::Max, vec![value, min]));
+                call(ShnFunction::Min, vec![value, max])
+            },
+            ShnExpr::Concat(ref values) => {
+                let mut text = String::new();
+                for value in values {
+                    text.push_str(&try!(value.eval(row)).to_string());
+                }
+                Ok(Value::Text(text))
+            },
+            ShnExpr::Lookup(ref value, ref table, ref default) => {
+                let key = try!(value.eval(row)).to_cell();
+                match ShnKey::from_cell(&key).and_then(|k| table.get(&k)) {
+                    Some(cell)  => Ok(Value::from_cell(cell)),
+                    None        => match *default {
+                        Some(ref default)   => default.eval(row),
+                        None                => Err(ShnError::InvalidValue(key)),
+                    },
+                }
+            },
+        }
+    }
+}
+
+/// Negates `value`, failing for text and for integers without a positive
+/// counterpart.
+fn negate(value: Value) -> Result<Value> {
+    match value {
+        Value::Integer(v) => v.checked_neg()
+            .map(Value::Integer)
+            .ok_or_else(|| ShnError::InvalidValue(value.to_cell())),
+        Value::Float(v) => Ok(Value::Float(-v)),
+        Value::Text(_)  => Err(ShnError::InvalidValue(value.to_cell())),
+    }
+}
+
+/// Applies `operator`, computing in integers if both values are integers.
+/// Fails on integer overflow, on division by zero, and for floating point
+/// results which are not finite although both operands are, reporting the
+/// left operand.
+fn binary(operator: ShnOperator, left: Value, right: Value) -> Result<Value> {
+    let result = match (&left, &right) {
+        (&Value::Integer(a), &Value::Integer(b)) => match operator {
+            ShnOperator::Add        => a.checked_add(b),
+            ShnOperator::Subtract   => a.checked_sub(b),
+            ShnOperator::Multiply   => a.checked_mul(b),
+            ShnOperator::Divide     => a.checked_div(b),
+            ShnOperator::Remainder  => a.checked_rem(b),
+        }.map(Value::Integer),
+        _ => match (left.as_f64(), right.as_f64()) {
+            (Some(a), Some(b)) => {
+                let result = match operator {
+                    ShnOperator::Add        => a + b,
+                    ShnOperator::Subtract   => a - b,
+                    ShnOperator::Multiply   => a * b,
+                    ShnOperator::Divide     => a / b,
+                    ShnOperator::Remainder  => a % b,
+                };
+                if result.is_finite() || !(a.is_finite() && b.is_finite()) {
+                    Some(Value::Float(result))
+                } else {
+                    None
+                }
+            },
+            (None, _)   => return Err(ShnError::InvalidValue(left.to_cell())),
+            _           => return Err(ShnError::InvalidValue(right.to_cell())),
+        },
+    };
+    result.ok_or_else(|| ShnError::InvalidValue(left.to_cell()))
+}
+
+/// Calls `function`, keeping integers as such.
+fn call(function: ShnFunction, mut args: Vec<Value>) -> Result<Value> {
+    for arg in &args {
+        if let Value::Text(_) = *arg {
+            return Err(ShnError::InvalidValue(arg.to_cell()));
+        }
+    }
+    let round = |v: Value, f: fn(f64) -> f64| match v {
+        Value::Float(v) => Value::Float(f(v)),
+        v               => v,
+    };
+    match function {
+        ShnFunction::Round  => Ok(round(args.remove(0), f64::round)),
+        ShnFunction::Floor  => Ok(round(args.remove(0), f64::floor)),
+        ShnFunction::Ceil   => Ok(round(args.remove(0), f64::ceil)),
+        ShnFunction::Min | ShnFunction::Max => {
+            let b = args.pop().unwrap();
+            let a = args.pop().unwrap();
+            let less = match (&a, &b) {
+                (&Value::Integer(x), &Value::Integer(y)) => x < y,
+                _ => a.as_f64() < b.as_f64(),
+            };
+            Ok(if less == (function == ShnFunction::Min) { a } else { b })
+        },
+    }
+}
+
+/// Whether an expression results in a number or in text, used to check
+/// expressions while parsing.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Kind {
+    Number,
+    Text,
+}
+
+impl ShnExpr {
+    /// Parses an expression, checking column names and types against
+    /// `schema`. Expressions consist of numbers, quoted strings, column
+    /// names, `+`, `-`, `*`, `/`, `%`, parentheses and the functions
+    /// `round(x)`, `floor(x)`, `ceil(x)`, `min(a, b)`, `max(a, b)`,
+    /// `clamp(x, min, max)`, `concat(a, ...)` and
+    /// `lookup(x, key: value, ... [, default])`.
+    pub fn parse(text: &str, schema: &ShnSchema) -> Result<ShnExpr> {
+        let mut parser = try!(Parser::new(text));
+        let (expr, _) = try!(parse_sum(&mut parser, schema));
+        if !parser.is_end() {
+            return parser.error("end of expression");
+        }
+        Ok(expr)
+    }
+}
+
+/// Fails at `offset` unless `kind` is a number.
+fn expect_number(kind: Kind, offset: usize) -> Result<()> {
+    match kind {
+        Kind::Number    => Ok(()),
+        Kind::Text      => Err(ShnError::InvalidQuery(
+            offset, "type mismatch, expected a number".to_owned())),
+    }
+}
+
+fn parse_sum(parser: &mut Parser, schema: &ShnSchema) -> Result<(ShnExpr, Kind)> {
+    let offset = parser.offset();
+    let (mut expr, kind) = try!(parse_product(parser, schema));
+    loop {
+        let operator = if parser.symbol("+") {
+            ShnOperator::Add
+        } else if parser.symbol("-") {
+            ShnOperator::Subtract
+        } else {
+            return Ok((expr, kind));
+        };
+        try!(expect_number(kind, offset));
+        let right_offset = parser.offset();
+        let (right, right_kind) = try!(parse_product(parser, schema));
+        try!(expect_number(right_kind, right_offset));
+        expr = ShnExpr::binary(operator, expr, right);
+    }
+}
+
+fn parse_product(parser: &mut Parser, schema: &ShnSchema)
+                 -> Result<(ShnExpr, Kind)> {
+    let offset = parser.offset();
+    let (mut expr, kind) = try!(parse_unary(parser, schema));
+    loop {
+        let operator = if parser.symbol("*") {
+            ShnOperator::Multiply
+        } else if parser.symbol("/") {
+            ShnOperator::Divide
+        } else if parser.symbol("%") {
+            ShnOperator::Remainder
+        } else {
+            return Ok((expr, kind));
+        };
+        try!(expect_number(kind, offset));
+        let right_offset = parser.offset();
+        let (right, right_kind) = try!(parse_unary(parser, schema));
+        try!(expect_number(right_kind, right_offset));
+        expr = ShnExpr::binary(operator, expr, right);
+    }
+}
+
+fn parse_unary(parser: &mut Parser, schema: &ShnSchema)
+               -> Result<(ShnExpr, Kind)> {
+    if parser.symbol("-") {
+        let offset = parser.offset();
+        try!(parser.enter());
+        let (expr, kind) = try!(parse_unary(parser, schema));
+        parser.leave();
+        try!(expect_number(kind, offset));
+        return Ok((ShnExpr::Negate(Box::new(expr)), kind));
+    }
+    parse_primary(parser, schema)
+}
+
+/// Parses a literal, column, function call or parenthesized expression.
+fn parse_primary(parser: &mut Parser, schema: &ShnSchema)
+                 -> Result<(ShnExpr, Kind)> {
+    let offset = parser.offset();
+    match parser.peek().cloned() {
+        Some(Token::Number(n)) => {
+            parser.next();
+            let cell = match n.parse::<i64>() {
+                Ok(v)   => Value::Integer(v).to_cell(),
+                Err(_)  => try!(ShnCell::parse(&n,
+                                               &ShnDataType::SingleFloatingPoint)
+                                .map_err(|_| ShnError::InvalidQuery(
+                                    offset, format!("invalid number `{}`", n)))),
+            };
+            Ok((ShnExpr::Literal(cell), Kind::Number))
+        },
+        Some(Token::Str(s)) => {
+            parser.next();
+            Ok((ShnExpr::Literal(ShnCell::StringZeroTerminated(s)), Kind::Text))
+        },
+        Some(Token::Symbol("(")) => {
+            parser.next();
+            try!(parser.enter());
+            let result = try!(parse_sum(parser, schema));
+            try!(parser.expect_symbol(")"));
+            parser.leave();
+            Ok(result)
+        },
+        Some(Token::Ident(name)) => {
+            parser.next();
+            if parser.symbol("(") {
+                try!(parser.enter());
+                let result = try!(parse_call(parser, schema, &name, offset));
+                parser.leave();
+                Ok(result)
+            } else {
+                parse_column(schema, &name, offset)
+            }
+        },
+        _ => parser.error("an expression"),
+    }
+}
+
+/// Resolves a column name to an expression.
+fn parse_column(schema: &ShnSchema, name: &str, offset: usize)
+                 -> Result<(ShnExpr, Kind)> {
+    match schema.columns.iter().find(|c| c.name == name) {
+        Some(column) => {
+            let kind = match column.data_type {
+                ShnDataType::StringFixedLen |
+                ShnDataType::StringZeroTerminated   => Kind::Text,
+                _                                   => Kind::Number,
+            };
+            Ok((ShnExpr::column(name), kind))
+        },
+        None => Err(ShnError::InvalidQuery(
+            offset, format!("unknown column `{}`", name))),
+    }
+}
+
+/// Parses the arguments of the function `name`, after the opening
+/// parenthesis.
+fn parse_call(parser: &mut Parser, schema: &ShnSchema, name: &str,
+              offset: usize) -> Result<(ShnExpr, Kind)> {
+    if name.eq_ignore_ascii_case("lookup") {
+        return parse_lookup(parser, schema);
+    }
+
+    let mut args = Vec::new();
+    if !parser.symbol(")") {
+        loop {
+            let arg_offset = parser.offset();
+            let (arg, kind) = try!(parse_sum(parser, schema));
+            args.push((arg, kind, arg_offset));
+            if !parser.symbol(",") {
+                break;
+            }
+        }
+        try!(parser.expect_symbol(")"));
+    }
+
+    if name.eq_ignore_ascii_case("concat") {
+        let args = args.into_iter().map(|(a, _, _)| a).collect();
+        return Ok((ShnExpr::Concat(args), Kind::Text));
+    }
+    let (function, count) = if name.eq_ignore_ascii_case("clamp") {
+        (None, 3)
+    } else {
+        match ShnFunction::from_name(name) {
+            Some((f, count))    => (Some(f), count),
+            None                => return Err(ShnError::InvalidQuery(
+                offset, format!("unknown function `{}`", name))),
+        }
+    };
+    if args.len() != count {
+        return Err(ShnError::InvalidQuery(
+            offset, format!("`{}` takes {} arguments", name, count)));
+    }
+    for &(_, kind, arg_offset) in &args {
+        try!(expect_number(kind, arg_offset));
+    }
+    let mut args: Vec<ShnExpr> = args.into_iter().map(|(a, _, _)| a).collect();
+    let expr = match function {
+        Some(f) => ShnExpr::Call(f, args),
+        None    => {
+            let max = args.pop().unwrap();
+            let min = args.pop().unwrap();
+            let value = args.pop().unwrap();
+            ShnExpr::Clamp(Box::new(value), Box::new(min), Box::new(max))
+        },
+    };
+    Ok((expr, Kind::Number))
+}
+
+/// Parses `value, key: value, ... [, default])` after `lookup(`.
+fn parse_lookup(parser: &mut Parser, schema: &ShnSchema)
+                -> Result<(ShnExpr, Kind)> {
+    let (value, _) = try!(parse_sum(parser, schema));
+    let mut table = HashMap::new();
+    let mut default = None;
+    let mut kind = None;
+    while parser.symbol(",") {
+        let offset = parser.offset();
+        let (expr, expr_kind) = try!(parse_sum(parser, schema));
+        if !parser.symbol(":") {
+            if kind.map_or(false, |k| k != expr_kind) {
+                return Err(ShnError::InvalidQuery(
+                    offset, "type mismatch, lookup values differ in type"
+                        .to_owned()));
+            }
+            kind = Some(expr_kind);
+            default = Some(Box::new(expr));
+            break;
+        }
+        let key = try!(constant(&expr)
+                       .and_then(|c| ShnKey::from_cell(&c))
+                       .ok_or_else(|| ShnError::InvalidQuery(
+                           offset,
+                           "lookup keys need to be integers or strings"
+                               .to_owned())));
+        let value_offset = parser.offset();
+        let (value, value_kind) = try!(parse_unary(parser, schema));
+        let cell = try!(constant(&value).ok_or_else(|| ShnError::InvalidQuery(
+            value_offset, "lookup values need to be constant".to_owned())));
+        if kind.map_or(false, |k| k != value_kind) {
+            return Err(ShnError::InvalidQuery(
+                value_offset,
+                "type mismatch, lookup values differ in type".to_owned()));
+        }
+        kind = Some(value_kind);
+        table.insert(key, cell);
+    }
+    try!(parser.expect_symbol(")"));
+    Ok((ShnExpr::Lookup(Box::new(value), table, default),
+        kind.unwrap_or(Kind::Number)))
+}
+
+/// Returns the value of literals and negated literals.
+fn constant(expr: &ShnExpr) -> Option<ShnCell> {
+    match *expr {
+        ShnExpr::Literal(ref cell) => Some(cell.clone()),
+        ShnExpr::Negate(ref e) => constant(e)
+            .and_then(|c| negate(Value::from_cell(&c)).ok())
+            .map(|v| v.to_cell()),
+        _ => None,
+    }
+}
+
+/// A cell changed by `ShnFile::update_where`.
+#[derive(Clone, PartialEq, Debug)]
+pub struct ShnCellChange {
+    /// The position of the row
+    pub row:    usize,
+    /// The value before the update
+    pub old:    ShnCell,
+    /// The value after the update
+    pub new:    ShnCell,
+}
+
+/// The outcome of `ShnFile::update_where`.
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct ShnUpdateSummary {
+    /// The number of rows matching the filter
+    pub matched:    usize,
+    /// The cells which changed, rows whose value stayed the same are not
+    /// included
+    pub changes:    Vec<ShnCellChange>,
+}
+
+impl ShnFile {
+    /// Sets the cell in the column named `column` to the result of `expr`
+    /// for every row matching `filter`, or for every row if there is no
+    /// filter. The results are converted to the type of the column as
+    /// described by `ShnExpr::evaluate_as`. If that fails for any row, or
+    /// the update would create duplicate keys in an index, nothing is
+    /// changed and the errors of all failed rows are returned.
+    pub fn update_where(&mut self,
+                        filter: Option<&ShnPredicate>,
+                        column: &str,
+                        expr: &ShnExpr)
+                        -> Result<ShnUpdateSummary> {
+        let position = match self.schema.columns.iter()
+            .position(|c| c.name == column) {
+                Some(p) => p,
+                None    => return Err(ShnError::UnknownColumn(column.to_owned())),
+            };
+        if self.schema.columns[position].is_id() {
+            return Err(ShnError::InvalidSchema);
+        }
+        if let Some(filter) = filter {
+            try!(filter.check(&self.schema));
+        }
+
+        let data_type = self.schema.columns[position].data_type.clone();
+        let mut summary = ShnUpdateSummary::default();
+        let mut errors = Vec::new();
+        for (i, row) in self.data.iter().enumerate() {
+            if !filter.map_or(true, |f| f.matches(row)) {
+                continue;
+            }
+            summary.matched += 1;
+            match expr.evaluate_as(row, &data_type) {
+                Ok(ref cell) if *cell == row.data[position] => {},
+                Ok(cell) => summary.changes.push(ShnCellChange {
+                    row: i,
+                    old: row.data[position].clone(),
+                    new: cell,
+                }),
+                Err(e) => errors.push((i, e)),
+            }
+        }
+        if !errors.is_empty() {
+            return Err(ShnError::RowErrors(errors));
+        }
+
+        for change in &summary.changes {
+            self.data[change.row].data[position] = change.new.clone();
+        }
+        // A failed reindex keeps the previous indexes, which still match
+        // once the old values are restored.
+        if let Err(e) = self.reindex() {
+            for change in &summary.changes {
+                self.data[change.row].data[position] = change.old.clone();
+            }
+            return Err(e);
+        }
+        Ok(summary)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::shn::ShnColumn;
+    use super::super::shn_query::ShnComparison;
+    use super::super::shn_test_util::table;
+
+    fn file() -> ShnFile {
+        let rows = [(1, "Sword", 70, 100, 0.5), (2, "Axe", 80, -5, 1.5),
+                    (3, "Stick", 1, i32::min_value(), 2.0)];
+        table(vec![ShnColumn::new_unsigned_integer("ID"),
+                   ShnColumn::new_string_fixed_len("Name", 16),
+                   ShnColumn::new_byte("Level"),
+                   ShnColumn::new_signed_integer("Gold"),
+                   ShnColumn::new_single_floating_point("Rate")],
+              rows.iter()
+              .map(|&(id, name, level, gold, rate)| {
+                  vec![ShnCell::UnsignedInteger(id),
+                       ShnCell::StringFixedLen(name.to_owned()),
+                       ShnCell::Byte(level),
+                       ShnCell::SignedInteger(gold),
+                       ShnCell::SingleFloatingPoint(rate)]
+              })
+              .collect())
+    }
+
+    fn evaluate(file: &ShnFile, row: usize, text: &str) -> Result<ShnCell> {
+        try!(ShnExpr::parse(text, &file.schema)).evaluate(&file.data[row])
+    }
+
+    fn error_offset<T: fmt::Debug>(result: Result<T>) -> (usize, String) {
+        match result {
+            Err(ShnError::InvalidQuery(offset, message)) => (offset, message),
+            r => panic!("{:?}", r),
+        }
+    }
+
+    #[test]
+    fn arithmetic() {
+        let file = file();
+        let cases = [
+            ("Level + 1", ShnCell::SignedInteger(71)),
+            ("Level - 2 * 3", ShnCell::SignedInteger(64)),
+            ("(Level - 2) * 3", ShnCell::SignedInteger(204)),
+            ("Level / 4", ShnCell::SignedInteger(17)),
+            ("Level % 4", ShnCell::SignedInteger(2)),
+            ("-Level", ShnCell::SignedInteger(-70)),
+            ("--Level", ShnCell::SignedInteger(70)),
+            ("Level * Rate", ShnCell::SingleFloatingPoint(35.0)),
+            ("Level / 4.0", ShnCell::SingleFloatingPoint(17.5)),
+            ("4294967295", ShnCell::UnsignedInteger(4294967295)),
+            ("round(2.5)", ShnCell::SingleFloatingPoint(3.0)),
+            ("floor(-2.5)", ShnCell::SingleFloatingPoint(-3.0)),
+            ("ceil(Level)", ShnCell::SignedInteger(70)),
+            ("min(Level, Gold)", ShnCell::SignedInteger(70)),
+            ("max(Level, Rate)", ShnCell::SignedInteger(70)),
+            ("clamp(Gold, 0, 50)", ShnCell::SignedInteger(50)),
+            ("concat(Name, '-', ID)",
+             ShnCell::StringZeroTerminated("Sword-1".to_owned())),
+            ("lookup(ID, 1: 'one', 2: 'two')",
+             ShnCell::StringZeroTerminated("one".to_owned())),
+            ("lookup(Name, 'Sword': -1, 0)", ShnCell::SignedInteger(-1)),
+        ];
+        for &(text, ref expected) in &cases {
+            assert_eq!(evaluate(&file, 0, text).unwrap(), *expected, "{}", text);
+        }
+        assert_eq!(evaluate(&file, 1, "lookup(Name, 'Sword': -1, 0)").unwrap(),
+                   ShnCell::SignedInteger(0));
+        assert!(evaluate(&file, 2, "lookup(ID, 1: 'one', 2: 'two')").is_err());
+    }
+
+    #[test]
+    fn overflow() {
+        let file = file();
+        let min = ShnCell::SignedInteger(i32::min_value());
+        assert_eq!(evaluate(&file, 2, "-Gold").unwrap(),
+                   ShnCell::UnsignedInteger(1 << 31));
+        for text in &["Level / 0", "Level % 0", "Level / (Gold - Gold)"] {
+            assert!(evaluate(&file, 0, text).is_err(), "{}", text);
+        }
+        assert!(evaluate(&file, 0, "Rate / 0").is_err());
+        assert!(evaluate(&file, 0, "Rate / 0.0").is_err());
+
+        // Overflows of i64, built without parsing
+        let big = ShnExpr::binary(ShnOperator::Multiply,
+                                  ShnExpr::column("Gold"),
+                                  ShnExpr::literal(1u32 << 31));
+        let row = &file.data[2];
+        assert_eq!(big.evaluate(row).unwrap(),
+                   ShnCell::SingleFloatingPoint(-(1i64 << 62) as f32));
+        let twice = ShnExpr::binary(ShnOperator::Add, big.clone(), big.clone());
+        let negated = ShnExpr::Negate(Box::new(twice.clone()));
+        assert!(twice.evaluate(row).is_ok());
+        match negated.evaluate(row) {
+            Err(ShnError::InvalidValue(_)) => {},
+            r => panic!("{:?}", r),
+        }
+        let more = ShnExpr::binary(ShnOperator::Subtract, twice, ShnExpr::literal(1u8));
+        match more.evaluate(row) {
+            Err(ShnError::InvalidValue(_)) => {},
+            r => panic!("{:?}", r),
+        }
+        assert!(ShnExpr::Negate(Box::new(ShnExpr::literal(min))).evaluate(row).is_ok());
+    }
+
+    #[test]
+    fn evaluate_as() {
+        let file = file();
+        let row = &file.data[0];
+        let parse = |text| ShnExpr::parse(text, &file.schema).unwrap();
+        assert_eq!(parse("Level * 3").evaluate_as(row, &ShnDataType::Byte).unwrap(),
+                   ShnCell::Byte(210));
+        assert!(parse("Level * 4").evaluate_as(row, &ShnDataType::Byte).is_err());
+        assert!(parse("Rate").evaluate_as(row, &ShnDataType::Byte).is_err());
+        assert_eq!(parse("round(Rate)").evaluate_as(row, &ShnDataType::Byte).unwrap(),
+                   ShnCell::Byte(1));
+        assert_eq!(parse("concat(Name, '!')")
+                   .evaluate_as(row, &ShnDataType::StringFixedLen).unwrap(),
+                   ShnCell::StringFixedLen("Sword!".to_owned()));
+        assert!(parse("Name").evaluate_as(row, &ShnDataType::SignedInteger).is_err());
+        assert!(parse("ID").evaluate_as(row, &ShnDataType::StringFixedLen).is_err());
+        assert!(parse("Gold * 10000000000000000000.0 * 10000000000000000000.0")
+                .evaluate_as(row, &ShnDataType::SingleFloatingPoint).is_err());
+    }
+
+    #[test]
+    fn parse_errors() {
+        let schema = file().schema.clone();
+        let cases = [
+            ("", 0, "expected an expression, found end of input"),
+            ("Nope + 1", 0, "unknown column `Nope`"),
+            ("Level +", 7, "expected an expression, found end of input"),
+            ("Name + 1", 0, "type mismatch, expected a number"),
+            ("1 * -Name", 5, "type mismatch, expected a number"),
+            ("Level 1", 6, "expected end of expression, found `1`"),
+            ("sqrt(Level)", 0, "unknown function `sqrt`"),
+            ("min(Level)", 0, "`min` takes 2 arguments"),
+            ("round(Name)", 6, "type mismatch, expected a number"),
+            ("lookup(ID, Level: 1)", 11, "lookup keys need to be integers or strings"),
+            ("lookup(ID, 1: Level)", 14, "lookup values need to be constant"),
+            ("lookup(ID, 1: 2, 3: 'x')", 20,
+             "type mismatch, lookup values differ in type"),
+            ("lookup(ID, 1: 2, 'x')", 17,
+             "type mismatch, lookup values differ in type"),
+        ];
+        for &(text, offset, message) in &cases {
+            assert_eq!(error_offset(ShnExpr::parse(text, &schema)),
+                       (offset, message.to_owned()), "{}", text);
+        }
+        let nested = format!("{}Level{}", "(".repeat(100_000), ")".repeat(100_000));
+        assert!(ShnExpr::parse(&nested, &schema).is_err());
+        let negated = format!("{}Level", "-".repeat(100_000));
+        assert!(ShnExpr::parse(&negated, &schema).is_err());
+        let calls = format!("{}Level{}", "round(".repeat(100_000), ")".repeat(100_000));
+        assert!(ShnExpr::parse(&calls, &schema).is_err());
+    }
+
+    #[test]
+    fn update_where() {
+        let mut file = file();
+        let expr = ShnExpr::parse("Level + 10", &file.schema).unwrap();
+        let filter = ShnPredicate::compare("Level", ShnComparison::Less, 80u8);
+        let summary = file.update_where(Some(&filter), "Level", &expr).unwrap();
+        assert_eq!(summary.matched, 2);
+        assert_eq!(summary.changes, vec![
+            ShnCellChange { row: 0, old: ShnCell::Byte(70), new: ShnCell::Byte(80) },
+            ShnCellChange { row: 2, old: ShnCell::Byte(1), new: ShnCell::Byte(11) },
+        ]);
+
+        // Fails as a whole, listing every failed row
+        let expr = ShnExpr::parse("Level * 4", &file.schema).unwrap();
+        match file.update_where(None, "Level", &expr) {
+            Err(ShnError::RowErrors(ref errors)) => {
+                assert_eq!(errors.iter().map(|&(r, _)| r).collect::<Vec<_>>(),
+                           vec![0, 1]);
+            },
+            r => panic!("{:?}", r),
+        }
+        assert_eq!(file.data[0].data[3], ShnCell::Byte(80));
+
+        // Duplicate keys restore the old values
+        file.index_by("ID").unwrap();
+        let expr = ShnExpr::literal(7u32);
+        assert!(file.update_where(None, "ID", &expr).is_err());
+        assert_eq!(file.data[1].data[1], ShnCell::UnsignedInteger(2));
+        assert!(file.get_by_key("ID", 3u32).is_some());
+
+        assert!(file.update_where(None, "__ID__", &expr).is_err());
+        match file.update_where(None, "Nope", &expr) {
+            Err(ShnError::UnknownColumn(ref c)) => assert_eq!(c, "Nope"),
+            r => panic!("{:?}", r),
+        }
+    }
+}
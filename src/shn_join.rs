@@ -0,0 +1,359 @@
+use ::std::collections::HashMap;
+use ::std::sync::Arc;
+
+use super::shn::{ ShnDataType, ShnCell, ShnSchema, ShnRow, ShnKey, ShnFile,
+                  ShnError, Result };
+
+/// Kinds of joins, see `ShnFile::join`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ShnJoinKind {
+    /// Only rows with a matching row in the other file
+    Inner,
+    /// All rows of the left file, whether they have a match or not
+    Left,
+}
+
+/// Options for joining two files, see `ShnFile::join`.
+#[derive(Clone, Debug)]
+pub struct JoinOptions {
+    /// The kind of join
+    pub kind:           ShnJoinKind,
+    /// Prepended to the names of left columns which clash with the name of a
+    /// right column
+    pub left_prefix:    String,
+    /// Prepended to the names of right columns which clash with the name of
+    /// a left column
+    pub right_prefix:   String,
+}
+
+impl JoinOptions {
+    /// Constructs new `JoinOptions` for an inner join, which keep the names
+    /// of left columns and prefix clashing right columns with `R_`.
+    pub fn new() -> Self {
+        JoinOptions {
+            kind:           ShnJoinKind::Inner,
+            left_prefix:    String::new(),
+            right_prefix:   "R_".to_owned(),
+        }
+    }
+}
+
+impl Default for JoinOptions {
+    fn default() -> Self {
+        JoinOptions::new()
+    }
+}
+
+/// The side a column of a `ShnJoin` comes from.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Side {
+    Left,
+    Right,
+}
+
+/// The result of joining two files, borrowing their rows.
+///
+/// The schema holds the `__ID__` pseudo-column, the data columns of the
+/// left file, and the data columns of the right file except its key column,
+/// since it matches the left one.
+#[derive(Clone, Debug)]
+pub struct ShnJoin<'a> {
+    left:       &'a ShnFile,
+    right:      &'a ShnFile,
+    schema:     Arc<ShnSchema>,
+    /// Where each column of `schema` comes from, `None` for `__ID__` if the
+    /// left file has none
+    sources:    Vec<Option<(Side, usize)>>,
+    rows:       Vec<(usize, Option<usize>)>,
+}
+
+impl ShnFile {
+    /// Joins this file with `right`, pairing rows whose cells in `column`
+    /// and `right_column` hold the same key. Rows of the left file appear in
+    /// order, once for every matching right row. Uses the index over
+    /// `right_column` if there is one, and builds a temporary one otherwise.
+    pub fn join<'a>(&'a self,
+                    column: &str,
+                    right: &'a ShnFile,
+                    right_column: &str,
+                    options: &JoinOptions)
+                    -> Result<ShnJoin<'a>> {
+        let position = |file: &ShnFile, name: &str| {
+            match file.schema.columns.iter().position(|c| c.name == name) {
+                Some(p) if file.schema.columns[p].data_type
+                    == ShnDataType::SingleFloatingPoint
+                    => Err(ShnError::InvalidSchema),
+                Some(p) => Ok(p),
+                None    => Err(ShnError::UnknownColumn(name.to_owned())),
+            }
+        };
+        let left_key = try!(position(self, column));
+        let right_key = try!(position(right, right_column));
+
+        let mut schema = ShnSchema::with_id_column();
+        let mut sources = vec![self.schema.columns.iter()
+                               .position(|c| c.is_id())
+                               .map(|p| (Side::Left, p))];
+        let clashes = |name: &str, other: &ShnSchema, skip: Option<usize>| {
+            other.columns.iter()
+                .enumerate()
+                .any(|(i, c)| !c.is_id() && Some(i) != skip && c.name == name)
+        };
+        for (i, c) in self.schema.columns.iter().enumerate() {
+            if c.is_id() {
+                continue;
+            }
+            let mut c = c.clone();
+            if clashes(&c.name, &right.schema, Some(right_key)) {
+                c.name = format!("{}{}", options.left_prefix, c.name);
+            }
+            schema.columns.push(c);
+            sources.push(Some((Side::Left, i)));
+        }
+        for (i, c) in right.schema.columns.iter().enumerate() {
+            if c.is_id() || i == right_key {
+                continue;
+            }
+            let mut c = c.clone();
+            if clashes(&c.name, &self.schema, None) {
+                c.name = format!("{}{}", options.right_prefix, c.name);
+            }
+            schema.columns.push(c);
+            sources.push(Some((Side::Right, i)));
+        }
+        for (i, c) in schema.columns.iter().enumerate() {
+            if schema.columns[..i].iter().any(|o| o.name == c.name) {
+                return Err(ShnError::DuplicateColumn(c.name.clone()));
+            }
+        }
+
+        let index = if right.is_indexed(right_column) {
+            None
+        } else {
+            let mut index: HashMap<ShnKey, Vec<usize>> = HashMap::new();
+            for (i, row) in right.data.iter().enumerate() {
+                if let Some(key) = ShnKey::from_cell(&row.data[right_key]) {
+                    index.entry(key).or_insert_with(Vec::new).push(i);
+                }
+            }
+            Some(index)
+        };
+
+        let mut rows = Vec::new();
+        for (i, row) in self.data.iter().enumerate() {
+            let found = match (ShnKey::from_cell(&row.data[left_key]), &index) {
+                (Some(key), &Some(ref index))
+                    => index.get(&key).cloned().unwrap_or_default(),
+                (Some(key), &None)
+                    => right.position_by_key(right_column, key)
+                        .into_iter()
+                        .collect(),
+                (None, _) => Vec::new(),
+            };
+            if found.is_empty() {
+                if options.kind == ShnJoinKind::Left {
+                    rows.push((i, None));
+                }
+            } else {
+                rows.extend(found.into_iter().map(|r| (i, Some(r))));
+            }
+        }
+
+        Ok(ShnJoin {
+            left:       self,
+            right:      right,
+            schema:     Arc::new(schema),
+            sources:    sources,
+            rows:       rows,
+        })
+    }
+}
+
+impl<'a> ShnJoin<'a> {
+    /// Returns the merged schema.
+    pub fn schema(&self) -> &Arc<ShnSchema> {
+        &self.schema
+    }
+
+    /// Returns the number of rows.
+    pub fn len(&self) -> usize {
+        self.rows.len()
+    }
+
+    /// Returns whether there are no rows.
+    pub fn is_empty(&self) -> bool {
+        self.rows.is_empty()
+    }
+
+    /// Returns the positions of the left and right row of every row. The
+    /// right row is missing for unmatched rows of left joins.
+    pub fn pairs(&self) -> &[(usize, Option<usize>)] {
+        &self.rows
+    }
+
+    /// Returns the cell of row `row` in column `column` of the merged
+    /// schema, or `None` for the right columns of unmatched rows.
+    pub fn get(&self, row: usize, column: usize) -> Option<&'a ShnCell> {
+        let (left, right) = match self.rows.get(row) {
+            Some(&r)    => r,
+            None        => return None,
+        };
+        match self.sources.get(column).and_then(|s| *s) {
+            Some((Side::Left, c))   => self.left.data[left].data.get(c),
+            Some((Side::Right, c))  => {
+                right.and_then(|r| self.right.data[r].data.get(c))
+            },
+            None                    => None,
+        }
+    }
+
+    /// Returns the cell of row `row` in the column named `column`, see
+    /// `get`.
+    pub fn get_by_name(&self, row: usize, column: &str) -> Option<&'a ShnCell> {
+        self.schema.columns.iter()
+            .position(|c| c.name == column)
+            .and_then(|c| self.get(row, c))
+    }
+
+    /// Copies the joined rows into a new file with the headers of the left
+    /// file. The `__ID__` pseudo-column is numbered from zero, and the right
+    /// columns of unmatched rows hold zero or empty strings.
+    pub fn to_file(&self) -> Result<ShnFile> {
+        if self.rows.len() > u16::max_value() as usize + 1 {
+            return Err(ShnError::InvalidSchema);
+        }
+        let mut file = ShnFile::new(self.left.crypt_header,
+                                    self.left.header,
+                                    self.schema.clone());
+        for row in 0..self.rows.len() {
+            let mut cells = Vec::with_capacity(self.schema.columns.len());
+            for (c, column) in self.schema.columns.iter().enumerate() {
+                cells.push(if column.is_id() {
+                    ShnCell::UnsignedShort(row as u16)
+                } else {
+                    self.get(row, c)
+                        .cloned()
+                        .unwrap_or_else(|| column.data_type.default_cell())
+                });
+            }
+            file.data.push(ShnRow {
+                schema: self.schema.clone(),
+                data:   cells,
+            });
+        }
+        Ok(file)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::shn::ShnColumn;
+    use super::super::shn_test_util::table;
+
+    fn items() -> ShnFile {
+        table(vec![ShnColumn::new_unsigned_integer("ID"),
+                   ShnColumn::new_string_fixed_len("Name", 8),
+                   ShnColumn::new_single_floating_point("Rate")],
+              [(1, "Sword"), (2, "Axe"), (3, "Bow")].iter()
+              .map(|&(id, name)| vec![ShnCell::UnsignedInteger(id),
+                                      ShnCell::StringFixedLen(name.to_owned()),
+                                      ShnCell::SingleFloatingPoint(0.5)])
+              .collect())
+    }
+
+    fn prices() -> ShnFile {
+        table(vec![ShnColumn::new_unsigned_short("ItemID"),
+                   ShnColumn::new_string_fixed_len("Name", 8),
+                   ShnColumn::new_unsigned_integer("Price")],
+              [(3, "shop", 30), (1, "shop", 10), (9, "shop", 90)].iter()
+              .map(|&(id, name, price)| vec![ShnCell::UnsignedShort(id),
+                                             ShnCell::StringFixedLen(name.to_owned()),
+                                             ShnCell::UnsignedInteger(price)])
+              .collect())
+    }
+
+    fn names(join: &ShnJoin) -> Vec<String> {
+        join.schema().columns.iter().map(|c| c.name.clone()).collect()
+    }
+
+    #[test]
+    fn inner_join() {
+        let items = items();
+        let mut prices = prices();
+        let options = JoinOptions::new();
+        let pairs = {
+            let join = items.join("ID", &prices, "ItemID", &options).unwrap();
+            assert_eq!(names(&join),
+                       vec!["__ID__", "ID", "Name", "Rate", "R_Name", "Price"]);
+            assert_eq!(join.len(), 2);
+            assert_eq!(join.get_by_name(1, "Name"),
+                       Some(&ShnCell::StringFixedLen("Bow".to_owned())));
+            assert_eq!(join.get_by_name(1, "Price"), Some(&ShnCell::UnsignedInteger(30)));
+            assert_eq!(join.get(0, 0), Some(&ShnCell::UnsignedShort(0)));
+            assert_eq!(join.get(0, 6), None);
+            assert_eq!(join.get(2, 1), None);
+            assert_eq!(join.get_by_name(0, "ItemID"), None);
+            join.pairs().to_vec()
+        };
+        assert_eq!(pairs, vec![(0, Some(1)), (2, Some(0))]);
+
+        // The same through the index of the right file
+        prices.index_by("ItemID").unwrap();
+        let join = items.join("ID", &prices, "ItemID", &options).unwrap();
+        assert_eq!(join.pairs(), &pairs[..]);
+    }
+
+    #[test]
+    fn left_join() {
+        let items = items();
+        let mut prices = prices();
+        prices.append_row(ShnRow {
+            schema: prices.schema.clone(),
+            data:   vec![ShnCell::UnsignedShort(3), ShnCell::UnsignedShort(1),
+                         ShnCell::StringFixedLen("fair".to_owned()),
+                         ShnCell::UnsignedInteger(12)],
+        }).unwrap();
+        let mut options = JoinOptions::new();
+        options.kind = ShnJoinKind::Left;
+        options.left_prefix = "L_".to_owned();
+        options.right_prefix = String::new();
+        let join = items.join("ID", &prices, "ItemID", &options).unwrap();
+        assert_eq!(names(&join),
+                   vec!["__ID__", "ID", "L_Name", "Rate", "Name", "Price"]);
+        assert_eq!(join.pairs(), &[(0, Some(1)), (0, Some(3)), (1, None), (2, Some(0))]);
+        assert_eq!(join.get_by_name(2, "Price"), None);
+
+        let file = join.to_file().unwrap();
+        assert_eq!(file.data.len(), 4);
+        assert_eq!(file.data[2].data,
+                   vec![ShnCell::UnsignedShort(2), ShnCell::UnsignedInteger(2),
+                        ShnCell::StringFixedLen("Axe".to_owned()),
+                        ShnCell::SingleFloatingPoint(0.5),
+                        ShnCell::StringFixedLen(String::new()),
+                        ShnCell::UnsignedInteger(0)]);
+        assert_eq!(file.data[1].data[5], ShnCell::UnsignedInteger(12));
+    }
+
+    #[test]
+    fn join_errors() {
+        let items = items();
+        let prices = prices();
+        let options = JoinOptions::new();
+        match items.join("Nope", &prices, "ItemID", &options) {
+            Err(ShnError::UnknownColumn(ref c)) => assert_eq!(c, "Nope"),
+            r => panic!("{:?}", r.map(|j| j.len())),
+        }
+        assert!(items.join("ID", &prices, "Nope", &options).is_err());
+        match items.join("Rate", &prices, "ItemID", &options) {
+            Err(ShnError::InvalidSchema) => {},
+            r => panic!("{:?}", r.map(|j| j.len())),
+        }
+        let mut options = JoinOptions::new();
+        options.right_prefix = String::new();
+        match items.join("ID", &prices, "ItemID", &options) {
+            Err(ShnError::DuplicateColumn(ref c)) => assert_eq!(c, "Name"),
+            r => panic!("{:?}", r.map(|j| j.len())),
+        }
+    }
+}
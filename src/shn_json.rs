@@ -0,0 +1,349 @@
+use ::std::io::{ self, Write };
+
+use super::shn::ShnCell;
+
+/// How deeply arrays and objects may be nested, to keep hostile documents
+/// from overflowing the stack.
+const MAX_DEPTH: usize = 128;
+
+/// Writes `text` as a quoted JSON string.
+pub fn write_string<W: Write>(writer: &mut W, text: &str) -> io::Result<()> {
+    try!(writer.write_all(b"\""));
+    for c in text.chars() {
+        match c {
+            '"'                 => try!(writer.write_all(b"\\\"")),
+            '\\'                => try!(writer.write_all(b"\\\\")),
+            '\n'                => try!(writer.write_all(b"\\n")),
+            '\r'                => try!(writer.write_all(b"\\r")),
+            '\t'                => try!(writer.write_all(b"\\t")),
+            c if c < ' '        => try!(write!(writer, "\\u{:04x}", c as u32)),
+            c                   => try!(write!(writer, "{}", c)),
+        }
+    }
+    writer.write_all(b"\"")
+}
+
+/// Writes the value of `cell` as a JSON string or number. Non-finite floating
+/// point values are written as `null`, since JSON has no representation
+/// for them.
+pub fn write_cell<W: Write>(writer: &mut W, cell: &ShnCell) -> io::Result<()> {
+    match *cell {
+        ShnCell::StringFixedLen(ref s) |
+        ShnCell::StringZeroTerminated(ref s)    => write_string(writer, s),
+        ShnCell::SingleFloatingPoint(v) if !v.is_finite()
+            => writer.write_all(b"null"),
+        ref cell                                => write!(writer, "{}", cell),
+    }
+}
+
+/// A parsed JSON value. Objects keep the order of their members.
+#[derive(Clone, PartialEq, Debug)]
+pub enum JsonValue {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<JsonValue>),
+    Object(Vec<(String, JsonValue)>),
+}
+
+/// Parses a JSON document, returning the byte offset and a description of
+/// the first error.
+pub fn parse(text: &str) -> ::std::result::Result<JsonValue, (usize, String)> {
+    let mut parser = JsonParser { text: text, pos: 0, depth: 0 };
+    let value = try!(parser.value());
+    parser.whitespace();
+    if parser.pos < text.len() {
+        return Err(parser.error("end of document"));
+    }
+    Ok(value)
+}
+
+struct JsonParser<'a> {
+    text:   &'a str,
+    pos:    usize,
+    depth:  usize,
+}
+
+impl<'a> JsonParser<'a> {
+    fn error(&self, expected: &str) -> (usize, String) {
+        match self.peek() {
+            Some(c) => (self.pos, format!("expected {}, found `{}`", expected, c)),
+            None    => (self.pos, format!("expected {}, found end of input",
+                                          expected)),
+        }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.text[self.pos..].chars().next()
+    }
+
+    fn whitespace(&mut self) {
+        while let Some(c) = self.peek() {
+            if !c.is_whitespace() {
+                break;
+            }
+            self.pos += c.len_utf8();
+        }
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        self.whitespace();
+        if self.peek() == Some(c) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, c: char) -> ::std::result::Result<(), (usize, String)> {
+        if self.eat(c) {
+            Ok(())
+        } else {
+            Err(self.error(&format!("`{}`", c)))
+        }
+    }
+
+    fn value(&mut self) -> ::std::result::Result<JsonValue, (usize, String)> {
+        self.whitespace();
+        if self.depth >= MAX_DEPTH {
+            return Err((self.pos, "nested too deeply".to_owned()));
+        }
+        self.depth += 1;
+        let value = self.nested_value();
+        self.depth -= 1;
+        value
+    }
+
+    fn nested_value(&mut self) -> ::std::result::Result<JsonValue, (usize, String)> {
+        let rest = &self.text[self.pos..];
+        for &(word, ref value) in &[("null", JsonValue::Null),
+                                    ("true", JsonValue::Bool(true)),
+                                    ("false", JsonValue::Bool(false))] {
+            if rest.starts_with(word) {
+                self.pos += word.len();
+                return Ok(value.clone());
+            }
+        }
+        match self.peek() {
+            Some('"') => self.string().map(JsonValue::String),
+            Some('[') => {
+                self.pos += 1;
+                let mut values = Vec::new();
+                if !self.eat(']') {
+                    loop {
+                        values.push(try!(self.value()));
+                        if !self.eat(',') {
+                            break;
+                        }
+                    }
+                    try!(self.expect(']'));
+                }
+                Ok(JsonValue::Array(values))
+            },
+            Some('{') => {
+                self.pos += 1;
+                let mut members = Vec::new();
+                if !self.eat('}') {
+                    loop {
+                        self.whitespace();
+                        let key = try!(self.string());
+                        try!(self.expect(':'));
+                        members.push((key, try!(self.value())));
+                        if !self.eat(',') {
+                            break;
+                        }
+                    }
+                    try!(self.expect('}'));
+                }
+                Ok(JsonValue::Object(members))
+            },
+            Some(c) if c == '-' || c.is_ascii_digit() => {
+                let len = rest.find(|c: char| {
+                    !(c.is_ascii_digit() || c == '-' || c == '+' || c == '.'
+                      || c == 'e' || c == 'E')
+                }).unwrap_or(rest.len());
+                match rest[..len].parse() {
+                    Ok(v)   => {
+                        self.pos += len;
+                        Ok(JsonValue::Number(v))
+                    },
+                    Err(_)  => Err((self.pos, "invalid number".to_owned())),
+                }
+            },
+            _ => Err(self.error("a value")),
+        }
+    }
+
+    fn string(&mut self) -> ::std::result::Result<String, (usize, String)> {
+        if self.peek() != Some('"') {
+            return Err(self.error("a string"));
+        }
+        let start = self.pos;
+        self.pos += 1;
+        let mut text = String::new();
+        loop {
+            let c = match self.peek() {
+                Some(c) => c,
+                None    => return Err((start, "unterminated string".to_owned())),
+            };
+            self.pos += c.len_utf8();
+            match c {
+                '"'     => return Ok(text),
+                '\\'    => {
+                    let escaped = match self.peek() {
+                        Some(e) => e,
+                        None    => continue,
+                    };
+                    self.pos += escaped.len_utf8();
+                    text.push(match escaped {
+                        '"' | '\\' | '/' => escaped,
+                        'n' => '\n',
+                        'r' => '\r',
+                        't' => '\t',
+                        'b' => '\u{8}',
+                        'f' => '\u{c}',
+                        'u' => try!(self.unicode_escape()),
+                        _   => return Err((self.pos - 1 - escaped.len_utf8(),
+                                           "invalid escape".to_owned())),
+                    });
+                },
+                c       => text.push(c),
+            }
+        }
+    }
+
+    /// Reads the digits of a `\u` escape, combining surrogate pairs.
+    fn unicode_escape(&mut self) -> ::std::result::Result<char, (usize, String)> {
+        let start = self.pos - 2;
+        let invalid = || Err((start, "invalid unicode escape".to_owned()));
+        let high = match self.hex() {
+            Some(h) => h,
+            None    => return invalid(),
+        };
+        let code = match high {
+            0xd800..=0xdbff => {
+                if !self.text[self.pos..].starts_with("\\u") {
+                    return invalid();
+                }
+                self.pos += 2;
+                match self.hex() {
+                    Some(low @ 0xdc00..=0xdfff)
+                        => 0x10000 + ((high - 0xd800) << 10) + (low - 0xdc00),
+                    _   => return invalid(),
+                }
+            },
+            h => h,
+        };
+        match ::std::char::from_u32(code) {
+            Some(c) => Ok(c),
+            None    => invalid(),
+        }
+    }
+
+    /// Reads four hexadecimal digits.
+    fn hex(&mut self) -> Option<u32> {
+        let digits = match self.text.get(self.pos..self.pos + 4) {
+            Some(d) if d.chars().all(|c| c.is_ascii_hexdigit()) => d,
+            _ => return None,
+        };
+        self.pos += 4;
+        u32::from_str_radix(digits, 16).ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn written(cell: &ShnCell) -> String {
+        let mut out = Vec::new();
+        write_cell(&mut out, cell).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    fn error(text: &str) -> (usize, String) {
+        parse(text).unwrap_err()
+    }
+
+    #[test]
+    fn write() {
+        assert_eq!(written(&ShnCell::StringFixedLen("a\"b\\c\n\r\t\u{1}é".to_owned())),
+                   "\"a\\\"b\\\\c\\n\\r\\t\\u0001é\"");
+        assert_eq!(written(&ShnCell::SignedShort(-3)), "-3");
+        assert_eq!(written(&ShnCell::SingleFloatingPoint(0.5)), "0.5");
+        assert_eq!(written(&ShnCell::SingleFloatingPoint(::std::f32::NAN)), "null");
+        assert_eq!(written(&ShnCell::SingleFloatingPoint(::std::f32::INFINITY)),
+                   "null");
+        let text = "a\"b\\c\n\u{1}é\u{1f600}";
+        assert_eq!(parse(&written(&ShnCell::StringZeroTerminated(text.to_owned()))),
+                   Ok(JsonValue::String(text.to_owned())));
+    }
+
+    #[test]
+    fn values() {
+        assert_eq!(parse(" { \"a\" : [1, -2.5, 3e2, true, false, null],\n\"b\": {} } "),
+                   Ok(JsonValue::Object(vec![
+                       ("a".to_owned(), JsonValue::Array(vec![
+                           JsonValue::Number(1.0), JsonValue::Number(-2.5),
+                           JsonValue::Number(300.0), JsonValue::Bool(true),
+                           JsonValue::Bool(false), JsonValue::Null])),
+                       ("b".to_owned(), JsonValue::Object(Vec::new())),
+                   ])));
+        // Members keep their order
+        assert_eq!(parse("{\"z\": 1, \"a\": 2}"),
+                   Ok(JsonValue::Object(vec![("z".to_owned(), JsonValue::Number(1.0)),
+                                             ("a".to_owned(), JsonValue::Number(2.0))])));
+        assert_eq!(parse("[]"), Ok(JsonValue::Array(Vec::new())));
+    }
+
+    #[test]
+    fn escapes() {
+        assert_eq!(parse(r#""\"\\\/\b\f\n\r\té€""#),
+                   Ok(JsonValue::String("\"\\/\u{8}\u{c}\n\r\t\u{e9}\u{20ac}"
+                                        .to_owned())));
+        assert_eq!(parse(r#""\ud83d\uDE00""#),
+                   Ok(JsonValue::String("\u{1f600}".to_owned())));
+        assert_eq!(error(r#"["\ud83d"]"#), (2, "invalid unicode escape".to_owned()));
+        assert_eq!(error(r#""\ud83dx""#), (1, "invalid unicode escape".to_owned()));
+        assert_eq!(error(r#""\ud83dA""#), (1, "invalid unicode escape".to_owned()));
+        assert_eq!(error(r#""\ude00""#), (1, "invalid unicode escape".to_owned()));
+        assert_eq!(error(r#""\u12""#), (1, "invalid unicode escape".to_owned()));
+        assert_eq!(error(r#""\u+123""#), (1, "invalid unicode escape".to_owned()));
+        assert_eq!(error(r#""ab\q""#), (3, "invalid escape".to_owned()));
+    }
+
+    #[test]
+    fn numbers() {
+        assert_eq!(parse("-0"), Ok(JsonValue::Number(0.0)));
+        assert_eq!(parse("1.5E-3"), Ok(JsonValue::Number(0.0015)));
+        assert_eq!(parse("4294967295"), Ok(JsonValue::Number(4294967295.0)));
+        assert_eq!(error("[1, 2-3]"), (4, "invalid number".to_owned()));
+        assert_eq!(error("--1"), (0, "invalid number".to_owned()));
+        assert_eq!(error("1 2"), (2, "expected end of document, found `2`".to_owned()));
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(error(""), (0, "expected a value, found end of input".to_owned()));
+        assert_eq!(error("[1,"), (3, "expected a value, found end of input".to_owned()));
+        assert_eq!(error("[1 2]"), (3, "expected `]`, found `2`".to_owned()));
+        assert_eq!(error("{\"a\" 1}"), (5, "expected `:`, found `1`".to_owned()));
+        assert_eq!(error("{1: 2}"), (1, "expected a string, found `1`".to_owned()));
+        assert_eq!(error("  \"abc"), (2, "unterminated string".to_owned()));
+        assert_eq!(error("\"ab\\"), (0, "unterminated string".to_owned()));
+        assert_eq!(error("[é]"), (1, "expected a value, found `é`".to_owned()));
+        assert_eq!(error("[\"é\" x]"), (6, "expected `]`, found `x`".to_owned()));
+    }
+
+    #[test]
+    fn nesting() {
+        let nested = |depth| format!("{}{}", "[".repeat(depth), "]".repeat(depth));
+        assert!(parse(&nested(MAX_DEPTH)).is_ok());
+        assert_eq!(error(&nested(100_000)),
+                   (MAX_DEPTH, "nested too deeply".to_owned()));
+        let objects = "{\"a\":".repeat(100_000);
+        assert_eq!(error(&objects).1, "nested too deeply");
+    }
+}
@@ -0,0 +1,482 @@
+use ::std::collections::BTreeMap;
+use ::std::io::Read;
+
+use super::shn::{ ShnDataType, ShnCell, ShnSchema, ShnError, Result };
+use super::shn_json::{ self, JsonValue };
+use super::shn_relation::ShnRelation;
+
+/// Documentation of a single column, see `ShnMetadata`.
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct ShnColumnMetadata {
+    /// What the column means
+    pub description:    Option<String>,
+    /// The unit of the values, like `ms` or `%`
+    pub unit:           Option<String>,
+    /// Labels of enumeration values
+    pub labels:         BTreeMap<i64, String>,
+    /// Names of bit flags, keyed by bit position from `0` to `31`
+    pub flags:          BTreeMap<u32, String>,
+    /// The table and column the values reference
+    pub references:     Option<(String, String)>,
+}
+
+impl ShnColumnMetadata {
+    /// Returns the label of the value of `cell`. Enumeration labels take
+    /// precedence, flags are joined by `|`, with unnamed bits appended as a
+    /// number. Returns `None` for values without any label.
+    pub fn label(&self, cell: &ShnCell) -> Option<String> {
+        let value = match cell.as_i64() {
+            Some(v) => v,
+            None    => return None,
+        };
+        if let Some(label) = self.labels.get(&value) {
+            return Some(label.clone());
+        }
+        if self.flags.is_empty() || value <= 0 {
+            return None;
+        }
+        let mut names = Vec::new();
+        let mut rest = value;
+        for (&bit, name) in &self.flags {
+            if value & (1 << bit) != 0 {
+                names.push(name.clone());
+                rest &= !(1 << bit);
+            }
+        }
+        if names.is_empty() {
+            return None;
+        }
+        if rest != 0 {
+            names.push(rest.to_string());
+        }
+        Some(names.join(" | "))
+    }
+
+    /// Parses `text` into a cell of type `data_type`, accepting enumeration
+    /// labels and flag names joined by `|` besides the plain values accepted
+    /// by `ShnCell::parse`.
+    pub fn parse_value(&self, text: &str, data_type: &ShnDataType)
+                       -> Result<ShnCell> {
+        let plain = ShnCell::parse(text, data_type);
+        if plain.is_ok() || text.is_empty() {
+            return plain;
+        }
+        let trimmed = text.trim();
+        let value = match self.labels.iter().find(|&(_, l)| l == trimmed) {
+            Some((&v, _)) => Some(v),
+            None if !self.flags.is_empty() => {
+                let mut value = Some(0i64);
+                for part in trimmed.split('|').map(|p| p.trim()) {
+                    let bits = self.flags.iter()
+                        .find(|&(_, n)| n == part)
+                        .map(|(&bit, _)| 1i64 << bit)
+                        .or_else(|| part.parse().ok());
+                    value = match (value, bits) {
+                        (Some(v), Some(b))  => Some(v | b),
+                        _                   => None,
+                    };
+                }
+                value
+            },
+            None => None,
+        };
+        match value {
+            Some(v) => ShnCell::parse(&v.to_string(), data_type),
+            None    => plain,
+        }
+    }
+}
+
+/// Documentation of a table and its columns, see `ShnMetadata`.
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct ShnTableMetadata {
+    /// What the table holds
+    pub description:    Option<String>,
+    /// The documented columns, keyed by name
+    pub columns:        BTreeMap<String, ShnColumnMetadata>,
+}
+
+impl ShnTableMetadata {
+    /// Returns the documentation of the column named `name`.
+    pub fn column(&self, name: &str) -> Option<&ShnColumnMetadata> {
+        self.columns.get(name)
+    }
+
+    /// Returns the label of `cell` in the column named `column`, or the
+    /// cell's plain value if it has none.
+    pub fn display(&self, column: &str, cell: &ShnCell) -> String {
+        self.column(column)
+            .and_then(|c| c.label(cell))
+            .unwrap_or_else(|| cell.to_string())
+    }
+
+    /// Returns the names of documented columns missing from `schema`, which
+    /// usually means the metadata is outdated.
+    pub fn unknown_columns(&self, schema: &ShnSchema) -> Vec<&str> {
+        self.columns.keys()
+            .filter(|n| !schema.columns.iter().any(|c| c.name == **n))
+            .map(|n| &n[..])
+            .collect()
+    }
+}
+
+/// Documentation of tables kept beside the files, with descriptions, units,
+/// labels of enumeration values, names of bit flags and foreign keys of
+/// columns. Stored as JSON, with every member being optional:
+///
+/// ```text
+/// {
+///   "ItemInfo": {
+///     "description": "Items as known to the client",
+///     "columns": {
+///       "ItemAuctionGroup": {
+///         "description": "Category in the auction house",
+///         "labels": { "0": "None", "1": "Weapon" }
+///       },
+///       "ItemUseFlags": { "flags": { "0": "Tradable", "3": "Storable" } },
+///       "ID": { "references": "ItemInfoServer.ID" },
+///       "MinWC": { "unit": "damage" }
+///     }
+///   }
+/// }
+/// ```
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct ShnMetadata {
+    /// The documented tables, keyed by name
+    pub tables:     BTreeMap<String, ShnTableMetadata>,
+}
+
+impl ShnMetadata {
+    /// Constructs empty metadata.
+    pub fn new() -> Self {
+        ShnMetadata::default()
+    }
+
+    /// Returns the documentation of the table named `name`.
+    pub fn table(&self, name: &str) -> Option<&ShnTableMetadata> {
+        self.tables.get(name)
+    }
+
+    /// Returns a relation for every column referencing another table, to
+    /// be added to a `ShnDatabase`.
+    pub fn relations(&self) -> Vec<ShnRelation> {
+        let mut relations = Vec::new();
+        for (table, metadata) in &self.tables {
+            for (column, c) in &metadata.columns {
+                if let Some((ref target, ref target_column)) = c.references {
+                    relations.push(ShnRelation::new(table, column, target,
+                                                    target_column));
+                }
+            }
+        }
+        relations
+    }
+
+    /// Reads metadata from JSON text.
+    pub fn read_from<R: Read>(mut source: R) -> Result<ShnMetadata> {
+        let mut text = String::new();
+        try!(source.read_to_string(&mut text).map_err(ShnError::Io));
+        ShnMetadata::from_json(&text)
+    }
+
+    /// Parses metadata from JSON text.
+    pub fn from_json(text: &str) -> Result<ShnMetadata> {
+        let document = try!(shn_json::parse(text).map_err(|(offset, e)| {
+            ShnError::InvalidMetadata(format!("{} at offset {}", e, offset))
+        }));
+        let mut metadata = ShnMetadata::new();
+        for (name, table) in try!(members(&document, "")) {
+            let mut t = ShnTableMetadata::default();
+            for (key, value) in try!(members(table, name)) {
+                let path = format!("{}.{}", name, key);
+                match &key[..] {
+                    "description"   => t.description = Some(try!(string(value, &path))),
+                    "columns"       => {
+                        for (column, c) in try!(members(value, &path)) {
+                            let path = format!("{}.{}", path, column);
+                            t.columns.insert(column.clone(),
+                                             try!(parse_column(c, &path)));
+                        }
+                    },
+                    _               => return Err(unknown_member(&path)),
+                }
+            }
+            metadata.tables.insert(name.clone(), t);
+        }
+        Ok(metadata)
+    }
+
+    /// Returns the metadata as JSON text, in the format read by
+    /// `from_json`.
+    pub fn to_json(&self) -> String {
+        let tables: Vec<String> = self.tables.iter().map(|(name, table)| {
+            let mut members = Vec::new();
+            if let Some(ref d) = table.description {
+                members.push(format!("\"description\": {}", quote(d)));
+            }
+            if !table.columns.is_empty() {
+                let columns: Vec<String> = table.columns.iter()
+                    .map(|(n, c)| format!("      {}: {}", quote(n), column_json(c)))
+                    .collect();
+                members.push(format!("\"columns\": {{\n{}\n    }}",
+                                     columns.join(",\n")));
+            }
+            if members.is_empty() {
+                format!("  {}: {{}}", quote(name))
+            } else {
+                format!("  {}: {{\n    {}\n  }}", quote(name),
+                        members.join(",\n    "))
+            }
+        }).collect();
+        if tables.is_empty() {
+            "{}\n".to_owned()
+        } else {
+            format!("{{\n{}\n}}\n", tables.join(",\n"))
+        }
+    }
+}
+
+/// Returns `text` as a quoted JSON string.
+fn quote(text: &str) -> String {
+    let mut out = Vec::new();
+    shn_json::write_string(&mut out, text).unwrap();
+    String::from_utf8(out).unwrap()
+}
+
+/// Returns the documentation of a column as a JSON object on a single line.
+fn column_json(column: &ShnColumnMetadata) -> String {
+    let object = |members: Vec<(String, String)>| {
+        let members: Vec<String> = members.into_iter()
+            .map(|(k, v)| format!("{}: {}", quote(&k), v))
+            .collect();
+        format!("{{{}}}", members.join(", "))
+    };
+    let mut members = Vec::new();
+    if let Some(ref d) = column.description {
+        members.push(("description".to_owned(), quote(d)));
+    }
+    if let Some(ref u) = column.unit {
+        members.push(("unit".to_owned(), quote(u)));
+    }
+    if !column.labels.is_empty() {
+        members.push(("labels".to_owned(), object(column.labels.iter()
+            .map(|(v, l)| (v.to_string(), quote(l)))
+            .collect())));
+    }
+    if !column.flags.is_empty() {
+        members.push(("flags".to_owned(), object(column.flags.iter()
+            .map(|(b, n)| (b.to_string(), quote(n)))
+            .collect())));
+    }
+    if let Some((ref t, ref c)) = column.references {
+        members.push(("references".to_owned(), quote(&format!("{}.{}", t, c))));
+    }
+    object(members)
+}
+
+fn unknown_member(path: &str) -> ShnError {
+    ShnError::InvalidMetadata(format!("unknown member `{}`", path))
+}
+
+/// Returns the members of the object `value`, located at `path`, which is
+/// empty for the whole document.
+fn members<'a>(value: &'a JsonValue, path: &str)
+               -> Result<&'a [(String, JsonValue)]> {
+    match *value {
+        JsonValue::Object(ref members)  => Ok(members),
+        _ if path.is_empty() => Err(ShnError::InvalidMetadata(
+            "the document needs to be an object".to_owned())),
+        _ => Err(ShnError::InvalidMetadata(
+            format!("`{}` needs to be an object", path))),
+    }
+}
+
+/// Returns the string `value`, located at `path`.
+fn string(value: &JsonValue, path: &str) -> Result<String> {
+    match *value {
+        JsonValue::String(ref s)    => Ok(s.clone()),
+        _ => Err(ShnError::InvalidMetadata(
+            format!("`{}` needs to be a string", path))),
+    }
+}
+
+fn parse_column(value: &JsonValue, path: &str) -> Result<ShnColumnMetadata> {
+    let mut column = ShnColumnMetadata::default();
+    for (key, value) in try!(members(value, path)) {
+        let path = format!("{}.{}", path, key);
+        match &key[..] {
+            "description"   => column.description = Some(try!(string(value, &path))),
+            "unit"          => column.unit = Some(try!(string(value, &path))),
+            "labels"        => {
+                for (v, label) in try!(members(value, &path)) {
+                    let v = try!(v.parse().map_err(|_| ShnError::InvalidMetadata(
+                        format!("`{}.{}` is not an integer", path, v))));
+                    column.labels.insert(v, try!(string(label, &path)));
+                }
+            },
+            "flags"         => {
+                for (bit, name) in try!(members(value, &path)) {
+                    let bit = match bit.parse() {
+                        Ok(b) if b < 32 => b,
+                        _ => return Err(ShnError::InvalidMetadata(
+                            format!("`{}.{}` is not a bit position", path, bit))),
+                    };
+                    column.flags.insert(bit, try!(string(name, &path)));
+                }
+            },
+            "references"    => {
+                let target = try!(string(value, &path));
+                let mut parts = target.splitn(2, '.');
+                match (parts.next(), parts.next()) {
+                    (Some(t), Some(c)) if !t.is_empty() && !c.is_empty() => {
+                        column.references = Some((t.to_owned(), c.to_owned()));
+                    },
+                    _ => return Err(ShnError::InvalidMetadata(
+                        format!("`{}` needs to look like `Table.Column`", path))),
+                }
+            },
+            _               => return Err(unknown_member(&path)),
+        }
+    }
+    Ok(column)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::shn::ShnColumn;
+
+    const EXAMPLE: &'static str = r#"{
+      "ItemInfo": {
+        "description": "Items as known to the client",
+        "columns": {
+          "ItemAuctionGroup": {
+            "description": "Category in the auction house",
+            "labels": { "0": "None", "1": "Weapon", "-1": "Any" }
+          },
+          "ItemUseFlags": { "flags": { "0": "Tradable", "3": "Storable" } },
+          "ID": { "references": "ItemInfoServer.ID" },
+          "MinWC": { "unit": "damage" }
+        }
+      },
+      "Empty": {}
+    }"#;
+
+    fn error(text: &str) -> String {
+        match ShnMetadata::from_json(text) {
+            Err(ShnError::InvalidMetadata(message)) => message,
+            r => panic!("{:?}", r),
+        }
+    }
+
+    #[test]
+    fn read_example() {
+        let metadata = ShnMetadata::read_from(EXAMPLE.as_bytes()).unwrap();
+        assert_eq!(metadata.tables.keys().collect::<Vec<_>>(),
+                   vec!["Empty", "ItemInfo"]);
+        assert_eq!(metadata.table("Empty"), Some(&ShnTableMetadata::default()));
+        let items = metadata.table("ItemInfo").unwrap();
+        assert_eq!(items.description.as_ref().unwrap(), "Items as known to the client");
+        let group = items.column("ItemAuctionGroup").unwrap();
+        assert_eq!(group.description.as_ref().unwrap(), "Category in the auction house");
+        assert_eq!(group.labels.get(&-1).unwrap(), "Any");
+        assert_eq!(items.column("ItemUseFlags").unwrap().flags.get(&3).unwrap(),
+                   "Storable");
+        assert_eq!(items.column("ID").unwrap().references,
+                   Some(("ItemInfoServer".to_owned(), "ID".to_owned())));
+        assert_eq!(items.column("MinWC").unwrap().unit.as_ref().unwrap(), "damage");
+        assert!(items.column("Nope").is_none());
+        assert_eq!(metadata.relations(),
+                   vec![ShnRelation::new("ItemInfo", "ID", "ItemInfoServer", "ID")]);
+    }
+
+    #[test]
+    fn json_round_trip() {
+        let metadata = ShnMetadata::from_json(EXAMPLE).unwrap();
+        let json = metadata.to_json();
+        assert_eq!(ShnMetadata::from_json(&json).unwrap(), metadata);
+        assert_eq!(ShnMetadata::new().to_json(), "{}\n");
+        assert_eq!(ShnMetadata::from_json(&ShnMetadata::new().to_json()).unwrap(),
+                   ShnMetadata::new());
+
+        let mut metadata = ShnMetadata::new();
+        let mut column = ShnColumnMetadata::default();
+        column.description = Some("quote \" and\nbreak".to_owned());
+        let mut table = ShnTableMetadata::default();
+        table.columns.insert("Odd \"Name\"".to_owned(), column);
+        metadata.tables.insert("T".to_owned(), table);
+        assert_eq!(ShnMetadata::from_json(&metadata.to_json()).unwrap(), metadata);
+    }
+
+    #[test]
+    fn labels() {
+        let metadata = ShnMetadata::from_json(EXAMPLE).unwrap();
+        let items = metadata.table("ItemInfo").unwrap();
+        let group = items.column("ItemAuctionGroup").unwrap();
+        assert_eq!(group.label(&ShnCell::Byte(1)).unwrap(), "Weapon");
+        assert_eq!(group.label(&ShnCell::SignedByte(-1)).unwrap(), "Any");
+        assert_eq!(group.label(&ShnCell::Byte(2)), None);
+        assert_eq!(group.label(&ShnCell::StringFixedLen("1".to_owned())), None);
+        let flags = items.column("ItemUseFlags").unwrap();
+        assert_eq!(flags.label(&ShnCell::UnsignedInteger(9)).unwrap(),
+                   "Tradable | Storable");
+        assert_eq!(flags.label(&ShnCell::UnsignedInteger(13)).unwrap(),
+                   "Tradable | Storable | 4");
+        assert_eq!(flags.label(&ShnCell::UnsignedInteger(4)), None);
+        assert_eq!(flags.label(&ShnCell::UnsignedInteger(0)), None);
+        assert_eq!(items.display("ItemAuctionGroup", &ShnCell::Byte(1)), "Weapon");
+        assert_eq!(items.display("ItemAuctionGroup", &ShnCell::Byte(7)), "7");
+        assert_eq!(items.display("Nope", &ShnCell::Byte(1)), "1");
+    }
+
+    #[test]
+    fn parse_values() {
+        let metadata = ShnMetadata::from_json(EXAMPLE).unwrap();
+        let items = metadata.table("ItemInfo").unwrap();
+        let group = items.column("ItemAuctionGroup").unwrap();
+        assert_eq!(group.parse_value(" Weapon ", &ShnDataType::Byte).unwrap(),
+                   ShnCell::Byte(1));
+        assert_eq!(group.parse_value("5", &ShnDataType::Byte).unwrap(),
+                   ShnCell::Byte(5));
+        assert!(group.parse_value("Any", &ShnDataType::Byte).is_err());
+        assert!(group.parse_value("Armor", &ShnDataType::Byte).is_err());
+        let flags = items.column("ItemUseFlags").unwrap();
+        let dword = ShnDataType::UnsignedInteger;
+        assert_eq!(flags.parse_value("Tradable | Storable", &dword).unwrap(),
+                   ShnCell::UnsignedInteger(9));
+        assert_eq!(flags.parse_value("Storable|4", &dword).unwrap(),
+                   ShnCell::UnsignedInteger(12));
+        assert!(flags.parse_value("Tradable | Nope", &dword).is_err());
+        assert!(flags.parse_value("", &dword).is_err());
+    }
+
+    #[test]
+    fn unknown_columns() {
+        let metadata = ShnMetadata::from_json(EXAMPLE).unwrap();
+        let mut schema = ShnSchema::with_id_column();
+        schema.columns.push(ShnColumn::new_unsigned_short("ID"));
+        schema.columns.push(ShnColumn::new_byte("ItemAuctionGroup"));
+        assert_eq!(metadata.table("ItemInfo").unwrap().unknown_columns(&schema),
+                   vec!["ItemUseFlags", "MinWC"]);
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(error("[]"), "the document needs to be an object");
+        assert_eq!(error("{\"T\": 1}"), "`T` needs to be an object");
+        assert_eq!(error("{\"T\": {\"name\": 1}}"), "unknown member `T.name`");
+        assert_eq!(error("{\"T\": {\"description\": 1}}"),
+                   "`T.description` needs to be a string");
+        assert_eq!(error("{\"T\": {\"columns\": {\"C\": {\"labels\": {\"x\": \"X\"}}}}}"),
+                   "`T.columns.C.labels.x` is not an integer");
+        assert_eq!(error("{\"T\": {\"columns\": {\"C\": {\"labels\": {\"1\": 1}}}}}"),
+                   "`T.columns.C.labels` needs to be a string");
+        assert_eq!(error("{\"T\": {\"columns\": {\"C\": {\"flags\": {\"32\": \"X\"}}}}}"),
+                   "`T.columns.C.flags.32` is not a bit position");
+        assert_eq!(error("{\"T\": {\"columns\": {\"C\": {\"references\": \"T.\"}}}}"),
+                   "`T.columns.C.references` needs to look like `Table.Column`");
+        assert_eq!(error("{\"T\": {\"columns\": {\"C\": {\"size\": 1}}}}"),
+                   "unknown member `T.columns.C.size`");
+        assert_eq!(error("{\"T\": }"), "expected a value, found `}` at offset 6");
+    }
+}
@@ -0,0 +1,636 @@
+use ::std::cmp::Ordering;
+use ::std::fmt;
+use ::std::io::{ self, Write };
+
+use super::shn::{ ShnDataType, ShnCell, ShnColumn, ShnSchema, ShnRow, ShnFile, ShnError,
+                  Result };
+use super::shn_csv;
+use super::shn_json;
+use super::shn_metadata::ShnTableMetadata;
+
+/// Comparison operators usable in a `ShnPredicate`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ShnComparison {
+    /// `=`
+    Equal,
+    /// `!=`
+    NotEqual,
+    /// `<`
+    Less,
+    /// `<=`
+    LessOrEqual,
+    /// `>`
+    Greater,
+    /// `>=`
+    GreaterOrEqual,
+    /// `~`, matches string cells containing the value.
+    Contains,
+}
+
+impl ShnComparison {
+    /// Returns the operator for `symbol`, one of `=`, `==`, `!=`, `<>`, `<`,
+    /// `<=`, `>`, `>=` or `~` for `Contains`.
+    pub fn from_symbol(symbol: &str) -> Option<ShnComparison> {
+        match symbol {
+            "=" | "=="  => Some(ShnComparison::Equal),
+            "!=" | "<>" => Some(ShnComparison::NotEqual),
+            "<"         => Some(ShnComparison::Less),
+            "<="        => Some(ShnComparison::LessOrEqual),
+            ">"         => Some(ShnComparison::Greater),
+            ">="        => Some(ShnComparison::GreaterOrEqual),
+            "~"         => Some(ShnComparison::Contains),
+            _           => None,
+        }
+    }
+
+    /// Returns the canonical symbol of the operator.
+    pub fn symbol(&self) -> &'static str {
+        match *self {
+            ShnComparison::Equal            => "=",
+            ShnComparison::NotEqual         => "!=",
+            ShnComparison::Less             => "<",
+            ShnComparison::LessOrEqual      => "<=",
+            ShnComparison::Greater          => ">",
+            ShnComparison::GreaterOrEqual   => ">=",
+            ShnComparison::Contains         => "~",
+        }
+    }
+}
+
+/// A condition on the cells of a row. Numeric cells are compared by value
+/// regardless of their exact type, so a `Byte` column can be compared to an
+/// `UnsignedInteger` value; strings are compared to strings only.
+#[derive(Clone, PartialEq, Debug)]
+pub enum ShnPredicate {
+    /// Compares the named cell to the value, the cell being the left operand.
+    Compare(String, ShnComparison, ShnCell),
+    /// Matches if both predicates match
+    And(Box<ShnPredicate>, Box<ShnPredicate>),
+    /// Matches if either predicate matches
+    Or(Box<ShnPredicate>, Box<ShnPredicate>),
+    /// Matches if the predicate does not match
+    Not(Box<ShnPredicate>),
+}
+
+impl ShnPredicate {
+    /// Constructs a predicate comparing the cell in `column` to `value`.
+    pub fn compare<V: Into<ShnCell>>(column: &str,
+                                     comparison: ShnComparison,
+                                     value: V) -> Self {
+        ShnPredicate::Compare(column.to_owned(), comparison, value.into())
+    }
+
+    /// Combines both predicates, matching if both match.
+    pub fn and(self, other: ShnPredicate) -> Self {
+        ShnPredicate::And(Box::new(self), Box::new(other))
+    }
+
+    /// Combines both predicates, matching if either matches.
+    pub fn or(self, other: ShnPredicate) -> Self {
+        ShnPredicate::Or(Box::new(self), Box::new(other))
+    }
+
+    /// Negates the predicate.
+    pub fn not(self) -> Self {
+        ShnPredicate::Not(Box::new(self))
+    }
+
+    /// Checks that all referenced columns exist in `schema`, and that their
+    /// values are comparable to the cells of those columns.
+    pub fn check(&self, schema: &ShnSchema) -> Result<()> {
+        match *self {
+            ShnPredicate::Compare(ref name, comparison, ref value) => {
+                let column = try!(schema.columns.iter()
+                                  .find(|c| c.name == *name)
+                                  .ok_or_else(|| ShnError::UnknownColumn(
+                                      name.clone())));
+                let column_is_string = match column.data_type {
+                    ShnDataType::StringFixedLen |
+                    ShnDataType::StringZeroTerminated   => true,
+                    _                                   => false,
+                };
+                if column_is_string != value.as_str().is_some()
+                    || (comparison == ShnComparison::Contains
+                        && !column_is_string) {
+                    return Err(ShnError::InvalidConversion(
+                        value.clone(), column.data_type.clone()));
+                }
+                Ok(())
+            },
+            ShnPredicate::And(ref a, ref b) |
+            ShnPredicate::Or(ref a, ref b) => {
+                try!(a.check(schema));
+                b.check(schema)
+            },
+            ShnPredicate::Not(ref a) => a.check(schema),
+        }
+    }
+
+    /// Returns whether `row` matches the predicate. Unknown columns and
+    /// incomparable values never match, use `check` to detect them up front.
+    pub fn matches(&self, row: &ShnRow) -> bool {
+        match *self {
+            ShnPredicate::Compare(ref name, comparison, ref value) => {
+                let cell = row.schema.columns.iter()
+                    .position(|c| c.name == *name)
+                    .and_then(|i| row.data.get(i));
+                match cell {
+                    Some(cell) => compare(cell, comparison, value),
+                    None       => false,
+                }
+            },
+            ShnPredicate::And(ref a, ref b) => a.matches(row) && b.matches(row),
+            ShnPredicate::Or(ref a, ref b)  => a.matches(row) || b.matches(row),
+            ShnPredicate::Not(ref a)        => !a.matches(row),
+        }
+    }
+}
+
+impl fmt::Display for ShnPredicate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ShnPredicate::Compare(ref name, comparison, ref value) => {
+                match value.as_str() {
+                    Some(s) => write!(f, "{} {} '{}'", name, comparison.symbol(),
+                                      s.replace('\'', "''")),
+                    None    => write!(f, "{} {} {}", name, comparison.symbol(),
+                                      value),
+                }
+            },
+            ShnPredicate::And(ref a, ref b) => write!(f, "({} AND {})", a, b),
+            ShnPredicate::Or(ref a, ref b)  => write!(f, "({} OR {})", a, b),
+            ShnPredicate::Not(ref a)        => write!(f, "NOT {}", a),
+        }
+    }
+}
+
+/// Compares `cell` to `value` using `comparison`.
+fn compare(cell: &ShnCell, comparison: ShnComparison, value: &ShnCell) -> bool {
+    if comparison == ShnComparison::Contains {
+        return match (cell.as_str(), value.as_str()) {
+            (Some(c), Some(v))  => c.contains(v),
+            _                   => false,
+        };
+    }
+    let ordering = match (cell.as_i64(), value.as_i64()) {
+        (Some(a), Some(b)) => Some(a.cmp(&b)),
+        _ => match (cell.as_f64(), value.as_f64()) {
+            (Some(a), Some(b)) => a.partial_cmp(&b),
+            _ => match (cell.as_str(), value.as_str()) {
+                (Some(a), Some(b))  => Some(a.cmp(b)),
+                _                   => None,
+            },
+        },
+    };
+    match ordering {
+        Some(o) => match comparison {
+            ShnComparison::Equal            => o == Ordering::Equal,
+            ShnComparison::NotEqual         => o != Ordering::Equal,
+            ShnComparison::Less             => o == Ordering::Less,
+            ShnComparison::LessOrEqual      => o != Ordering::Greater,
+            ShnComparison::Greater          => o == Ordering::Greater,
+            ShnComparison::GreaterOrEqual   => o != Ordering::Less,
+            ShnComparison::Contains         => unreachable!(),
+        },
+        // NaN is only unequal to anything
+        None => comparison == ShnComparison::NotEqual,
+    }
+}
+
+/// Sort direction for `ShnQuery::order_by`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ShnOrder {
+    /// Smallest values first
+    Ascending,
+    /// Largest values first
+    Descending,
+}
+
+/// A query selecting, filtering and ordering the rows of a `ShnFile`.
+///
+/// ```
+/// # extern crate shn;
+/// # use std::sync::Arc;
+/// # use shn::{ SHN_CRYPT_HEADER_LEN, ShnFile, ShnSchema, ShnColumn, ShnRow,
+/// #            ShnCell, ShnError };
+/// use shn::{ ShnQuery, ShnPredicate, ShnComparison, ShnOrder };
+/// # fn main() { run().unwrap(); }
+/// # fn run() -> Result<(), ShnError> {
+/// # let mut schema = ShnSchema::with_id_column();
+/// # schema.columns.push(ShnColumn::new_unsigned_integer("ID"));
+/// # schema.columns.push(ShnColumn::new_string_fixed_len("Name", 16));
+/// # schema.columns.push(ShnColumn::new_byte("Level"));
+/// # let schema = Arc::new(schema);
+/// # let mut file = ShnFile::new([0; SHN_CRYPT_HEADER_LEN], 0, schema.clone());
+/// # for &(id, name, level) in &[(1, "Sword", 70), (2, "Axe", 80), (3, "Stick", 1)] {
+/// #     file.append_row(ShnRow {
+/// #         schema: schema.clone(),
+/// #         data:   vec![ShnCell::UnsignedShort(0), ShnCell::UnsignedInteger(id),
+/// #                      ShnCell::StringFixedLen(name.to_owned()), ShnCell::Byte(level)],
+/// #     })?;
+/// # }
+/// let result = ShnQuery::new()
+///     .select(&["ID", "Name"])
+///     .filter(ShnPredicate::compare("Level", ShnComparison::Greater, 60u8))
+///     .order_by("Name", ShnOrder::Ascending)
+///     .limit(10)
+///     .run(&file)?;
+/// assert_eq!(result.len(), 2);
+/// assert_eq!(result.get(0, 1), Some(&ShnCell::StringFixedLen("Axe".to_owned())));
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct ShnQuery {
+    columns:    Option<Vec<String>>,
+    filter:     Option<ShnPredicate>,
+    order:      Vec<(String, ShnOrder)>,
+    limit:      Option<usize>,
+}
+
+impl ShnQuery {
+    /// Constructs a query returning all rows with all columns except
+    /// `__ID__`.
+    pub fn new() -> Self {
+        ShnQuery::default()
+    }
+
+    /// Selects the columns to return, in the given order.
+    pub fn select<S: AsRef<str>>(mut self, columns: &[S]) -> Self {
+        self.columns = Some(columns.iter()
+                            .map(|c| c.as_ref().to_owned())
+                            .collect());
+        self
+    }
+
+    /// Only returns rows matching `predicate`. Calling this more than once
+    /// requires rows to match all predicates.
+    pub fn filter(mut self, predicate: ShnPredicate) -> Self {
+        self.filter = Some(match self.filter.take() {
+            Some(f) => f.and(predicate),
+            None    => predicate,
+        });
+        self
+    }
+
+    /// Orders the rows by `column`. Later calls only break ties of earlier
+    /// ones. Rows are kept in file order otherwise.
+    pub fn order_by(mut self, column: &str, order: ShnOrder) -> Self {
+        self.order.push((column.to_owned(), order));
+        self
+    }
+
+    /// Returns at most `count` rows.
+    pub fn limit(mut self, count: usize) -> Self {
+        self.limit = Some(count);
+        self
+    }
+
+    /// Runs the query against `file`. Fails if any referenced column does not
+    /// exist or a filter value is not comparable to its column.
+    pub fn run<'a>(&self, file: &'a ShnFile) -> Result<ShnResultSet<'a>> {
+        let schema = &*file.schema;
+        let position = |name: &str| {
+            schema.columns.iter()
+                .position(|c| c.name == name)
+                .ok_or_else(|| ShnError::UnknownColumn(name.to_owned()))
+        };
+
+        let columns = match self.columns {
+            Some(ref names) => {
+                let mut columns = Vec::with_capacity(names.len());
+                for name in names {
+                    columns.push(try!(position(name)));
+                }
+                columns
+            },
+            None => (0..schema.columns.len())
+                .filter(|&i| !schema.columns[i].is_id())
+                .collect(),
+        };
+        if let Some(ref filter) = self.filter {
+            try!(filter.check(schema));
+        }
+        let mut order = Vec::with_capacity(self.order.len());
+        for &(ref name, direction) in &self.order {
+            order.push((try!(position(name)), direction));
+        }
+
+        let mut rows: Vec<&ShnRow> = file.data.iter()
+            .filter(|r| self.filter.as_ref().map_or(true, |f| f.matches(r)))
+            .collect();
+        if !order.is_empty() {
+            rows.sort_by(|a, b| {
+                for &(i, direction) in &order {
+                    let o = a.data[i].cmp(&b.data[i]);
+                    if o != Ordering::Equal {
+                        return match direction {
+                            ShnOrder::Ascending     => o,
+                            ShnOrder::Descending    => o.reverse(),
+                        };
+                    }
+                }
+                Ordering::Equal
+            });
+        }
+        if let Some(limit) = self.limit {
+            rows.truncate(limit);
+        }
+
+        Ok(ShnResultSet {
+            schema: schema,
+            columns: columns,
+            rows: rows,
+            metadata: None,
+        })
+    }
+}
+
+/// The result of a `ShnQuery`, borrowing the rows of the queried file.
+#[derive(Clone, Debug)]
+pub struct ShnResultSet<'a> {
+    schema:     &'a ShnSchema,
+    columns:    Vec<usize>,
+    rows:       Vec<&'a ShnRow>,
+    metadata:   Option<&'a ShnTableMetadata>,
+}
+
+impl<'a> ShnResultSet<'a> {
+    /// Shows the labels defined by `metadata` instead of plain values when
+    /// printing or exporting the result.
+    pub fn with_metadata(mut self, metadata: &'a ShnTableMetadata) -> Self {
+        self.metadata = Some(metadata);
+        self
+    }
+
+    /// Returns the text of `cell` in the column at `column` of the schema,
+    /// labelled if there is metadata.
+    fn text(&self, column: usize, cell: &ShnCell) -> String {
+        match self.metadata {
+            Some(m) => m.display(&self.schema.columns[column].name, cell),
+            None    => cell.to_string(),
+        }
+    }
+
+    /// Returns the selected columns.
+    pub fn columns(&self) -> Vec<&'a ShnColumn> {
+        let schema = self.schema;
+        self.columns.iter().map(|&i| &schema.columns[i]).collect()
+    }
+
+    /// Returns the number of rows.
+    pub fn len(&self) -> usize {
+        self.rows.len()
+    }
+
+    /// Returns whether no row matched.
+    pub fn is_empty(&self) -> bool {
+        self.rows.is_empty()
+    }
+
+    /// Returns the selected cell of `column` in `row`.
+    pub fn get(&self, row: usize, column: usize) -> Option<&'a ShnCell> {
+        match (self.rows.get(row), self.columns.get(column)) {
+            (Some(r), Some(&c)) => Some(&r.data[c]),
+            _                   => None,
+        }
+    }
+
+    /// Returns the selected cells of `row`.
+    pub fn row(&self, row: usize) -> Option<Vec<&'a ShnCell>> {
+        self.rows.get(row)
+            .map(|r| self.columns.iter().map(|&c| &r.data[c]).collect())
+    }
+
+    /// Returns the full rows which matched, in result order.
+    pub fn source_rows(&self) -> &[&'a ShnRow] {
+        &self.rows
+    }
+
+    /// Writes the result as CSV, with a header line of column names.
+    /// Labelled values are written as their labels.
+    pub fn write_csv<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        try!(shn_csv::write_record(writer,
+                                   self.columns().iter().map(|c| &c.name[..])));
+        for r in &self.rows {
+            try!(shn_csv::write_record(
+                writer, self.columns.iter().map(|&c| self.text(c, &r.data[c]))));
+        }
+        Ok(())
+    }
+
+    /// Writes the result as a JSON array holding one object per row, keyed
+    /// by column name. Labelled values are written as strings.
+    pub fn write_json<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        let columns = self.columns();
+        try!(writer.write_all(b"["));
+        for (i, r) in self.rows.iter().enumerate() {
+            try!(writer.write_all(if i == 0 { &b"\n  {"[..] } else { &b",\n  {"[..] }));
+            for (j, (column, &c)) in columns.iter()
+                .zip(self.columns.iter())
+                .enumerate() {
+                if j > 0 {
+                    try!(writer.write_all(b", "));
+                }
+                try!(shn_json::write_string(writer, &column.name));
+                try!(writer.write_all(b": "));
+                let label = self.metadata
+                    .and_then(|m| m.column(&column.name))
+                    .and_then(|m| m.label(&r.data[c]));
+                try!(match label {
+                    Some(label) => shn_json::write_string(writer, &label),
+                    None        => shn_json::write_cell(writer, &r.data[c]),
+                });
+            }
+            try!(writer.write_all(b"}"));
+        }
+        writer.write_all(if self.rows.is_empty() { &b"]\n"[..] } else { &b"\n]\n"[..] })
+    }
+}
+
+/// Prints the result as a table with aligned columns.
+impl<'a> fmt::Display for ShnResultSet<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let header: Vec<String> = self.columns().iter()
+            .map(|c| c.name.clone())
+            .collect();
+        let cells: Vec<Vec<String>> = self.rows.iter()
+            .map(|r| self.columns.iter().map(|&c| self.text(c, &r.data[c])).collect())
+            .collect();
+        let mut widths: Vec<usize> = header.iter()
+            .map(|h| h.chars().count())
+            .collect();
+        for row in &cells {
+            for (w, c) in widths.iter_mut().zip(row.iter()) {
+                *w = ::std::cmp::max(*w, c.chars().count());
+            }
+        }
+
+        let line = |f: &mut fmt::Formatter, row: &[String]| -> fmt::Result {
+            for (i, (c, w)) in row.iter().zip(widths.iter()).enumerate() {
+                if i > 0 {
+                    try!(write!(f, " | "));
+                }
+                try!(write!(f, "{}", c));
+                if i + 1 < row.len() {
+                    for _ in c.chars().count()..*w {
+                        try!(write!(f, " "));
+                    }
+                }
+            }
+            writeln!(f, "")
+        };
+        try!(line(f, &header));
+        let separator: Vec<String> = widths.iter()
+            .map(|&w| ::std::iter::repeat('-').take(w).collect())
+            .collect();
+        try!(line(f, &separator));
+        for row in &cells {
+            try!(line(f, row));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::std::convert::TryFrom;
+    use super::super::shn_test_util::table;
+
+    fn file() -> ShnFile {
+        let rows = [(1, "Sword", 70, 0.5), (2, "Axe", 80, 1.5),
+                    (3, "Stick", 1, ::std::f32::NAN), (4, "Bow", 70, 2.0)];
+        table(vec![ShnColumn::new_unsigned_integer("ID"),
+                   ShnColumn::new_string_fixed_len("Name", 16),
+                   ShnColumn::new_byte("Level"),
+                   ShnColumn::new_single_floating_point("Rate")],
+              rows.iter()
+              .map(|&(id, name, level, rate)| {
+                  vec![ShnCell::UnsignedInteger(id),
+                       ShnCell::StringFixedLen(name.to_owned()),
+                       ShnCell::Byte(level),
+                       ShnCell::SingleFloatingPoint(rate)]
+              })
+              .collect())
+    }
+
+    fn ids(file: &ShnFile, query: &ShnQuery) -> Vec<u32> {
+        let result = query.clone().select(&["ID"]).run(file).unwrap();
+        (0..result.len())
+            .map(|i| u32::try_from(result.get(i, 0).unwrap()).unwrap())
+            .collect()
+    }
+
+    fn filtered(file: &ShnFile, predicate: ShnPredicate) -> Vec<u32> {
+        ids(file, &ShnQuery::new().filter(predicate))
+    }
+
+    #[test]
+    fn comparisons() {
+        let file = file();
+        let level = |c, v: u32| ShnPredicate::compare("Level", c, v);
+        assert_eq!(filtered(&file, level(ShnComparison::Equal, 70)), vec![1, 4]);
+        assert_eq!(filtered(&file, level(ShnComparison::NotEqual, 70)), vec![2, 3]);
+        assert_eq!(filtered(&file, level(ShnComparison::Less, 70)), vec![3]);
+        assert_eq!(filtered(&file, level(ShnComparison::LessOrEqual, 70)),
+                   vec![1, 3, 4]);
+        assert_eq!(filtered(&file, level(ShnComparison::Greater, 70)), vec![2]);
+        assert_eq!(filtered(&file, level(ShnComparison::GreaterOrEqual, 80)),
+                   vec![2]);
+        assert_eq!(filtered(&file, ShnPredicate::compare(
+            "Name", ShnComparison::Contains, "o")), vec![1, 4]);
+        assert_eq!(filtered(&file, ShnPredicate::compare(
+            "Name", ShnComparison::Less, "C")), vec![2, 4]);
+        // Floats compare to integers, NaN is only unequal
+        assert_eq!(filtered(&file, ShnPredicate::compare(
+            "Rate", ShnComparison::GreaterOrEqual, 1u8)), vec![2, 4]);
+        assert_eq!(filtered(&file, ShnPredicate::compare(
+            "Rate", ShnComparison::NotEqual, 0.5f32)), vec![2, 3, 4]);
+    }
+
+    #[test]
+    fn combined_predicates() {
+        let file = file();
+        let level = ShnPredicate::compare("Level", ShnComparison::Equal, 70u8);
+        let id = ShnPredicate::compare("ID", ShnComparison::Greater, 2u32);
+        assert_eq!(filtered(&file, level.clone().and(id.clone())), vec![4]);
+        assert_eq!(filtered(&file, level.clone().or(id.clone())), vec![1, 3, 4]);
+        assert_eq!(filtered(&file, level.clone().not()), vec![2, 3]);
+        assert_eq!(level.and(id.not()).to_string(), "(Level = 70 AND NOT ID > 2)");
+        assert_eq!(ShnPredicate::compare("Name", ShnComparison::Equal, "it's")
+                   .to_string(), "Name = 'it''s'");
+    }
+
+    #[test]
+    fn check_errors() {
+        let file = file();
+        let unknown = ShnPredicate::compare("Nope", ShnComparison::Equal, 1u8);
+        match ShnQuery::new().filter(unknown.clone()).run(&file) {
+            Err(ShnError::UnknownColumn(ref name)) if name == "Nope" => {},
+            r => panic!("{:?}", r.map(|r| r.len())),
+        }
+        assert!(!unknown.matches(&file.data[0]));
+        for predicate in vec![
+            ShnPredicate::compare("Name", ShnComparison::Equal, 1u8),
+            ShnPredicate::compare("Level", ShnComparison::Equal, "1"),
+            ShnPredicate::compare("Level", ShnComparison::Contains, 1u8),
+        ] {
+            match predicate.check(&file.schema) {
+                Err(ShnError::InvalidConversion(..)) => {},
+                r => panic!("{}: {:?}", predicate, r),
+            }
+        }
+        assert!(ShnQuery::new().select(&["Nope"]).run(&file).is_err());
+        assert!(ShnQuery::new().order_by("Nope", ShnOrder::Ascending)
+                .run(&file).is_err());
+    }
+
+    #[test]
+    fn select_order_limit() {
+        let file = file();
+        let all = ShnQuery::new().run(&file).unwrap();
+        let names: Vec<&str> = all.columns().iter().map(|c| &c.name[..]).collect();
+        assert_eq!(names, vec!["ID", "Name", "Level", "Rate"]);
+        assert_eq!(all.len(), 4);
+
+        let query = ShnQuery::new()
+            .order_by("Level", ShnOrder::Descending)
+            .order_by("Name", ShnOrder::Ascending);
+        assert_eq!(ids(&file, &query), vec![2, 4, 1, 3]);
+        assert_eq!(ids(&file, &query.clone().limit(2)), vec![2, 4]);
+        assert_eq!(ids(&file, &query.limit(0)), Vec::<u32>::new());
+
+        let result = ShnQuery::new().select(&["Level", "ID"]).run(&file).unwrap();
+        assert_eq!(result.row(1), Some(vec![&ShnCell::Byte(80),
+                                            &ShnCell::UnsignedInteger(2)]));
+        assert!(result.row(4).is_none());
+        assert!(result.get(0, 2).is_none());
+        assert_eq!(result.source_rows()[3].data[1], ShnCell::UnsignedInteger(4));
+    }
+
+    #[test]
+    fn output() {
+        let file = file();
+        let result = ShnQuery::new()
+            .select(&["ID", "Name"])
+            .filter(ShnPredicate::compare("ID", ShnComparison::LessOrEqual, 2u8))
+            .run(&file)
+            .unwrap();
+        assert_eq!(result.to_string(),
+                   "ID | Name\n-- | -----\n1  | Sword\n2  | Axe\n");
+        let mut csv = Vec::new();
+        result.write_csv(&mut csv).unwrap();
+        assert_eq!(String::from_utf8(csv).unwrap(), "ID,Name\r\n1,Sword\r\n2,Axe\r\n");
+        let mut json = Vec::new();
+        result.write_json(&mut json).unwrap();
+        assert_eq!(String::from_utf8(json).unwrap(),
+                   "[\n  {\"ID\": 1, \"Name\": \"Sword\"},\n  {\"ID\": 2, \"Name\": \"Axe\"}\n]\n");
+
+        let empty = ShnQuery::new().limit(0).run(&file).unwrap();
+        assert!(empty.is_empty());
+        let mut json = Vec::new();
+        empty.write_json(&mut json).unwrap();
+        assert_eq!(json, b"[]\n");
+    }
+}
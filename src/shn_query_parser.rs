@@ -0,0 +1,583 @@
+use super::shn::{ ShnDataType, ShnCell, ShnColumn, ShnSchema, ShnError, Result };
+use super::shn_metadata::ShnTableMetadata;
+use super::shn_query::{ ShnQuery, ShnPredicate, ShnComparison, ShnOrder };
+
+/// A token of the query language.
+#[derive(Clone, PartialEq, Debug)]
+pub enum Token {
+    /// A keyword or column name, column names may be quoted with backticks
+    Ident(String),
+    /// An unsigned number, kept as text so it can be parsed into any type
+    Number(String),
+    /// A string quoted with single or double quotes
+    Str(String),
+    /// An operator or punctuation
+    Symbol(&'static str),
+}
+
+/// Symbols in order of decreasing length, so the longest match wins.
+const SYMBOLS: &'static [&'static str] = &[
+    "==", "!=", "<>", "<=", ">=",
+    "=", "<", ">", "~", ",", "(", ")", "*", "+", "-", "/", "%", ":",
+];
+
+/// How deeply parentheses and prefix operators may be nested, to keep
+/// hostile input from overflowing the stack.
+const MAX_DEPTH: usize = 64;
+
+/// Splits `text` into tokens along with their byte offsets.
+fn tokenize(text: &str) -> Result<Vec<(usize, Token)>> {
+    let mut tokens = Vec::new();
+    let mut chars = text.char_indices().peekable();
+    while let Some(&(start, c)) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+        } else if c.is_alphabetic() || c == '_' {
+            let mut ident = String::new();
+            while let Some(&(_, c)) = chars.peek() {
+                if !(c.is_alphanumeric() || c == '_') {
+                    break;
+                }
+                ident.push(c);
+                chars.next();
+            }
+            tokens.push((start, Token::Ident(ident)));
+        } else if c.is_ascii_digit() || c == '.' {
+            let mut number = String::new();
+            while let Some(&(_, c)) = chars.peek() {
+                if !(c.is_ascii_digit() || c == '.') {
+                    break;
+                }
+                number.push(c);
+                chars.next();
+            }
+            tokens.push((start, Token::Number(number)));
+        } else if c == '\'' || c == '"' || c == '`' {
+            // Quotes are escaped by doubling them
+            chars.next();
+            let mut quoted = String::new();
+            loop {
+                match chars.next() {
+                    Some((_, q)) if q == c => {
+                        match chars.peek() {
+                            Some(&(_, q)) if q == c => {
+                                quoted.push(q);
+                                chars.next();
+                            },
+                            _ => break,
+                        }
+                    },
+                    Some((_, other)) => quoted.push(other),
+                    None => return Err(ShnError::InvalidQuery(
+                        start, "unterminated quote".to_owned())),
+                }
+            }
+            tokens.push((start, if c == '`' {
+                Token::Ident(quoted)
+            } else {
+                Token::Str(quoted)
+            }));
+        } else {
+            match SYMBOLS.iter().find(|s| text[start..].starts_with(*s)) {
+                Some(s) => {
+                    for _ in 0..s.len() {
+                        chars.next();
+                    }
+                    tokens.push((start, Token::Symbol(s)));
+                },
+                None => return Err(ShnError::InvalidQuery(
+                    start, format!("unexpected character `{}`", c))),
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+/// A cursor over the tokens of a query, shared by the query and
+/// expression parsers.
+pub struct Parser<'a> {
+    tokens:     Vec<(usize, Token)>,
+    pos:        usize,
+    end:        usize,
+    depth:      usize,
+    metadata:   Option<&'a ShnTableMetadata>,
+}
+
+impl<'a> Parser<'a> {
+    /// Tokenizes `text`.
+    pub fn new(text: &str) -> Result<Self> {
+        Parser::with_metadata(text, None)
+    }
+
+    /// Tokenizes `text`, accepting the labels defined by `metadata` in
+    /// place of values.
+    pub fn with_metadata(text: &str, metadata: Option<&'a ShnTableMetadata>)
+                         -> Result<Self> {
+        Ok(Parser {
+            tokens:     try!(tokenize(text)),
+            pos:        0,
+            end:        text.len(),
+            depth:      0,
+            metadata:   metadata,
+        })
+    }
+
+    /// Returns the next token without consuming it.
+    pub fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).map(|&(_, ref t)| t)
+    }
+
+    /// Consumes the next token.
+    pub fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).map(|&(_, ref t)| t.clone());
+        if token.is_some() {
+            self.pos += 1;
+        }
+        token
+    }
+
+    /// Returns whether all tokens have been consumed.
+    pub fn is_end(&self) -> bool {
+        self.pos >= self.tokens.len()
+    }
+
+    /// Returns the offset of the next token, or the length of the text at
+    /// its end.
+    pub fn offset(&self) -> usize {
+        self.tokens.get(self.pos).map_or(self.end, |&(o, _)| o)
+    }
+
+    /// Returns an error at the next token.
+    pub fn error<T>(&self, message: &str) -> Result<T> {
+        let found = match self.peek() {
+            Some(&Token::Ident(ref s))  => format!("`{}`", s),
+            Some(&Token::Number(ref s)) => format!("`{}`", s),
+            Some(&Token::Str(ref s))    => format!("'{}'", s),
+            Some(&Token::Symbol(s))     => format!("`{}`", s),
+            None                        => "end of input".to_owned(),
+        };
+        Err(ShnError::InvalidQuery(self.offset(),
+                                   format!("expected {}, found {}",
+                                           message, found)))
+    }
+
+    /// Enters a nested part of the text, like a parenthesized condition.
+    /// Fails if parts are nested too deeply, see `MAX_DEPTH`.
+    pub fn enter(&mut self) -> Result<()> {
+        if self.depth >= MAX_DEPTH {
+            return Err(ShnError::InvalidQuery(self.offset(),
+                                              "nested too deeply".to_owned()));
+        }
+        self.depth += 1;
+        Ok(())
+    }
+
+    /// Leaves a nested part entered through `enter`.
+    pub fn leave(&mut self) {
+        self.depth -= 1;
+    }
+
+    /// Consumes the next token if it is the keyword `keyword`, ignoring case.
+    pub fn keyword(&mut self, keyword: &str) -> bool {
+        let matches = match self.peek() {
+            Some(&Token::Ident(ref s))  => s.eq_ignore_ascii_case(keyword),
+            _                           => false,
+        };
+        if matches {
+            self.pos += 1;
+        }
+        matches
+    }
+
+    /// Consumes the next token if it is `symbol`.
+    pub fn symbol(&mut self, symbol: &str) -> bool {
+        let matches = match self.peek() {
+            Some(&Token::Symbol(s)) => s == symbol,
+            _                       => false,
+        };
+        if matches {
+            self.pos += 1;
+        }
+        matches
+    }
+
+    /// Consumes the keyword `keyword` or fails.
+    pub fn expect_keyword(&mut self, keyword: &str) -> Result<()> {
+        if self.keyword(keyword) {
+            Ok(())
+        } else {
+            self.error(&format!("`{}`", keyword.to_uppercase()))
+        }
+    }
+
+    /// Consumes `symbol` or fails.
+    pub fn expect_symbol(&mut self, symbol: &str) -> Result<()> {
+        if self.symbol(symbol) {
+            Ok(())
+        } else {
+            self.error(&format!("`{}`", symbol))
+        }
+    }
+
+    /// Consumes a column name, which has to exist in `schema`.
+    pub fn column<'s>(&mut self, schema: &'s ShnSchema)
+                      -> Result<&'s ShnColumn> {
+        let offset = self.offset();
+        let name = match self.peek() {
+            Some(&Token::Ident(ref s))  => s.clone(),
+            _                           => return self.error("a column name"),
+        };
+        match schema.columns.iter().find(|c| c.name == name) {
+            Some(column) => {
+                self.pos += 1;
+                Ok(column)
+            },
+            None => Err(ShnError::InvalidQuery(
+                offset, format!("unknown column `{}`", name))),
+        }
+    }
+
+    /// Consumes a number or string literal, which is typed after `column`.
+    /// Numbers not representable by the column's type are kept as
+    /// `SingleFloatingPoint` values, since they can still be compared
+    /// against. Strings are looked up as labels of numeric columns if there
+    /// is metadata.
+    pub fn literal(&mut self, column: &ShnColumn) -> Result<ShnCell> {
+        let offset = self.offset();
+        let negative = self.symbol("-");
+        let cell = match self.peek() {
+            Some(&Token::Number(ref n)) => {
+                let text = if negative { format!("-{}", n) } else { n.clone() };
+                match ShnCell::parse(&text, &column.data_type) {
+                    Ok(ref cell) if cell.as_str().is_none() => cell.clone(),
+                    _ => try!(ShnCell::parse(&text,
+                                             &ShnDataType::SingleFloatingPoint)
+                              .map_err(|_| ShnError::InvalidQuery(
+                                  offset, format!("invalid number `{}`", text)))),
+                }
+            },
+            Some(&Token::Str(ref s)) if !negative => match column.data_type {
+                ShnDataType::StringFixedLen
+                    => ShnCell::StringFixedLen(s.clone()),
+                ShnDataType::StringZeroTerminated
+                    => ShnCell::StringZeroTerminated(s.clone()),
+                ref t => {
+                    let labelled = self.metadata
+                        .and_then(|m| m.column(&column.name))
+                        .and_then(|m| m.parse_value(s, t).ok());
+                    labelled.unwrap_or_else(|| ShnCell::StringZeroTerminated(s.clone()))
+                },
+            },
+            _ => return self.error("a number or a string"),
+        };
+        self.pos += 1;
+        Ok(cell)
+    }
+}
+
+impl ShnQuery {
+    /// Parses a query in a small SQL-like language, checking column names
+    /// and value types against `schema`:
+    ///
+    /// ```text
+    /// SELECT * | column, ...
+    ///     [WHERE condition]
+    ///     [ORDER BY column [ASC | DESC], ...]
+    ///     [LIMIT count]
+    /// ```
+    ///
+    /// Conditions compare a column to a number or a quoted string using `=`,
+    /// `!=`, `<`, `<=`, `>`, `>=` or `~` (contains), and can be combined with
+    /// `AND`, `OR`, `NOT` and parentheses. Keywords are case insensitive,
+    /// column names are not, and may be quoted with backticks.
+    pub fn parse(text: &str, schema: &ShnSchema) -> Result<ShnQuery> {
+        ShnQuery::parse_with_metadata(text, schema, None)
+    }
+
+    /// Parses a query like `parse`, accepting the labels defined by
+    /// `metadata` in place of values, like `WHERE Class = 'Weapon'`.
+    pub fn parse_with_metadata(text: &str,
+                               schema: &ShnSchema,
+                               metadata: Option<&ShnTableMetadata>)
+                               -> Result<ShnQuery> {
+        let mut parser = try!(Parser::with_metadata(text, metadata));
+        let mut query = ShnQuery::new();
+
+        try!(parser.expect_keyword("select"));
+        if !parser.symbol("*") {
+            let mut columns = Vec::new();
+            loop {
+                columns.push(try!(parser.column(schema)).name.clone());
+                if !parser.symbol(",") {
+                    break;
+                }
+            }
+            query = query.select(&columns);
+        }
+        if parser.keyword("where") {
+            query = query.filter(try!(parse_or(&mut parser, schema)));
+        }
+        if parser.keyword("order") {
+            try!(parser.expect_keyword("by"));
+            loop {
+                let column = try!(parser.column(schema));
+                let order = if parser.keyword("desc") {
+                    ShnOrder::Descending
+                } else {
+                    parser.keyword("asc");
+                    ShnOrder::Ascending
+                };
+                query = query.order_by(&column.name, order);
+                if !parser.symbol(",") {
+                    break;
+                }
+            }
+        }
+        if parser.keyword("limit") {
+            let limit = match parser.peek() {
+                Some(&Token::Number(ref n)) => n.parse().ok(),
+                _                           => None,
+            };
+            match limit {
+                Some(limit) => {
+                    parser.next();
+                    query = query.limit(limit);
+                },
+                None => return parser.error("a row count"),
+            }
+        }
+        if !parser.is_end() {
+            return parser.error("end of query");
+        }
+        Ok(query)
+    }
+}
+
+impl ShnPredicate {
+    /// Parses a condition as used in the `WHERE` clause of
+    /// `ShnQuery::parse`, checking it against `schema`.
+    pub fn parse(text: &str, schema: &ShnSchema) -> Result<ShnPredicate> {
+        ShnPredicate::parse_with_metadata(text, schema, None)
+    }
+
+    /// Parses a condition like `parse`, accepting the labels defined by
+    /// `metadata` in place of values.
+    pub fn parse_with_metadata(text: &str,
+                               schema: &ShnSchema,
+                               metadata: Option<&ShnTableMetadata>)
+                               -> Result<ShnPredicate> {
+        let mut parser = try!(Parser::with_metadata(text, metadata));
+        let predicate = try!(parse_or(&mut parser, schema));
+        if !parser.is_end() {
+            return parser.error("end of condition");
+        }
+        Ok(predicate)
+    }
+}
+
+/// Parses a condition of `OR`ed terms.
+fn parse_or(parser: &mut Parser, schema: &ShnSchema) -> Result<ShnPredicate> {
+    let mut predicate = try!(parse_and(parser, schema));
+    while parser.keyword("or") {
+        predicate = predicate.or(try!(parse_and(parser, schema)));
+    }
+    Ok(predicate)
+}
+
+/// Parses a term of `AND`ed factors.
+fn parse_and(parser: &mut Parser, schema: &ShnSchema) -> Result<ShnPredicate> {
+    let mut predicate = try!(parse_not(parser, schema));
+    while parser.keyword("and") {
+        predicate = predicate.and(try!(parse_not(parser, schema)));
+    }
+    Ok(predicate)
+}
+
+/// Parses a negated or parenthesized condition, or a single comparison.
+fn parse_not(parser: &mut Parser, schema: &ShnSchema) -> Result<ShnPredicate> {
+    if parser.keyword("not") {
+        try!(parser.enter());
+        let predicate = try!(parse_not(parser, schema));
+        parser.leave();
+        return Ok(predicate.not());
+    }
+    if parser.symbol("(") {
+        try!(parser.enter());
+        let predicate = try!(parse_or(parser, schema));
+        try!(parser.expect_symbol(")"));
+        parser.leave();
+        return Ok(predicate);
+    }
+
+    let column = try!(parser.column(schema));
+    let comparison = match parser.peek() {
+        Some(&Token::Symbol(s)) => ShnComparison::from_symbol(s),
+        _                       => None,
+    };
+    let comparison = match comparison {
+        Some(c) => {
+            parser.next();
+            c
+        },
+        None => return parser.error("a comparison operator"),
+    };
+    let offset = parser.offset();
+    let value = try!(parser.literal(column));
+    let predicate = ShnPredicate::Compare(column.name.clone(), comparison, value);
+    match predicate.check(schema) {
+        Ok(())  => Ok(predicate),
+        Err(_)  => {
+            let expected = if comparison == ShnComparison::Contains {
+                "`~` needs a string column and a string".to_owned()
+            } else {
+                format!("`{}` has type {:?}", column.name, column.data_type)
+            };
+            Err(ShnError::InvalidQuery(offset,
+                                       format!("type mismatch, {}", expected)))
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::shn_metadata::ShnColumnMetadata;
+
+    fn schema() -> ShnSchema {
+        let mut schema = ShnSchema::with_id_column();
+        schema.columns.push(ShnColumn::new_unsigned_integer("ID"));
+        schema.columns.push(ShnColumn::new_string_fixed_len("Name", 16));
+        schema.columns.push(ShnColumn::new_byte("Level"));
+        schema.columns.push(ShnColumn::new_signed_short("Max HP"));
+        schema
+    }
+
+    fn error_offset<T: ::std::fmt::Debug>(result: Result<T>) -> (usize, String) {
+        match result {
+            Err(ShnError::InvalidQuery(offset, message)) => (offset, message),
+            r => panic!("{:?}", r),
+        }
+    }
+
+    #[test]
+    fn tokens() {
+        let tokens: Vec<Token> = tokenize("a_1 >= 1.5 'it''s' `x y`<>\"q\"")
+            .unwrap()
+            .into_iter()
+            .map(|(_, t)| t)
+            .collect();
+        assert_eq!(tokens, vec![Token::Ident("a_1".to_owned()),
+                                Token::Symbol(">="),
+                                Token::Number("1.5".to_owned()),
+                                Token::Str("it's".to_owned()),
+                                Token::Ident("x y".to_owned()),
+                                Token::Symbol("<>"),
+                                Token::Str("q".to_owned())]);
+        let offsets: Vec<usize> = tokenize("a  =  'b'").unwrap()
+            .into_iter()
+            .map(|(o, _)| o)
+            .collect();
+        assert_eq!(offsets, vec![0, 3, 6]);
+        assert_eq!(error_offset(tokenize("a = 'b")).0, 4);
+        assert_eq!(error_offset(tokenize("a = #")),
+                   (4, "unexpected character `#`".to_owned()));
+    }
+
+    #[test]
+    fn parse_query() {
+        let schema = schema();
+        let query = ShnQuery::parse(
+            "select ID, `Max HP` where Level >= 60 and not (Name ~ 'Axe' \
+             or ID = 3) ORDER BY Level desc, Name LIMIT 5", &schema).unwrap();
+        let expected = ShnQuery::new()
+            .select(&["ID", "Max HP"])
+            .filter(ShnPredicate::compare("Level", ShnComparison::GreaterOrEqual, 60u8)
+                    .and(ShnPredicate::compare("Name", ShnComparison::Contains,
+                                               ShnCell::StringFixedLen("Axe".to_owned()))
+                         .or(ShnPredicate::compare("ID", ShnComparison::Equal, 3u32))
+                         .not()))
+            .order_by("Level", ShnOrder::Descending)
+            .order_by("Name", ShnOrder::Ascending)
+            .limit(5);
+        assert_eq!(format!("{:?}", query), format!("{:?}", expected));
+        assert_eq!(format!("{:?}", ShnQuery::parse("SELECT *", &schema).unwrap()),
+                   format!("{:?}", ShnQuery::new()));
+    }
+
+    #[test]
+    fn parse_precedence() {
+        let schema = schema();
+        let predicate = ShnPredicate::parse(
+            "ID = 1 or ID = 2 and Level < 3", &schema).unwrap();
+        assert_eq!(predicate.to_string(), "(ID = 1 OR (ID = 2 AND Level < 3))");
+        let predicate = ShnPredicate::parse("not not ID != 1", &schema).unwrap();
+        assert_eq!(predicate.to_string(), "NOT NOT ID != 1");
+    }
+
+    #[test]
+    fn parse_literals() {
+        let schema = schema();
+        assert_eq!(ShnPredicate::parse("`Max HP` > -5", &schema).unwrap(),
+                   ShnPredicate::compare("Max HP", ShnComparison::Greater,
+                                         ShnCell::SignedShort(-5)));
+        // Out of range for the column, but still comparable
+        assert_eq!(ShnPredicate::parse("Level < 300.5", &schema).unwrap(),
+                   ShnPredicate::compare("Level", ShnComparison::Less, 300.5f32));
+        assert_eq!(ShnPredicate::parse("Name = \"a\"", &schema).unwrap(),
+                   ShnPredicate::compare("Name", ShnComparison::Equal,
+                                         ShnCell::StringFixedLen("a".to_owned())));
+    }
+
+    #[test]
+    fn parse_labels() {
+        let schema = schema();
+        let mut metadata = ShnTableMetadata::default();
+        let mut level = ShnColumnMetadata::default();
+        level.labels.insert(7, "Seven".to_owned());
+        metadata.columns.insert("Level".to_owned(), level);
+        assert_eq!(ShnPredicate::parse_with_metadata("Level = 'Seven'", &schema,
+                                                     Some(&metadata)).unwrap(),
+                   ShnPredicate::compare("Level", ShnComparison::Equal, 7u8));
+        assert!(ShnPredicate::parse("Level = 'Seven'", &schema).is_err());
+    }
+
+    #[test]
+    fn parse_errors() {
+        let schema = schema();
+        let cases = [
+            ("", 0, "expected `SELECT`, found end of input"),
+            ("select", 6, "expected a column name, found end of input"),
+            ("select Nope", 7, "unknown column `Nope`"),
+            ("select * where", 14, "expected a column name, found end of input"),
+            ("select * where Level = 'x'", 23, "type mismatch, `Level` has type Byte"),
+            ("select * where Level ~ 1", 23,
+             "type mismatch, `~` needs a string column and a string"),
+            ("select * where Level 1", 21, "expected a comparison operator, found `1`"),
+            ("select * where (Level = 1", 25, "expected `)`, found end of input"),
+            ("select * order Level", 15, "expected `BY`, found `Level`"),
+            ("select * limit x", 15, "expected a row count, found `x`"),
+            ("select * limit 1 2", 17, "expected end of query, found `2`"),
+            ("select * where Level = -'a'", 24, "expected a number or a string, found 'a'"),
+            ("select * where Level = 1.2.3", 23, "invalid number `1.2.3`"),
+        ];
+        for &(text, offset, message) in &cases {
+            assert_eq!(error_offset(ShnQuery::parse(text, &schema)),
+                       (offset, message.to_owned()), "{}", text);
+        }
+        assert_eq!(error_offset(ShnPredicate::parse("ID = 1 )", &schema)),
+                   (7, "expected end of condition, found `)`".to_owned()));
+    }
+
+    #[test]
+    fn nesting_limit() {
+        let schema = schema();
+        let nested = |depth: usize| {
+            format!("{}ID = 1{}", "(".repeat(depth), ")".repeat(depth))
+        };
+        assert!(ShnPredicate::parse(&nested(MAX_DEPTH), &schema).is_ok());
+        assert_eq!(error_offset(ShnPredicate::parse(&nested(100_000), &schema)),
+                   (MAX_DEPTH + 1, "nested too deeply".to_owned()));
+        let negated = format!("{}ID = 1", "not ".repeat(100_000));
+        assert!(ShnPredicate::parse(&negated, &schema).is_err());
+    }
+}
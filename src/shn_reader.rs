@@ -3,16 +3,19 @@ use super::shn::{
     Endianess,
     Result,
     ShnSchema,
+    ShnHeader,
     ShnFile,
     ShnRow,
     ShnColumn,
     ShnCell,
     ShnDataType,
     ShnError,
-    decrypt,
+    ShnCipher,
 };
+use super::shn_table::ShnTable;
 
-use ::std::io::{Read, Cursor };
+use ::std::io::{ self, Read, Seek, SeekFrom, BufReader };
+use ::std::ops::Range;
 use ::std::sync::Arc;
 
 use encoding::DecoderTrap;
@@ -20,21 +23,238 @@ use ::encoding::types::EncodingRef;
 use ::byteorder::ReadBytesExt;
 
 
+/// Options for reading only parts of a file, see `read_with_options`.
+#[derive(Clone, Debug, Default)]
+pub struct ReadOptions {
+    /// Names of the columns to read, all columns are read if `None`. The
+    /// `__ID__` pseudo-column is always read. Columns keep the order they
+    /// have in the file.
+    pub columns:    Option<Vec<String>>,
+    /// The rows to read, all rows are read if `None`.
+    pub rows:       Option<Range<usize>>,
+}
+
+impl ReadOptions {
+    /// Constructs new `ReadOptions`, which read the whole file.
+    pub fn new() -> Self {
+        ReadOptions::default()
+    }
+}
+
+/// Decrypts the data read from the wrapped `Read` on the fly, and stops at
+/// the end of the encrypted data.
+pub struct ShnCipherReader<T> {
+    inner:      T,
+    cipher:     ShnCipher,
+    len:        usize,
+}
+
+impl<T> ShnCipherReader<T> {
+    fn new(inner: T, len: usize) -> Self {
+        ShnCipherReader {
+            inner:      inner,
+            cipher:     ShnCipher::new(len),
+            len:        len,
+        }
+    }
+
+    /// Returns the number of bytes left until the end of the data.
+    fn remaining(&self) -> usize {
+        self.len.saturating_sub(self.cipher.position())
+    }
+}
+
+impl<T: Read> Read for ShnCipherReader<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let max = ::std::cmp::min(buf.len(), self.remaining());
+        let len = try!(self.inner.read(&mut buf[..max]));
+        self.cipher.apply(&mut buf[..len]);
+        Ok(len)
+    }
+}
+
+/// Positions are relative to the start of the encrypted data.
+impl<T: Seek> Seek for ShnCipherReader<T> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let current = self.cipher.position() as i64;
+        let target = match pos {
+            SeekFrom::Start(p)      => p as i64,
+            SeekFrom::End(p)        => self.len as i64 + p,
+            SeekFrom::Current(p)    => current + p,
+        };
+        if target < 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                      "seek before start of data"));
+        }
+        try!(self.inner.seek(SeekFrom::Current(target - current)));
+        if target >= current {
+            self.cipher.skip((target - current) as usize);
+        } else {
+            self.cipher = ShnCipher::new(self.len);
+            self.cipher.skip(target as usize);
+        }
+        Ok(target as u64)
+    }
+}
+
 // TODO: I might want to move this to a trait instead?
 /// Functions for reading an `ShnFile` from a `Read`
 pub struct ShnReader;
 
 impl ShnReader {
     /// Reads a `ShnFile` from the given `Read` implementor.
-    pub fn read_from<T: Read>(mut source: T, enc: &EncodingRef)
+    pub fn read_from<T: Read>(source: T, enc: &EncodingRef)
                               -> Result<ShnFile> {
+        let (header, reader) = try!(ShnReader::read_preamble(source, enc));
+        let mut reader = BufReader::new(reader);
+        let mut file = ShnFile::new(header.crypt_header,
+                                    header.header,
+                                    header.schema);
+        try!(ShnReader::read_rows(&mut file,
+                                  &mut reader,
+                                  header.record_count as usize,
+                                  enc));
+	      Ok(file)
+    }
+
+    /// Reads the rows of a `SHN` file from the given `Read` implementor
+    /// directly into a `ShnTable`.
+    pub fn read_table_from<T: Read>(source: T, enc: &EncodingRef)
+                                    -> Result<ShnTable> {
+        let (header, reader) = try!(ShnReader::read_preamble(source, enc));
+        let capacity = ShnReader::row_capacity(&header.schema,
+                                               &reader,
+                                               header.record_count as usize);
+        let mut reader = BufReader::new(reader);
+        let mut table = ShnTable::new(header.crypt_header,
+                                      header.header,
+                                      header.schema.clone());
+        try!(table.reserve(capacity));
+        for _ in 0..header.record_count {
+            let mut cells = Vec::with_capacity(header.schema.columns.len());
+            for c in &header.schema.columns {
+                cells.push(try!(ShnReader::read_cell(&mut reader, c, enc)));
+            }
+            try!(table.push_cells(cells));
+        }
+        Ok(table)
+    }
+
+    /// Reads the parts of a `ShnFile` selected by `options` from the given
+    /// `Read` implementor. Columns which are not selected are skipped without
+    /// being decoded, and if all columns have a fixed length, rows before the
+    /// selected ones are skipped by seeking past them.
+    pub fn read_with_options<T>(source: T,
+                                enc: &EncodingRef,
+                                options: &ReadOptions)
+                                -> Result<ShnFile>
+        where T: Read + Seek {
+        let (header, reader) = try!(ShnReader::read_preamble(source, enc));
+        let mut reader = BufReader::new(reader);
+
+        let full_schema = header.schema;
+        let selected: Vec<bool> = match options.columns {
+            Some(ref names) => {
+                for name in names {
+                    if !full_schema.columns.iter().any(|c| c.name == *name) {
+                        return Err(ShnError::UnknownColumn(name.clone()));
+                    }
+                }
+                full_schema.columns.iter()
+                    .map(|c| c.is_id() || names.contains(&c.name))
+                    .collect()
+            },
+            None => vec![true; full_schema.columns.len()],
+        };
+        let schema = Arc::new(ShnSchema {
+            columns: full_schema.columns.iter()
+                .zip(selected.iter())
+                .filter(|&(_, s)| *s)
+                .map(|(c, _)| c.clone())
+                .collect(),
+        });
+
+        let record_count = header.record_count as usize;
+        let rows = match options.rows {
+            Some(ref r) => {
+                let end = ::std::cmp::min(r.end, record_count);
+                ::std::cmp::min(r.start, end)..end
+            },
+            None        => 0..record_count,
+        };
+
+        match full_schema.fixed_record_length() {
+            Some(len) => {
+                try!(reader.seek(SeekFrom::Current((len * rows.start) as i64))
+                     .map_err(|_| ShnError::InvalidFile));
+            },
+            None => {
+                for _ in 0..rows.start {
+                    for c in &full_schema.columns {
+                        try!(ShnReader::skip_cell(&mut reader, c));
+                    }
+                }
+            },
+        }
+
+        let mut data = Vec::with_capacity(
+            ShnReader::row_capacity(&full_schema,
+                                    reader.get_ref(),
+                                    rows.end - rows.start));
+        for _ in rows {
+            let mut cells = Vec::with_capacity(schema.columns.len());
+            for (c, s) in full_schema.columns.iter().zip(selected.iter()) {
+                if *s {
+                    cells.push(try!(ShnReader::read_cell(&mut reader, c, enc)));
+                } else {
+                    try!(ShnReader::skip_cell(&mut reader, c));
+                }
+            }
+            data.push(ShnRow {
+                schema: schema.clone(),
+                data: cells,
+            });
+        }
+
+        let mut file = ShnFile::new(header.crypt_header, header.header, schema);
+        file.data = data;
+        Ok(file)
+    }
+
+    /// Reads everything up to the first row from the given `Read`
+    /// implementor, without touching the rows themselves.
+    pub fn read_header<T: Read>(source: T, enc: &EncodingRef)
+                                -> Result<ShnHeader> {
+        ShnReader::read_preamble(source, enc).map(|(header, _)| header)
+    }
+
+    /// Returns how many of `count` rows of `schema` may follow in `reader`,
+    /// judging by the length of the smallest possible row. Used to reserve
+    /// memory without trusting the record count of the file.
+    fn row_capacity<T>(schema: &ShnSchema,
+                       reader: &ShnCipherReader<T>,
+                       count: usize)
+                       -> usize {
+        let min_length = schema.columns.iter()
+            .map(|c| c.fixed_length().unwrap_or(1))
+            .sum::<usize>();
+        match reader.remaining().checked_div(min_length) {
+            Some(rows)  => ::std::cmp::min(rows, count),
+            None        => count,
+        }
+    }
+
+    /// Reads the headers and the schema, and returns them along with a
+    /// reader positioned at the first row.
+    fn read_preamble<T: Read>(mut source: T, enc: &EncodingRef)
+                              -> Result<(ShnHeader, ShnCipherReader<T>)> {
 	      let crypt_header = try!(ShnReader::read_crypt_header(&mut source));
 	      let data_length = try!(source.read_i32::<Endianess>()
                                .map_err(|_| ShnError::InvalidFile)) - 0x24;
-	      let mut data = vec![0; data_length as usize];
-	      try!(source.read(&mut data[..]).map_err(|_| ShnError::InvalidFile));
-	      decrypt(&mut data[..]);
-	      let mut reader = Cursor::new(data);
+        if data_length < 0 {
+            return Err(ShnError::InvalidFile);
+        }
+        let mut reader = ShnCipherReader::new(source, data_length as usize);
 
 	      let header = try!(reader.read_u32::<Endianess>()
                           .map_err(|_| ShnError::InvalidFile));
@@ -46,27 +266,28 @@ impl ShnReader {
                                .map_err(|_| ShnError::InvalidFile));
         let schema = try!(ShnReader::read_schema(&mut reader,
                                                  colmn_count,
-                                                 default_len as i32,
                                                  enc));
-        let mut file = ShnFile {
+        let record_length = try!(schema.declared_record_length());
+        if record_length != default_len {
+            return Err(ShnError::RecordLengthMismatch(default_len,
+                                                      record_length));
+        }
+        let header = ShnHeader {
             crypt_header: crypt_header,
             header: header,
+            record_count: record_count,
+            default_record_length: default_len,
             schema: Arc::new(schema),
-            data: Vec::new()
         };
-        try!(ShnReader::read_rows(&mut file,
-                                  &mut reader,
-                                  record_count as usize,
-                                  enc));
-	      Ok(file)
+        Ok((header, reader))
     }
 
     fn read_rows<T>(file: &mut ShnFile,
-                    reader: &mut Cursor<T>,
+                    reader: &mut T,
                     count: usize,
                     enc: &EncodingRef)
                     -> Result<()>
-	      where T: AsRef<[u8]> {
+	      where T: Read {
 	      for _ in 0..count {
 	          let row = try!(ShnReader::read_row(file, reader, enc));
 	          file.data.push(row);
@@ -92,16 +313,14 @@ impl ShnReader {
     fn read_crypt_header<T: Read>(source: &mut T)
                                   -> Result<[u8; SHN_CRYPT_HEADER_LEN]> {
 	      let mut buffer = [0; SHN_CRYPT_HEADER_LEN];
-	      try!(source.read(&mut buffer).map_err(|_| ShnError::InvalidFile));
+	      try!(source.read_exact(&mut buffer).map_err(|_| ShnError::InvalidFile));
 	      Ok(buffer)
     }
 
     fn read_schema<T: Read>(source: &mut T,
 			                      column_count: u32,
-			                      expected_len: i32,
 			                      enc: &EncodingRef) -> Result<ShnSchema> {
-	      let mut columns = Vec::with_capacity(column_count as usize);
-	      let mut len = 2; // because of that intrinsic row.
+	      let mut columns = Vec::new();
 	      /* This one seems to be intrinsic. I don't actually think it holds 
          * any valuable data or anything of relevance at all, to be honest. 
          * However it is there. weird.
@@ -111,14 +330,10 @@ impl ShnReader {
          * B) If the value is actually relevant for the file we keep it the 
          *    right way, without loosing any information.
          */
-	      columns.push(ShnColumn {
-	          name: "__ID__".to_owned(),
-	          data_type: ShnDataType::UnsignedShort,
-	          data_length: 2,
-	      });
+	      columns.push(ShnColumn::new_id());
 	      for _ in 0..column_count {
 	          let mut buf = vec![0; 48];
-	          try!(source.read(&mut buf[..])
+	          try!(source.read_exact(&mut buf[..])
                  .map_err(|_| ShnError::InvalidFile));
 	          let name = try!(enc.decode(&buf[..], DecoderTrap::Strict)
                             .map_err(|_| ShnError::InvalidEncoding));
@@ -127,23 +342,37 @@ impl ShnReader {
                              .map_err(|_| ShnError::InvalidFile));
 	          let clen = try!(source.read_i32::<Endianess>()
                             .map_err(|_| ShnError::InvalidFile));
+	          let data_type = try!(ShnDataType::try_from_id(ctype)
+                                 .ok_or_else(|| ShnError::UnknownDataType(
+                                     name.clone(), ctype)));
 	          columns.push(ShnColumn {
 		            name: name,
-		            data_type: ShnDataType::from_id(ctype),
+		            data_type: data_type,
 		            data_length: clen,
 	          });
-	          len = len + clen;
 	      }
+	      Ok(ShnSchema {
+		        columns: columns,
+	      })
+    }
 
-	      if len != expected_len {
-	          println!("length does not equal expected length! {} != {}",
-                     len, expected_len);
-	          Err(ShnError::InvalidSchema)
-	      } else {
-	          Ok(ShnSchema {
-		            columns: columns,
-	          })
-	      }
+    /// Moves past a single cell without decoding it.
+    fn skip_cell<T: Read>(source: &mut T, column: &ShnColumn) -> Result<()> {
+        match column.fixed_length() {
+            Some(len) => {
+                let skipped = try!(io::copy(&mut source.take(len as u64),
+                                            &mut io::sink())
+                                   .map_err(|_| ShnError::InvalidFile));
+                if skipped != len as u64 {
+                    return Err(ShnError::InvalidFile);
+                }
+            },
+            None => {
+                while try!(source.read_u8()
+                           .map_err(|_| ShnError::InvalidFile)) != 0 {}
+            },
+        }
+        Ok(())
     }
 
     fn read_cell<T: Read>(source: &mut T,
@@ -154,13 +383,10 @@ impl ShnReader {
         match column.data_type {
 	    ShnDataType::StringFixedLen => {
 		let mut buf = vec![0; column.data_length as usize];
-		try!(cursor.read(&mut buf[..])
+		try!(cursor.read_exact(&mut buf[..])
                      .map_err(|_| ShnError::InvalidFile));
 		let str = try!(enc.decode(&buf[..], DecoderTrap::Ignore)
-                               .map_err(|e| {
-		                   println!("error while decoding: {:?}", e);
-		                   ShnError::InvalidEncoding
-		               }));
+                               .map_err(|_| ShnError::InvalidEncoding));
 		Ok(ShnCell::StringFixedLen(str.trim_matches('\u{0}')
                                            .to_owned()))
 	    },
@@ -216,3 +442,243 @@ impl ShnReader {
 	}
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::shn::decrypt;
+    use super::super::shn_writer::ShnWriter;
+    use ::encoding::all::ASCII;
+    use ::std::io::Cursor;
+
+    fn file(zero_terminated: bool, count: usize) -> ShnFile {
+        let mut schema = ShnSchema::with_id_column();
+        schema.columns.push(ShnColumn::new_unsigned_integer("ID"));
+        schema.columns.push(ShnColumn::new_string_fixed_len("Name", 8));
+        if zero_terminated {
+            schema.columns.push(ShnColumn::new_string_terminated("Desc"));
+        }
+        schema.columns.push(ShnColumn::new_byte("Level"));
+        let schema = Arc::new(schema);
+        let mut file = ShnFile::new([0; SHN_CRYPT_HEADER_LEN], 0, schema.clone());
+        for i in 0..count {
+            let mut data = vec![ShnCell::UnsignedShort(i as u16),
+                                ShnCell::UnsignedInteger(i as u32 * 3),
+                                ShnCell::StringFixedLen(format!("n{}", i))];
+            if zero_terminated {
+                data.push(ShnCell::StringZeroTerminated("x".repeat(i % 4)));
+            }
+            data.push(ShnCell::Byte(i as u8));
+            file.append_row(ShnRow {
+                schema: schema.clone(),
+                data:   data,
+            }).unwrap();
+        }
+        file
+    }
+
+    fn write(file: &ShnFile) -> Vec<u8> {
+        let enc: EncodingRef = ASCII;
+        let mut buf = Vec::new();
+        ShnWriter::write_to(file, &enc, &mut buf).unwrap();
+        buf
+    }
+
+    fn cipher_reader(plain: &[u8]) -> ShnCipherReader<Cursor<Vec<u8>>> {
+        let mut data = plain.to_vec();
+        decrypt(&mut data[..]);
+        ShnCipherReader::new(Cursor::new(data), plain.len())
+    }
+
+    #[test]
+    fn cipher_reader_round_trip() {
+        let plain: Vec<u8> = (0..1500).map(|i| (i * 7 % 251) as u8).collect();
+        let mut reader = cipher_reader(&plain);
+        let mut read = Vec::new();
+        reader.read_to_end(&mut read).unwrap();
+        assert_eq!(read, plain);
+    }
+
+    #[test]
+    fn cipher_reader_seek() {
+        let plain: Vec<u8> = (0..1500).map(|i| (i * 7 % 251) as u8).collect();
+        let mut reader = cipher_reader(&plain);
+        let mut buf = [0; 10];
+        for &pos in &[700, 3, 1024, 0, 1490] {
+            assert_eq!(reader.seek(SeekFrom::Start(pos)).unwrap(), pos);
+            reader.read_exact(&mut buf).unwrap();
+            assert_eq!(&buf[..], &plain[pos as usize..pos as usize + 10]);
+        }
+        assert_eq!(reader.seek(SeekFrom::End(-5)).unwrap(), 1495);
+        assert_eq!(reader.seek(SeekFrom::Current(-95)).unwrap(), 1400);
+        reader.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf[..], &plain[1400..1410]);
+        assert!(reader.seek(SeekFrom::Current(-2000)).is_err());
+    }
+
+    #[test]
+    fn cipher_reader_past_end() {
+        let mut reader = cipher_reader(&[1, 2, 3, 4]);
+        assert_eq!(reader.seek(SeekFrom::Start(10)).unwrap(), 10);
+        assert_eq!(reader.read(&mut [0; 4]).unwrap(), 0);
+        assert_eq!(reader.seek(SeekFrom::Start(2)).unwrap(), 2);
+        let mut rest = Vec::new();
+        reader.read_to_end(&mut rest).unwrap();
+        assert_eq!(rest, vec![3, 4]);
+    }
+
+    #[test]
+    fn read_round_trip() {
+        let enc: EncodingRef = ASCII;
+        for &zero_terminated in &[false, true] {
+            let file = file(zero_terminated, 30);
+            let written = write(&file);
+            let read = ShnReader::read_from(&written[..], &enc).unwrap();
+            assert_eq!(read.schema, file.schema);
+            assert_eq!(read.data, file.data);
+            let table = ShnReader::read_table_from(&written[..], &enc).unwrap();
+            assert_eq!(table.len(), 30);
+            assert_eq!(table.schema(), &file.schema);
+            for (row, expected) in table.rows().zip(file.data.iter()) {
+                assert_eq!(row.to_row().data, expected.data);
+            }
+            assert_eq!(table.to_file(), file);
+        }
+    }
+
+    #[test]
+    fn read_header_only() {
+        let enc: EncodingRef = ASCII;
+        let mut file = file(true, 20);
+        file.header = 9;
+        file.crypt_header = [5; SHN_CRYPT_HEADER_LEN];
+        let written = write(&file);
+
+        let mut source = &written[..];
+        let header = ShnReader::read_header(&mut source, &enc).unwrap();
+        assert_eq!(header.crypt_header, file.crypt_header);
+        assert_eq!(header.header, 9);
+        assert_eq!(header.record_count, 20);
+        assert_eq!(header.default_record_length,
+                   file.schema.declared_record_length().unwrap());
+        assert_eq!(header.schema, file.schema);
+
+        // Only the headers and the column definitions have been read
+        let preamble = SHN_CRYPT_HEADER_LEN + 4 + 16 + 4 * 56;
+        assert_eq!(written.len() - source.len(), preamble);
+        let rows = file.row_offsets(&enc).unwrap();
+        assert_eq!(source.len(), *rows.last().unwrap());
+    }
+
+    #[test]
+    fn read_with_options_selects() {
+        let enc: EncodingRef = ASCII;
+        for &zero_terminated in &[false, true] {
+            let file = file(zero_terminated, 30);
+            let written = write(&file);
+            let mut options = ReadOptions::new();
+            options.columns = Some(vec!["Level".to_owned(), "ID".to_owned()]);
+            options.rows = Some(10..13);
+            let read = ShnReader::read_with_options(Cursor::new(&written[..]),
+                                                    &enc, &options).unwrap();
+            let names: Vec<&str> = read.schema.columns.iter()
+                .map(|c| &c.name[..])
+                .collect();
+            assert_eq!(names, vec!["__ID__", "ID", "Level"]);
+            assert_eq!(read.data.len(), 3);
+            for (row, i) in read.data.iter().zip(10..) {
+                assert_eq!(row.data, vec![ShnCell::UnsignedShort(i as u16),
+                                          ShnCell::UnsignedInteger(i * 3),
+                                          ShnCell::Byte(i as u8)]);
+            }
+
+            let all = ShnReader::read_with_options(Cursor::new(&written[..]),
+                                                   &enc,
+                                                   &ReadOptions::new()).unwrap();
+            assert_eq!(all.data, file.data);
+        }
+    }
+
+    #[test]
+    fn read_with_options_clamps_rows() {
+        let enc: EncodingRef = ASCII;
+        let written = write(&file(true, 5));
+        let mut options = ReadOptions::new();
+        options.rows = Some(3..100);
+        let read = ShnReader::read_with_options(Cursor::new(&written[..]),
+                                                &enc, &options).unwrap();
+        assert_eq!(read.data.len(), 2);
+        options.rows = Some(50..100);
+        let read = ShnReader::read_with_options(Cursor::new(&written[..]),
+                                                &enc, &options).unwrap();
+        assert!(read.data.is_empty());
+    }
+
+    #[test]
+    fn read_with_options_unknown_column() {
+        let enc: EncodingRef = ASCII;
+        let written = write(&file(false, 5));
+        let mut options = ReadOptions::new();
+        options.columns = Some(vec!["Nope".to_owned()]);
+        match ShnReader::read_with_options(Cursor::new(&written[..]),
+                                           &enc, &options) {
+            Err(ShnError::UnknownColumn(ref name)) if name == "Nope" => {},
+            r => panic!("{:?}", r.map(|f| f.data)),
+        }
+    }
+
+    #[test]
+    fn huge_record_count() {
+        let enc: EncodingRef = ASCII;
+        for &zero_terminated in &[false, true] {
+            let mut written = write(&file(zero_terminated, 3));
+            // The record count is the second field of the encrypted data
+            decrypt(&mut written[0x24..]);
+            for b in &mut written[0x24 + 4..0x24 + 8] {
+                *b = 0xff;
+            }
+            decrypt(&mut written[0x24..]);
+            assert!(ShnReader::read_from(&written[..], &enc).is_err());
+            assert!(ShnReader::read_table_from(&written[..], &enc).is_err());
+            let mut options = ReadOptions::new();
+            options.rows = Some(1..usize::max_value());
+            assert!(ShnReader::read_with_options(Cursor::new(&written[..]),
+                                                 &enc, &options).is_err());
+        }
+    }
+
+    #[test]
+    fn truncated_file() {
+        let enc: EncodingRef = ASCII;
+        let written = write(&file(true, 3));
+        for len in &[0, 10, 0x24, 0x30, written.len() - 1] {
+            match ShnReader::read_from(&written[..*len], &enc) {
+                Err(ShnError::InvalidFile) => {},
+                r => panic!("{}: {:?}", len, r.map(|f| f.data)),
+            }
+        }
+    }
+
+    #[test]
+    fn unknown_data_type() {
+        let enc: EncodingRef = ASCII;
+        let mut written = write(&file(false, 2));
+        decrypt(&mut written[0x24..]);
+        // The type of `Name`, after the header, the record count and length,
+        // the column count and the name, type and length of `ID`
+        let offset = 0x24 + 16 + 56 + 48;
+        assert_eq!(&written[offset - 48..offset - 44], b"Name");
+        written[offset] = 99;
+        decrypt(&mut written[0x24..]);
+        match ShnReader::read_from(&written[..], &enc) {
+            Err(ShnError::UnknownDataType(ref column, 99)) => {
+                assert_eq!(column, "Name");
+            },
+            r => panic!("{:?}", r.map(|f| f.data)),
+        }
+        match ShnReader::read_header(&mut &written[..], &enc) {
+            Err(ShnError::UnknownDataType(_, 99)) => {},
+            r => panic!("{:?}", r.map(|h| h.schema)),
+        }
+    }
+}
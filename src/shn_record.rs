@@ -0,0 +1,419 @@
+use ::std::convert::TryFrom;
+use ::std::fmt;
+use ::std::sync::Arc;
+
+use super::shn::{ ShnDataType, ShnCell, ShnColumn, ShnSchema, ShnRow, ShnFile,
+                  ShnError, Result };
+
+/// A typed row of a table with a known layout, usually defined through
+/// `shn_record!`.
+pub trait ShnRecord: Sized {
+    /// Returns the name of the table, like `ItemInfo`.
+    fn table_name() -> &'static str;
+
+    /// Returns the expected data columns, without the `__ID__`
+    /// pseudo-column.
+    fn columns() -> Vec<ShnColumn>;
+
+    /// Constructs a record from the cells of the data columns, in the order
+    /// of `columns`.
+    fn from_cells(cells: &[ShnCell]) -> Result<Self>;
+
+    /// Returns the cells of the data columns, in the order of `columns`.
+    fn to_cells(&self) -> Result<Vec<ShnCell>>;
+
+    /// Returns the expected schema, starting with the `__ID__`
+    /// pseudo-column.
+    fn schema() -> ShnSchema {
+        let mut schema = ShnSchema::with_id_column();
+        schema.columns.extend(Self::columns());
+        schema
+    }
+
+    /// Checks that the data columns of `schema` match the expected ones,
+    /// failing with `ShnError::SchemaMismatch` listing the differences
+    /// otherwise.
+    fn check_schema(schema: &ShnSchema) -> Result<()> {
+        let differences = schema.differences(&Self::schema());
+        if differences.is_empty() {
+            Ok(())
+        } else {
+            Err(ShnError::SchemaMismatch(Self::table_name().to_owned(),
+                                         differences))
+        }
+    }
+    /// Constructs a record from `row`, skipping its `__ID__` pseudo-column.
+    /// The schema of the row is not checked, see `check_schema`.
+    fn from_row(row: &ShnRow) -> Result<Self> {
+        let skip = if row.schema.id_column().is_some() { 1 } else { 0 };
+        Self::from_cells(&row.data[skip..])
+    }
+
+    /// Returns the record as a row of `schema`, with `id` in the `__ID__`
+    /// pseudo-column if the schema has one. The schema is not checked, see
+    /// `check_schema`.
+    fn to_row(&self, schema: &Arc<ShnSchema>, id: u16) -> Result<ShnRow> {
+        let mut cells = Vec::with_capacity(schema.columns.len());
+        if schema.id_column().is_some() {
+            cells.push(ShnCell::UnsignedShort(id));
+        }
+        cells.extend(try!(self.to_cells()));
+        Ok(ShnRow {
+            schema: schema.clone(),
+            data:   cells,
+        })
+    }
+}
+
+/// A difference between the data columns of two schemas, see
+/// `ShnSchema::differences`.
+#[derive(Clone, PartialEq, Debug)]
+pub enum ShnColumnDifference {
+    /// An expected column does not exist
+    Missing(ShnColumn),
+    /// A column is not expected
+    Unexpected(ShnColumn),
+    /// A column has a different type or length than expected
+    Changed {
+        /// The expected column
+        expected:   ShnColumn,
+        /// The actual column
+        found:      ShnColumn,
+    },
+    /// A column is out of order compared to the other shared columns
+    Moved {
+        /// The name of the column
+        name:       String,
+        /// The expected position among the data columns
+        expected:   usize,
+        /// The actual position among the data columns
+        found:      usize,
+    },
+}
+
+impl fmt::Display for ShnColumnDifference {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let describe = |c: &ShnColumn| match c.data_type {
+            ShnDataType::StringFixedLen
+                => format!("{:?}({})", c.data_type, c.data_length),
+            ref t => format!("{:?}", t),
+        };
+        match *self {
+            ShnColumnDifference::Missing(ref c)
+                => write!(f, "missing column `{}` {}", c.name, describe(c)),
+            ShnColumnDifference::Unexpected(ref c)
+                => write!(f, "unexpected column `{}` {}", c.name, describe(c)),
+            ShnColumnDifference::Changed { ref expected, ref found }
+                => write!(f, "column `{}` is {} instead of {}", found.name,
+                          describe(found), describe(expected)),
+            ShnColumnDifference::Moved { ref name, expected, found }
+                => write!(f, "column `{}` is at {} instead of {}", name, found,
+                          expected),
+        }
+    }
+}
+
+impl ShnSchema {
+    /// Compares the data columns of this schema against the `expected`
+    /// ones by name. Columns are compared by type and length, and columns
+    /// existing in both schemas are expected in the same order. Every
+    /// shared column at another position than expected is reported as
+    /// moved, so swapping two columns reports both. Returns nothing if both
+    /// match.
+    pub fn differences(&self, expected: &ShnSchema) -> Vec<ShnColumnDifference> {
+        let columns = self.data_columns();
+        let expected = expected.data_columns();
+        let find = |columns: &[ShnColumn], name: &str| {
+            columns.iter().position(|c| c.name == name)
+        };
+        let mut differences = Vec::new();
+        for e in expected {
+            match find(columns, &e.name).map(|p| &columns[p]) {
+                None => differences.push(ShnColumnDifference::Missing(e.clone())),
+                Some(c) if c.data_type != e.data_type
+                    || c.data_length != e.data_length => {
+                    differences.push(ShnColumnDifference::Changed {
+                        expected:   e.clone(),
+                        found:      c.clone(),
+                    });
+                },
+                Some(_) => {},
+            }
+        }
+        for c in columns {
+            if find(expected, &c.name).is_none() {
+                differences.push(ShnColumnDifference::Unexpected(c.clone()));
+            }
+        }
+
+        // Only the relative order of shared columns matters, so a single
+        // missing column does not move all columns after it
+        let shared = |a: &[ShnColumn], b: &[ShnColumn]| -> Vec<String> {
+            a.iter()
+                .filter(|c| find(b, &c.name).is_some())
+                .map(|c| c.name.clone())
+                .collect()
+        };
+        let found_order = shared(columns, expected);
+        let expected_order = shared(expected, columns);
+        for (i, name) in found_order.iter().enumerate() {
+            if expected_order[i] != *name {
+                differences.push(ShnColumnDifference::Moved {
+                    name:       name.clone(),
+                    expected:   find(expected, name).unwrap(),
+                    found:      find(columns, name).unwrap(),
+                });
+            }
+        }
+        differences
+    }
+}
+
+impl ShnFile {
+    /// Converts all rows into records, after checking the schema with
+    /// `ShnRecord::check_schema`. Fails with `ShnError::RowErrors` listing
+    /// every row which can not be converted.
+    pub fn records<T: ShnRecord>(&self) -> Result<Vec<T>> {
+        try!(T::check_schema(&self.schema));
+        let mut records = Vec::with_capacity(self.data.len());
+        let mut errors = Vec::new();
+        for (i, row) in self.data.iter().enumerate() {
+            match T::from_row(row) {
+                Ok(r)   => records.push(r),
+                Err(e)  => errors.push((i, e)),
+            }
+        }
+        if errors.is_empty() {
+            Ok(records)
+        } else {
+            Err(ShnError::RowErrors(errors))
+        }
+    }
+
+    /// Appends `record` as a new row, numbered after its position, after
+    /// checking the schema with `ShnRecord::check_schema`.
+    pub fn append_record<T: ShnRecord>(&mut self, record: &T) -> Result<()> {
+        try!(T::check_schema(&self.schema));
+        if self.data.len() > u16::max_value() as usize {
+            return Err(ShnError::InvalidSchema);
+        }
+        let row = try!(record.to_row(&self.schema, self.data.len() as u16));
+        self.append_row(row)
+    }
+}
+
+/// Converts a cell into a field of a record, used by `shn_record!`.
+#[doc(hidden)]
+pub fn record_field<T>(cell: Option<&ShnCell>) -> Result<T>
+    where for<'a> T: TryFrom<&'a ShnCell, Error = ShnError> {
+    match cell {
+        Some(cell)  => T::try_from(cell),
+        None        => Err(ShnError::InvalidSchema),
+    }
+}
+
+/// Converts a field of a record into a cell of `data_type`, used by
+/// `shn_record!`.
+#[doc(hidden)]
+pub fn record_cell<T: Into<ShnCell>>(value: T, data_type: ShnDataType)
+                                     -> Result<ShnCell> {
+    let cell = value.into();
+    cell.convert_to(&data_type)
+        .ok_or_else(|| ShnError::InvalidConversion(cell, data_type))
+}
+
+/// Defines a struct holding the rows of a table with a known layout, and
+/// implements `ShnRecord` for it. Every field names its column, the data
+/// type of the column and its length in bytes, in the order of the file.
+/// Field types need to convert from and into `ShnCell`s.
+///
+/// ```
+/// #[macro_use] extern crate shn;
+///
+/// shn_record! {
+///     /// A row of `ItemInfo`
+///     pub struct Item = "ItemInfo" {
+///         id: u16 = "ID", UnsignedShort(2),
+///         inx_name: String = "InxName", StringFixedLen(32),
+///     }
+/// }
+/// # fn main() {}
+/// ```
+#[macro_export]
+macro_rules! shn_record {
+    ($(#[$meta:meta])*
+     pub struct $name:ident = $table:tt {
+         $($(#[$field_meta:meta])*
+           $field:ident: $ty:ty = $column:tt, $data_type:ident($len:expr)),*
+         $(,)*
+     }) => {
+        $(#[$meta])*
+        #[derive(Clone, PartialEq, Debug, Default)]
+        pub struct $name {
+            $($(#[$field_meta])*
+              #[doc = concat!("The `", $column, "` column")]
+              pub $field: $ty,)*
+        }
+
+        impl $crate::ShnRecord for $name {
+            fn table_name() -> &'static str {
+                $table
+            }
+
+            fn columns() -> Vec<$crate::ShnColumn> {
+                vec![$($crate::ShnColumn {
+                    name:           $column.to_owned(),
+                    data_type:      $crate::ShnDataType::$data_type,
+                    data_length:    $len,
+                }),*]
+            }
+
+            fn from_cells(cells: &[$crate::ShnCell])
+                          -> ::std::result::Result<Self, $crate::ShnError> {
+                let mut cells = cells.iter();
+                Ok($name {
+                    $($field: try!($crate::record_field(cells.next())),)*
+                })
+            }
+
+            fn to_cells(&self)
+                        -> ::std::result::Result<Vec<$crate::ShnCell>,
+                                                 $crate::ShnError> {
+                Ok(vec![$(try!($crate::record_cell(
+                    self.$field.clone(), $crate::ShnDataType::$data_type))),*])
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::shn::SHN_CRYPT_HEADER_LEN;
+
+    shn_record! {
+        /// A row of a test table
+        pub struct Item = "Item" {
+            id: u16 = "ID", UnsignedShort(2),
+            name: String = "Name", StringFixedLen(8),
+            level: u8 = "Level", UnsignedShort(2),
+            rate: f32 = "Rate", SingleFloatingPoint(4),
+        }
+    }
+
+    fn item(id: u16, level: u8) -> Item {
+        Item {
+            id:     id,
+            name:   format!("n{}", id),
+            level:  level,
+            rate:   0.5,
+        }
+    }
+
+    /// Returns the schema of `Item` with the data columns in `order`, given
+    /// by their position, and the changes applied to them.
+    fn schema(order: &[usize], change: &Fn(&mut ShnColumn)) -> ShnSchema {
+        let columns = Item::columns();
+        let mut schema = ShnSchema::with_id_column();
+        for &i in order {
+            let mut column = columns[i].clone();
+            change(&mut column);
+            schema.columns.push(column);
+        }
+        schema
+    }
+
+    fn differences(schema: &ShnSchema) -> Vec<ShnColumnDifference> {
+        match Item::check_schema(schema) {
+            Err(ShnError::SchemaMismatch(ref table, ref d)) => {
+                assert_eq!(table, "Item");
+                d.clone()
+            },
+            r => panic!("{:?}", r),
+        }
+    }
+
+    #[test]
+    fn matching_schema() {
+        assert!(Item::check_schema(&Item::schema()).is_ok());
+        assert!(Item::check_schema(&schema(&[0, 1, 2, 3], &|_| {})).is_ok());
+        assert_eq!(Item::table_name(), "Item");
+        assert_eq!(Item::schema().columns[0].name, "__ID__");
+    }
+
+    #[test]
+    fn changed_schema() {
+        let columns = Item::columns();
+        let mut changed = schema(&[0, 1, 3], &|c| if c.name == "Name" {
+            c.data_length = 16;
+        });
+        changed.columns.push(ShnColumn::new_byte("Extra"));
+        let mut name = columns[1].clone();
+        name.data_length = 16;
+        assert_eq!(differences(&changed), vec![
+            ShnColumnDifference::Changed {
+                expected:   columns[1].clone(),
+                found:      name,
+            },
+            ShnColumnDifference::Missing(columns[2].clone()),
+            ShnColumnDifference::Unexpected(ShnColumn::new_byte("Extra")),
+        ]);
+        let error = ShnError::SchemaMismatch("Item".to_owned(),
+                                             differences(&changed));
+        assert_eq!(error.to_string(),
+                   "schema of `Item` differs: column `Name` is \
+                    StringFixedLen(16) instead of StringFixedLen(8), missing \
+                    column `Level` UnsignedShort, unexpected column `Extra` Byte");
+    }
+
+    #[test]
+    fn moved_columns() {
+        // A single swap moves both columns
+        assert_eq!(differences(&schema(&[1, 0, 2, 3], &|_| {})), vec![
+            ShnColumnDifference::Moved { name: "Name".to_owned(), expected: 1,
+                                         found: 0 },
+            ShnColumnDifference::Moved { name: "ID".to_owned(), expected: 0,
+                                         found: 1 },
+        ]);
+        assert_eq!(differences(&schema(&[0, 2, 3, 1], &|_| {})).len(), 3);
+        assert_eq!(differences(&schema(&[0, 2, 3, 1], &|_| {}))[0].to_string(),
+                   "column `Level` is at 1 instead of 2");
+        // Missing columns do not move the ones after them
+        assert_eq!(differences(&schema(&[1, 2, 3], &|_| {})),
+                   vec![ShnColumnDifference::Missing(Item::columns()[0].clone())]);
+    }
+
+    #[test]
+    fn file_records() {
+        let schema = Arc::new(Item::schema());
+        let mut file = ShnFile::new([0; SHN_CRYPT_HEADER_LEN], 0, schema.clone());
+        let items = vec![item(7, 10), item(9, 200)];
+        for i in &items {
+            file.append_record(i).unwrap();
+        }
+        assert_eq!(file.data[1].data[0], ShnCell::UnsignedShort(1));
+        assert_eq!(file.data[1].data[3], ShnCell::UnsignedShort(200));
+        assert_eq!(file.records::<Item>().unwrap(), items);
+        assert_eq!(Item::from_row(&items[0].to_row(&schema, 3).unwrap()).unwrap(),
+                   items[0]);
+
+        // Values out of range of a field fail for their row
+        file.data[0].data[3] = ShnCell::UnsignedShort(300);
+        match file.records::<Item>() {
+            Err(ShnError::RowErrors(ref errors)) => {
+                assert_eq!(errors.len(), 1);
+                assert_eq!(errors[0].0, 0);
+            },
+            r => panic!("{:?}", r),
+        }
+
+        let other = Arc::new(ShnSchema::with_id_column());
+        let mut file = ShnFile::new([0; SHN_CRYPT_HEADER_LEN], 0, other);
+        match file.append_record(&items[0]) {
+            Err(ShnError::SchemaMismatch(..)) => {},
+            r => panic!("{:?}", r),
+        }
+        assert!(file.records::<Item>().is_err());
+        assert!(Item::from_cells(&[ShnCell::UnsignedShort(1)]).is_err());
+    }
+}
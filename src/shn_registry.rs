@@ -0,0 +1,290 @@
+use ::std::io::{ BufRead, Write };
+
+use super::shn::{ ShnSchema, ShnError, Result };
+use super::shn_record::ShnRecord;
+
+const FNV_OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+const FNV_PRIME: u64 = 0x100000001b3;
+
+impl ShnSchema {
+    /// Returns a fingerprint of the data columns, computed with 64 bit
+    /// FNV-1a over the name, the type id and the length of every column.
+    /// It only changes along with the layout of the file, and is the same
+    /// across platforms and versions of this library.
+    ///
+    /// Types are hashed by `ShnDataType::to_id`, since the type ids
+    /// sharing a `ShnDataType` can not be told apart after reading.
+    pub fn fingerprint(&self) -> u64 {
+        let mut hash = FNV_OFFSET_BASIS;
+        {
+            let mut feed = |bytes: &[u8]| {
+                for &b in bytes {
+                    hash ^= b as u64;
+                    hash = hash.wrapping_mul(FNV_PRIME);
+                }
+            };
+            for c in self.data_columns() {
+                feed(c.name.as_bytes());
+                // Separates the name from the type, so names can not run
+                // into the following bytes
+                feed(&[0]);
+                feed(&c.data_type.to_id().to_le_bytes());
+                feed(&c.data_length.to_le_bytes());
+            }
+        }
+        hash
+    }
+}
+
+/// A known schema, see `ShnRegistry`.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct ShnRegistryEntry {
+    /// The fingerprint of the schema, see `ShnSchema::fingerprint`
+    pub fingerprint:    u64,
+    /// The name of the table, like `ItemInfo`
+    pub table:          String,
+    /// The client version using the schema, in no particular format
+    pub version:        String,
+}
+
+/// Maps schema fingerprints to the tables and client versions they are
+/// known from, to identify files regardless of their name.
+///
+/// Registries are stored as text, with one entry per line holding the
+/// fingerprint in hexadecimal, the table name and the version, separated by
+/// whitespace. Empty lines and lines starting with `#` are ignored:
+///
+/// ```text
+/// # fingerprint     table     version
+/// 8f3ac1d2e0b47a55  ItemInfo  NA 2016-03
+/// ```
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct ShnRegistry {
+    /// The known schemas, in the order they were added
+    pub entries:    Vec<ShnRegistryEntry>,
+}
+
+impl ShnRegistry {
+    /// Constructs an empty registry.
+    pub fn new() -> Self {
+        ShnRegistry::default()
+    }
+
+    /// Adds an entry, unless the same one is known already.
+    pub fn add(&mut self, fingerprint: u64, table: &str, version: &str) {
+        let entry = ShnRegistryEntry {
+            fingerprint:    fingerprint,
+            table:          table.to_owned(),
+            version:        version.to_owned(),
+        };
+        if !self.entries.contains(&entry) {
+            self.entries.push(entry);
+        }
+    }
+
+    /// Adds the schema of a file holding the table `table` of the client
+    /// version `version`.
+    pub fn add_schema(&mut self, schema: &ShnSchema, table: &str, version: &str) {
+        self.add(schema.fingerprint(), table, version);
+    }
+
+    /// Adds the schema expected by the record `T`.
+    pub fn add_record<T: ShnRecord>(&mut self, version: &str) {
+        self.add_schema(&T::schema(), T::table_name(), version);
+    }
+
+    /// Adds all entries of `other`.
+    pub fn extend(&mut self, other: &ShnRegistry) {
+        for e in &other.entries {
+            self.add(e.fingerprint, &e.table, &e.version);
+        }
+    }
+
+    /// Returns all entries matching the fingerprint of `schema`. There may
+    /// be several, since tables of the same layout share their fingerprint.
+    pub fn identify(&self, schema: &ShnSchema) -> Vec<&ShnRegistryEntry> {
+        let fingerprint = schema.fingerprint();
+        self.entries.iter()
+            .filter(|e| e.fingerprint == fingerprint)
+            .collect()
+    }
+
+    /// Returns the entry of the table `table` matching the fingerprint of
+    /// `schema`, failing with `ShnError::UnknownSchema` if there is none.
+    /// Used to refuse interpreting data of unknown layouts.
+    pub fn require(&self, schema: &ShnSchema, table: &str)
+                   -> Result<&ShnRegistryEntry> {
+        let fingerprint = schema.fingerprint();
+        self.entries.iter()
+            .find(|e| e.fingerprint == fingerprint && e.table == table)
+            .ok_or_else(|| ShnError::UnknownSchema(table.to_owned(), fingerprint))
+    }
+
+    /// Reads a registry from text.
+    pub fn read_from<R: BufRead>(source: R) -> Result<ShnRegistry> {
+        let mut registry = ShnRegistry::new();
+        for (i, line) in source.lines().enumerate() {
+            let line = try!(line.map_err(ShnError::Io));
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let invalid = |message: &str| {
+                ShnError::InvalidRegistry(i + 1, message.to_owned())
+            };
+            let mut parts = line.split_whitespace();
+            let fingerprint = try!(parts.next()
+                                   .and_then(|f| u64::from_str_radix(f, 16).ok())
+                                   .ok_or_else(|| invalid("invalid fingerprint")));
+            let table = try!(parts.next()
+                             .ok_or_else(|| invalid("missing table name")));
+            let version: Vec<&str> = parts.collect();
+            registry.add(fingerprint, table, &version.join(" "));
+        }
+        Ok(registry)
+    }
+
+    /// Writes the registry as text, in the format read by `read_from`.
+    pub fn write_to<W: Write>(&self, dest: &mut W) -> Result<()> {
+        for e in &self.entries {
+            let line = format!("{:016x} {} {}", e.fingerprint, e.table,
+                               e.version);
+            try!(writeln!(dest, "{}", line.trim_end()).map_err(ShnError::Io));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::shn::{ ShnDataType, ShnColumn };
+
+    fn schema() -> ShnSchema {
+        let mut schema = ShnSchema::with_id_column();
+        schema.columns.push(ShnColumn::new_unsigned_short("ID"));
+        schema.columns.push(ShnColumn::new_string_fixed_len("InxName", 32));
+        schema.columns.push(ShnColumn::new_byte("Type"));
+        schema
+    }
+
+    fn error_line(text: &str) -> (usize, String) {
+        match ShnRegistry::read_from(text.as_bytes()) {
+            Err(ShnError::InvalidRegistry(line, message)) => (line, message),
+            r => panic!("{:?}", r),
+        }
+    }
+
+    #[test]
+    fn fingerprint() {
+        let schema = schema();
+        // Stable across versions, since registries store it
+        assert_eq!(schema.fingerprint(), 0x3300c1361f15fb49);
+        assert_eq!(ShnSchema::with_id_column().fingerprint(), FNV_OFFSET_BASIS);
+
+        let mut changed = schema.clone();
+        changed.columns[2].name = "Kind".to_owned();
+        assert!(changed.fingerprint() != schema.fingerprint());
+        let mut changed = schema.clone();
+        changed.columns[2].data_type = ShnDataType::SignedByte;
+        assert!(changed.fingerprint() != schema.fingerprint());
+        let mut changed = schema.clone();
+        changed.columns[1].data_length = 16;
+        assert!(changed.fingerprint() != schema.fingerprint());
+        let mut changed = schema.clone();
+        changed.columns.swap(2, 3);
+        assert!(changed.fingerprint() != schema.fingerprint());
+        // Names can not run into the type
+        let mut a = ShnSchema::with_id_column();
+        a.columns.push(ShnColumn::new_byte("A\u{1}"));
+        let mut b = ShnSchema::with_id_column();
+        b.columns.push(ShnColumn::new_byte("A"));
+        assert!(a.fingerprint() != b.fingerprint());
+    }
+
+    #[test]
+    fn identify() {
+        let schema = schema();
+        let mut registry = ShnRegistry::new();
+        registry.add_schema(&schema, "ItemInfo", "NA");
+        registry.add_schema(&schema, "ItemInfo", "NA");
+        registry.add_schema(&schema, "ItemInfoCopy", "");
+        registry.add(1, "Other", "EU");
+        assert_eq!(registry.entries.len(), 3);
+        let tables: Vec<&str> = registry.identify(&schema).iter()
+            .map(|e| &e.table[..])
+            .collect();
+        assert_eq!(tables, vec!["ItemInfo", "ItemInfoCopy"]);
+        assert!(registry.identify(&ShnSchema::with_id_column()).is_empty());
+
+        assert_eq!(registry.require(&schema, "ItemInfoCopy").unwrap().version, "");
+        match registry.require(&schema, "Other") {
+            Err(ShnError::UnknownSchema(ref table, fingerprint)) => {
+                assert_eq!(table, "Other");
+                assert_eq!(fingerprint, schema.fingerprint());
+            },
+            r => panic!("{:?}", r),
+        }
+
+        let mut other = ShnRegistry::new();
+        other.add(1, "Other", "EU");
+        other.add(2, "More", "EU");
+        registry.extend(&other);
+        assert_eq!(registry.entries.len(), 4);
+    }
+
+    shn_record! {
+        /// A row of a test table
+        pub struct Item = "Item" {
+            id: u16 = "ID", UnsignedShort(2),
+            inx_name: String = "InxName", StringFixedLen(32),
+            item_type: u8 = "Type", Byte(1),
+        }
+    }
+
+    #[test]
+    fn add_record() {
+        let mut registry = ShnRegistry::new();
+        registry.add_record::<Item>("NA");
+        let entries = registry.identify(&schema());
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].table, "Item");
+        assert_eq!(entries[0].version, "NA");
+    }
+
+    #[test]
+    fn text_round_trip() {
+        let text = "# fingerprint     table     version\n\
+                    \n\
+                    8f3ac1d2e0b47a55  ItemInfo  NA 2016-03\n\
+                    \t00000000000000FF MobInfo\n";
+        let registry = ShnRegistry::read_from(text.as_bytes()).unwrap();
+        assert_eq!(registry.entries, vec![
+            ShnRegistryEntry {
+                fingerprint:    0x8f3ac1d2e0b47a55,
+                table:          "ItemInfo".to_owned(),
+                version:        "NA 2016-03".to_owned(),
+            },
+            ShnRegistryEntry {
+                fingerprint:    0xff,
+                table:          "MobInfo".to_owned(),
+                version:        String::new(),
+            },
+        ]);
+        let mut written = Vec::new();
+        registry.write_to(&mut written).unwrap();
+        assert_eq!(String::from_utf8(written.clone()).unwrap(),
+                   "8f3ac1d2e0b47a55 ItemInfo NA 2016-03\n\
+                    00000000000000ff MobInfo\n");
+        assert_eq!(ShnRegistry::read_from(&written[..]).unwrap(), registry);
+    }
+
+    #[test]
+    fn read_errors() {
+        assert_eq!(error_line("# header\nxyz ItemInfo"),
+                   (2, "invalid fingerprint".to_owned()));
+        assert_eq!(error_line("\n\n12ab"), (3, "missing table name".to_owned()));
+        assert_eq!(error_line("1ffffffffffffffff ItemInfo"),
+                   (1, "invalid fingerprint".to_owned()));
+    }
+}
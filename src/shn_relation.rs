@@ -0,0 +1,342 @@
+use ::std::collections::HashSet;
+use ::std::fmt;
+
+use super::shn::{ ShnDataType, ShnFile, ShnKey, ShnError, Result };
+use super::shn_database::ShnDatabase;
+
+/// A foreign key relation, stating that the cells of a column reference the
+/// rows of another table by their cell in a column of that table, like
+/// `ItemInfoServer.ID` referencing `ItemInfo.ID`.
+#[derive(Clone, PartialEq, Debug)]
+pub struct ShnRelation {
+    /// The name of the referencing table
+    pub table:          String,
+    /// The name of the referencing column
+    pub column:         String,
+    /// The name of the referenced table
+    pub target_table:   String,
+    /// The name of the referenced column, usually the key column
+    pub target_column:  String,
+    /// Keys which do not reference anything, like `0` in many columns
+    pub ignore:         Vec<ShnKey>,
+    /// Whether every row of the referenced table is expected to be
+    /// referenced, like the rows of `ItemInfo` by `ItemInfoServer`.
+    /// Unreferenced rows are reported as orphans only if this is set.
+    pub complete:       bool,
+}
+
+impl ShnRelation {
+    /// Constructs a relation from `table.column` to
+    /// `target_table.target_column`.
+    pub fn new(table: &str, column: &str, target_table: &str,
+               target_column: &str) -> Self {
+        ShnRelation {
+            table:          table.to_owned(),
+            column:         column.to_owned(),
+            target_table:   target_table.to_owned(),
+            target_column:  target_column.to_owned(),
+            ignore:         Vec::new(),
+            complete:       false,
+        }
+    }
+
+    /// Returns whether `key` does not reference anything.
+    fn ignores(&self, key: &ShnKey) -> bool {
+        self.ignore.contains(key)
+    }
+}
+
+impl fmt::Display for ShnRelation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}.{} -> {}.{}", self.table, self.column,
+               self.target_table, self.target_column)
+    }
+}
+
+/// A problem found by `ShnDatabase::check_integrity`. `relation` is the
+/// position of the violated relation in `ShnDatabase::relations`.
+#[derive(Debug)]
+pub enum ShnIntegrityIssue {
+    /// A table or column of the relation does not exist
+    Missing {
+        /// The violated relation
+        relation:   usize,
+        /// The missing table or column
+        error:      ShnError,
+    },
+    /// The key columns hold values which can never match, like strings and
+    /// integers, or floating point values
+    TypeMismatch {
+        /// The violated relation
+        relation:   usize,
+        /// The type of the referencing column
+        column:     ShnDataType,
+        /// The type of the referenced column
+        target:     ShnDataType,
+    },
+    /// A row references a key no row of the referenced table holds
+    DanglingReference {
+        /// The violated relation
+        relation:   usize,
+        /// The position of the referencing row
+        row:        usize,
+        /// The referenced key
+        key:        ShnKey,
+    },
+    /// A row of the referenced table of a complete relation is not
+    /// referenced
+    Orphan {
+        /// The violated relation
+        relation:   usize,
+        /// The position of the unreferenced row in the referenced table
+        row:        usize,
+    },
+}
+
+/// A row referencing another row, see `ShnDatabase::references_to`.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct ShnReference {
+    /// The position of the relation in `ShnDatabase::relations`
+    pub relation:   usize,
+    /// The name of the referencing table
+    pub table:      String,
+    /// The position of the referencing row
+    pub row:        usize,
+}
+
+/// Returns whether two key columns can hold matching keys.
+fn comparable(a: &ShnDataType, b: &ShnDataType) -> bool {
+    let kind = |t: &ShnDataType| match *t {
+        ShnDataType::StringFixedLen |
+        ShnDataType::StringZeroTerminated   => Some(true),
+        ShnDataType::SingleFloatingPoint    => None,
+        _                                   => Some(false),
+    };
+    match (kind(a), kind(b)) {
+        (Some(a), Some(b))  => a == b,
+        _                   => false,
+    }
+}
+
+/// Returns the position of `column` in `file`.
+fn position(file: &ShnFile, column: &str) -> Result<usize> {
+    file.schema.columns.iter()
+        .position(|c| c.name == column)
+        .ok_or_else(|| ShnError::UnknownColumn(column.to_owned()))
+}
+
+impl ShnDatabase {
+    /// Checks all relations, and returns every missing table or column,
+    /// type mismatch between key columns, dangling reference, and orphan of
+    /// complete relations. Relations with missing parts or mismatching types
+    /// are not checked any further.
+    pub fn check_integrity(&self) -> Vec<ShnIntegrityIssue> {
+        let mut issues = Vec::new();
+        for (i, relation) in self.relations().iter().enumerate() {
+            let resolved = self.table(&relation.table)
+                .and_then(|t| position(t, &relation.column).map(|c| (t, c)))
+                .and_then(|(t, c)| {
+                    let target = try!(self.table(&relation.target_table));
+                    let target_column = try!(position(target,
+                                                      &relation.target_column));
+                    Ok((t, c, target, target_column))
+                });
+            let (table, column, target, target_column) = match resolved {
+                Ok(r)   => r,
+                Err(e)  => {
+                    issues.push(ShnIntegrityIssue::Missing {
+                        relation:   i,
+                        error:      e,
+                    });
+                    continue;
+                },
+            };
+
+            let column_type = &table.schema.columns[column].data_type;
+            let target_type = &target.schema.columns[target_column].data_type;
+            if !comparable(column_type, target_type) {
+                issues.push(ShnIntegrityIssue::TypeMismatch {
+                    relation:   i,
+                    column:     column_type.clone(),
+                    target:     target_type.clone(),
+                });
+                continue;
+            }
+
+            let keys: HashSet<ShnKey> = target.data.iter()
+                .filter_map(|r| ShnKey::from_cell(&r.data[target_column]))
+                .collect();
+            let mut referenced = HashSet::new();
+            for (row, r) in table.data.iter().enumerate() {
+                let key = match ShnKey::from_cell(&r.data[column]) {
+                    Some(key)   => key,
+                    None        => continue,
+                };
+                if relation.ignores(&key) {
+                    continue;
+                }
+                if !keys.contains(&key) {
+                    issues.push(ShnIntegrityIssue::DanglingReference {
+                        relation:   i,
+                        row:        row,
+                        key:        key,
+                    });
+                } else if relation.complete {
+                    referenced.insert(key);
+                }
+            }
+
+            if relation.complete {
+                for (row, r) in target.data.iter().enumerate() {
+                    let referenced = ShnKey::from_cell(&r.data[target_column])
+                        .map_or(true, |k| relation.ignores(&k)
+                                || referenced.contains(&k));
+                    if !referenced {
+                        issues.push(ShnIntegrityIssue::Orphan {
+                            relation:   i,
+                            row:        row,
+                        });
+                    }
+                }
+            }
+        }
+        issues
+    }
+
+    /// Returns all rows referencing row `row` of the table `table` through
+    /// any relation, in the order of the relations and rows. Rows out of
+    /// range are not referenced, and relations with missing tables or
+    /// columns are skipped, see `check_integrity`.
+    pub fn references_to(&self, table: &str, row: usize)
+                         -> Result<Vec<ShnReference>> {
+        let target = try!(self.table(table));
+        if row >= target.data.len() {
+            return Ok(Vec::new());
+        }
+
+        let mut references = Vec::new();
+        for (i, relation) in self.relations().iter().enumerate() {
+            if relation.target_table != table {
+                continue;
+            }
+            let resolved = position(target, &relation.target_column)
+                .and_then(|tc| {
+                    let referencing = try!(self.table(&relation.table));
+                    let column = try!(position(referencing, &relation.column));
+                    Ok((tc, referencing, column))
+                });
+            let (target_column, referencing, column) = match resolved {
+                Ok(r)   => r,
+                Err(_)  => continue,
+            };
+            let key = match ShnKey::from_cell(&target.data[row].data[target_column]) {
+                Some(ref key) if !relation.ignores(key) => key.clone(),
+                _                                       => continue,
+            };
+            for (r, cells) in referencing.data.iter().enumerate() {
+                if ShnKey::from_cell(&cells.data[column]).as_ref() == Some(&key) {
+                    references.push(ShnReference {
+                        relation:   i,
+                        table:      relation.table.clone(),
+                        row:        r,
+                    });
+                }
+            }
+        }
+        Ok(references)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::encoding::all::ASCII;
+    use super::super::shn::{ ShnCell, ShnColumn };
+    use super::super::shn_test_util::table;
+
+    fn database() -> ShnDatabase {
+        let mut database = ShnDatabase::new();
+        let item = table(vec![ShnColumn::new_unsigned_integer("ID"),
+                              ShnColumn::new_string_fixed_len("Name", 8),
+                              ShnColumn::new_single_floating_point("Rate")],
+                         [(1, "Sword"), (2, "Axe"), (3, "Bow")].iter()
+                         .map(|&(id, name)| vec![ShnCell::UnsignedInteger(id),
+                                                 ShnCell::StringFixedLen(name.to_owned()),
+                                                 ShnCell::SingleFloatingPoint(1.0)])
+                         .collect());
+        let drop = table(vec![ShnColumn::new_unsigned_short("MobID"),
+                              ShnColumn::new_unsigned_short("ItemID")],
+                         [(10, 1), (10, 0), (11, 9), (12, 1), (12, 3)].iter()
+                         .map(|&(mob, item)| vec![ShnCell::UnsignedShort(mob),
+                                                  ShnCell::UnsignedShort(item)])
+                         .collect());
+        let server = table(vec![ShnColumn::new_unsigned_integer("ID")],
+                           vec![vec![ShnCell::UnsignedInteger(1)]]);
+        database.insert("Item", item, "Item.shn", ASCII);
+        database.insert("Drop", drop, "Drop.shn", ASCII);
+        database.insert("Server", server, "Server.shn", ASCII);
+        database
+    }
+
+    #[test]
+    fn display() {
+        assert_eq!(ShnRelation::new("Drop", "ItemID", "Item", "ID").to_string(),
+                   "Drop.ItemID -> Item.ID");
+    }
+
+    #[test]
+    fn integrity() {
+        let mut database = database();
+        assert!(database.check_integrity().is_empty());
+        let mut drops = ShnRelation::new("Drop", "ItemID", "Item", "ID");
+        drops.ignore.push(ShnKey::from(0u8));
+        database.add_relation(drops);
+        let mut server = ShnRelation::new("Server", "ID", "Item", "ID");
+        server.complete = true;
+        database.add_relation(server);
+        database.add_relation(ShnRelation::new("Drop", "Nope", "Item", "ID"));
+        database.add_relation(ShnRelation::new("Drop", "ItemID", "Nope", "ID"));
+        database.add_relation(ShnRelation::new("Drop", "ItemID", "Item", "Name"));
+        assert_eq!(database.add_relation(
+            ShnRelation::new("Item", "Rate", "Item", "Rate")), 5);
+
+        let issues: Vec<String> = database.check_integrity().iter()
+            .map(|i| format!("{:?}", i))
+            .collect();
+        assert_eq!(issues, vec![
+            "DanglingReference { relation: 0, row: 2, key: Integer(9) }",
+            "Orphan { relation: 1, row: 1 }",
+            "Orphan { relation: 1, row: 2 }",
+            "Missing { relation: 2, error: UnknownColumn(\"Nope\") }",
+            "Missing { relation: 3, error: UnknownTable(\"Nope\") }",
+            "TypeMismatch { relation: 4, column: UnsignedShort, \
+             target: StringFixedLen }",
+            "TypeMismatch { relation: 5, column: SingleFloatingPoint, \
+             target: SingleFloatingPoint }",
+        ]);
+    }
+
+    #[test]
+    fn references() {
+        let mut database = database();
+        let mut drops = ShnRelation::new("Drop", "ItemID", "Item", "ID");
+        drops.ignore.push(ShnKey::from(0u8));
+        database.add_relation(drops);
+        database.add_relation(ShnRelation::new("Server", "ID", "Item", "ID"));
+        database.add_relation(ShnRelation::new("Drop", "Nope", "Item", "ID"));
+        let reference = |relation, table: &str, row| ShnReference {
+            relation:   relation,
+            table:      table.to_owned(),
+            row:        row,
+        };
+        assert_eq!(database.references_to("Item", 0).unwrap(),
+                   vec![reference(0, "Drop", 0), reference(0, "Drop", 3),
+                        reference(1, "Server", 0)]);
+        assert_eq!(database.references_to("Item", 2).unwrap(),
+                   vec![reference(0, "Drop", 4)]);
+        assert!(database.references_to("Item", 1).unwrap().is_empty());
+        assert!(database.references_to("Item", 3).unwrap().is_empty());
+        assert!(database.references_to("Drop", 0).unwrap().is_empty());
+        assert!(database.references_to("Nope", 0).is_err());
+    }
+}
@@ -0,0 +1,458 @@
+use ::std::io::{ Read, Write };
+use ::std::path::Path;
+use ::std::sync::Arc;
+
+use ::encoding::{ DecoderTrap, EncoderTrap, EncodingRef };
+
+use super::shn::{ SHN_CRYPT_HEADER_LEN, ShnDataType, ShnCell, ShnColumn, ShnSchema,
+           ShnRow, ShnFile, ShnError, Result };
+use super::shn_database::replace_file;
+
+/// A table of a ShineTable text file, see `read_shine_from`.
+#[derive(Clone, PartialEq, Debug)]
+pub struct ShnShineTable {
+    /// The name given by the `#table` directive
+    pub name:   String,
+    /// The rows of the table, with zeroed headers
+    pub file:   ShnFile,
+}
+
+/// Returns the type and length of the ShineTable type `name`.
+fn parse_type(name: &str) -> Option<(ShnDataType, i32)> {
+    let name = name.to_ascii_lowercase();
+    if name.starts_with("string[") && name.ends_with(']') {
+        return name[7..name.len() - 1].parse().ok()
+            .and_then(|l| if l > 0 { Some(l) } else { None })
+            .map(|l| (ShnDataType::StringFixedLen, l));
+    }
+    let data_type = match &name[..] {
+        "byte"              => ShnDataType::Byte,
+        "char"              => ShnDataType::SignedByte,
+        "word"              => ShnDataType::UnsignedShort,
+        "short"             => ShnDataType::SignedShort,
+        "dwrd" | "dword"    => ShnDataType::UnsignedInteger,
+        "int" | "integer"   => ShnDataType::SignedInteger,
+        "float"             => ShnDataType::SingleFloatingPoint,
+        "string"            => ShnDataType::StringZeroTerminated,
+        // Holds the name rows are looked up by in most tables. It has no
+        // type of its own in SHN files, so it is written back as `string[32]`
+        "index"             => return Some((ShnDataType::StringFixedLen, 32)),
+        _                   => return None,
+    };
+    Some((data_type.clone(), data_type.default_length() as i32))
+}
+
+/// Returns the ShineTable type of `column`, as read by `parse_type`.
+fn type_name(column: &ShnColumn) -> String {
+    match column.data_type {
+        ShnDataType::StringFixedLen         => format!("string[{}]", column.data_length),
+        ShnDataType::StringZeroTerminated   => "string".to_owned(),
+        ShnDataType::Byte                   => "byte".to_owned(),
+        ShnDataType::SignedByte             => "char".to_owned(),
+        ShnDataType::UnsignedShort          => "word".to_owned(),
+        ShnDataType::SignedShort            => "short".to_owned(),
+        ShnDataType::UnsignedInteger        => "dwrd".to_owned(),
+        ShnDataType::SignedInteger          => "int".to_owned(),
+        ShnDataType::SingleFloatingPoint    => "float".to_owned(),
+    }
+}
+
+/// Splits a line into its fields, which are separated by tabs, or by any
+/// whitespace in lines without tabs. Fields are not trimmed, since strings
+/// may start or end with spaces.
+fn fields(line: &str) -> Vec<&str> {
+    if line.contains('\t') {
+        line.split('\t').collect()
+    } else {
+        line.split_whitespace().collect()
+    }
+}
+
+/// Removes empty fields left by trailing tabs, keeping at least `min`.
+fn trim_trailing(fields: &mut Vec<&str>, min: usize) {
+    while fields.len() > min && fields.last() == Some(&"") {
+        fields.pop();
+    }
+}
+
+/// A table being read, until its columns are complete.
+struct Pending {
+    name:   String,
+    types:  Option<Vec<(ShnDataType, i32)>>,
+    names:  Option<Vec<String>>,
+    file:   Option<ShnFile>,
+}
+
+impl Pending {
+    /// Returns the file rows are added to, creating it once both the types
+    /// and the names of the columns are known.
+    fn file(&mut self, line: usize) -> Result<&mut ShnFile> {
+        if self.file.is_none() {
+            let (types, names) = match (&self.types, &self.names) {
+                (&Some(ref t), &Some(ref n)) => (t, n),
+                _ => return Err(ShnError::InvalidShineTable(line, format!(
+                    "`{}` lacks #columntype or #columnname", self.name))),
+            };
+            if types.len() != names.len() {
+                return Err(ShnError::InvalidShineTable(line, format!(
+                    "`{}` has {} column types but {} column names",
+                    self.name, types.len(), names.len())));
+            }
+            let mut schema = ShnSchema::with_id_column();
+            for (&(ref data_type, length), name) in types.iter().zip(names) {
+                if schema.columns.iter().any(|c| c.name == *name) {
+                    return Err(ShnError::DuplicateColumn(name.clone()));
+                }
+                schema.columns.push(ShnColumn {
+                    name:           name.clone(),
+                    data_type:      data_type.clone(),
+                    data_length:    length,
+                });
+            }
+            self.file = Some(ShnFile::new([0; SHN_CRYPT_HEADER_LEN], 0,
+                                          Arc::new(schema)));
+        }
+        Ok(self.file.as_mut().unwrap())
+    }
+
+    fn finish(mut self, line: usize) -> Result<ShnShineTable> {
+        try!(self.file(line));
+        Ok(ShnShineTable {
+            name:   self.name,
+            file:   self.file.unwrap(),
+        })
+    }
+}
+
+/// Reads all tables of a ShineTable text file, using the given encoding.
+///
+/// Each table starts with `#table <name>`, followed by its column types
+/// and names in `#columntype` and `#columnname`, and a `#record` line for
+/// every row. Fields are separated by tabs, or by whitespace in lines
+/// without tabs, where values can be neither empty nor hold spaces.
+/// Trailing tabs are ignored, except where a record needs them for empty
+/// strings in its last columns. Tables end at the next `#table` or at
+/// `#end`. Lines starting with `;` are comments. Other directives, like
+/// `#define` or `#ignore`, change how a file is read and are not supported.
+///
+/// Column types map to `ShnDataType`s as follows, ignoring case:
+/// `byte`, `char` (signed byte), `word`, `short`, `dwrd` or `dword`, `int`
+/// or `integer`, `float`, `string[N]` (fixed length), `string` (zero
+/// terminated), and `index`, which is read as a string of 32 bytes and thus
+/// written back as `string[32]`.
+pub fn read_shine_from<R: Read>(source: &mut R, encoding: &EncodingRef)
+                                -> Result<Vec<ShnShineTable>> {
+    let mut bytes = Vec::new();
+    try!(source.read_to_end(&mut bytes).map_err(ShnError::Io));
+    let text = try!(encoding.decode(&bytes, DecoderTrap::Strict)
+                    .map_err(|_| ShnError::InvalidEncoding));
+
+    let mut tables = Vec::new();
+    let mut current: Option<Pending> = None;
+    let mut last = 0;
+    for (i, line) in text.lines().enumerate() {
+        let number = i + 1;
+        last = number;
+        let invalid = |message: String| ShnError::InvalidShineTable(number, message);
+        let trimmed = line.trim();
+        if trimmed.is_empty() || trimmed.starts_with(';') {
+            continue;
+        }
+        // Only trimmed at the start, as trailing tabs separate empty strings
+        let mut parts = fields(line.trim_start());
+        let directive = parts[0].to_ascii_lowercase();
+        if directive == "#table" || directive == "#end" {
+            if let Some(table) = current.take() {
+                tables.push(try!(table.finish(number)));
+            }
+            if directive == "#table" {
+                let name = try!(parts.get(1).filter(|n| !n.is_empty())
+                                .ok_or_else(|| invalid("missing table name"
+                                                       .to_owned())));
+                current = Some(Pending {
+                    name:   name.to_string(),
+                    types:  None,
+                    names:  None,
+                    file:   None,
+                });
+            }
+            continue;
+        }
+        let table = match current {
+            Some(ref mut t) => t,
+            None => return Err(invalid(format!("`{}` outside of a table",
+                                               parts[0]))),
+        };
+        match &directive[..] {
+            "#columntype" if table.file.is_none() => {
+                trim_trailing(&mut parts, 1);
+                let mut types = Vec::new();
+                for t in &parts[1..] {
+                    types.push(try!(parse_type(t).ok_or_else(|| {
+                        invalid(format!("unknown column type `{}`", t))
+                    })));
+                }
+                table.types = Some(types);
+            },
+            "#columnname" if table.file.is_none() => {
+                trim_trailing(&mut parts, 1);
+                if parts[1..].contains(&"") {
+                    return Err(invalid("empty column name".to_owned()));
+                }
+                table.names = Some(parts[1..].iter()
+                                   .map(|n| n.to_string())
+                                   .collect());
+            },
+            "#record" => {
+                let file = try!(table.file(number));
+                let columns = file.schema.data_columns().len();
+                trim_trailing(&mut parts, columns + 1);
+                let values = &parts[1..];
+                if values.len() != columns {
+                    let hint = if values.len() > columns && !line.contains('\t') {
+                        ", strings holding spaces need to be separated by tabs"
+                    } else {
+                        ""
+                    };
+                    return Err(invalid(format!("expected {} values, found {}{}",
+                                               columns, values.len(), hint)));
+                }
+                if file.data.len() > u16::max_value() as usize {
+                    return Err(invalid("too many records".to_owned()));
+                }
+                let mut cells = vec![ShnCell::UnsignedShort(file.data.len() as u16)];
+                for (value, column) in values.iter().zip(file.schema.data_columns()) {
+                    let cell = ShnCell::parse(value, &column.data_type)
+                        .and_then(|cell| {
+                            try!(column.check_length(&cell));
+                            Ok(cell)
+                        });
+                    cells.push(try!(cell.map_err(|e| {
+                        invalid(format!("{} in column `{}`", e, column.name))
+                    })));
+                }
+                let row = ShnRow {
+                    schema: file.schema.clone(),
+                    data:   cells,
+                };
+                try!(file.append_row(row));
+            },
+            _ => return Err(invalid(format!("unexpected `{}`", parts[0]))),
+        }
+    }
+    if let Some(table) = current.take() {
+        tables.push(try!(table.finish(last)));
+    }
+    Ok(tables)
+}
+
+/// Writes tables as a ShineTable text file, using the given encoding. See
+/// `read_shine_from` for the format and the names of column types. Fields
+/// are separated by tabs, so strings holding tabs or line breaks can not be
+/// written.
+pub fn write_shine_to<W: Write>(dest: &mut W,
+                                tables: &[ShnShineTable],
+                                encoding: &EncodingRef)
+                                -> Result<()> {
+    let mut text = String::new();
+    for table in tables {
+        let columns = table.file.schema.data_columns();
+        let skip = table.file.schema.columns.len() - columns.len();
+        let types: Vec<String> = columns.iter().map(type_name).collect();
+        let names: Vec<&str> = columns.iter().map(|c| &c.name[..]).collect();
+        text.push_str(&format!("#table\t{}\n#columntype\t{}\n#columnname\t{}\n",
+                               table.name, types.join("\t"), names.join("\t")));
+        for row in &table.file.data {
+            text.push_str("#record");
+            for cell in &row.data[skip..] {
+                let value = cell.to_string();
+                if value.contains(&['\t', '\n', '\r'][..]) {
+                    return Err(ShnError::InvalidValue(cell.clone()));
+                }
+                text.push('\t');
+                text.push_str(&value);
+            }
+            text.push('\n');
+        }
+        text.push_str("#end\n\n");
+    }
+    let bytes = try!(encoding.encode(&text, EncoderTrap::Strict)
+                     .map_err(|_| ShnError::InvalidEncoding));
+    dest.write_all(&bytes).map_err(ShnError::Io)
+}
+
+/// Writes tables as a ShineTable text file at `path`, see `write_shine_to`.
+/// The file is written to a temporary file next to `path` first, which then
+/// replaces it, so a failure leaves any previous file intact.
+pub fn write_shine_file<P: AsRef<Path>>(path: P,
+                                        tables: &[ShnShineTable],
+                                        encoding: &EncodingRef)
+                                        -> Result<()> {
+    replace_file(path.as_ref(), |dest| write_shine_to(dest, tables, encoding))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::std::env;
+    use ::std::fs;
+    use ::std::process;
+    use ::encoding::all::ASCII;
+
+    fn read(text: &str) -> Result<Vec<ShnShineTable>> {
+        let enc: EncodingRef = ASCII;
+        read_shine_from(&mut text.as_bytes(), &enc)
+    }
+
+    fn write(tables: &[ShnShineTable]) -> String {
+        let enc: EncodingRef = ASCII;
+        let mut out = Vec::new();
+        write_shine_to(&mut out, tables, &enc).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    fn error(text: &str) -> (usize, String) {
+        match read(text) {
+            Err(ShnError::InvalidShineTable(line, message)) => (line, message),
+            r => panic!("{:?}", r),
+        }
+    }
+
+    const TEXT: &'static str = "\
+#table\tItems
+#columntype\tword\tstring[12]\tchar\tfloat
+#columnname\tID\tName\tDelta\tRate
+#record\t1\tSword\t-3\t0.5
+#record\t2\t Two words \t4\t1
+#end
+
+#table\tText
+#columntype\tdwrd\tstring
+#columnname\tKey\tValue
+#record\t7\tHello, world
+#record\t8\t
+#end
+
+";
+
+    #[test]
+    fn round_trip() {
+        let tables = read(TEXT).unwrap();
+        assert_eq!(tables.len(), 2);
+        assert_eq!(tables[0].name, "Items");
+        assert_eq!(tables[0].file.data.len(), 2);
+        assert_eq!(tables[0].file.data[1].data,
+                   vec![ShnCell::UnsignedShort(1), ShnCell::UnsignedShort(2),
+                        ShnCell::StringFixedLen(" Two words ".to_owned()),
+                        ShnCell::SignedByte(4), ShnCell::SingleFloatingPoint(1.0)]);
+        assert_eq!(tables[1].file.data[1].data[2],
+                   ShnCell::StringZeroTerminated(String::new()));
+
+        let text = write(&tables);
+        assert_eq!(text, TEXT);
+        assert_eq!(read(&text).unwrap(), tables);
+    }
+
+    #[test]
+    fn separators() {
+        // Whitespace separated lines, trailing tabs, comments and a table
+        // ended by the next one
+        let text = "; comment\n\
+                    #TABLE A\n\
+                    #ColumnType  Byte  DWORD\n\
+                    #columnname\tX\tY\t\t\n\
+                    #record 1 2\n\
+                    #record\t3\t4\t\n\
+                    #table\tB\n\
+                    #columntype\tstring\tstring\t\n\
+                    #columnname\tS\tT\n\
+                    #record\ta\t\t\n\
+                    #record\t\t\n";
+        let tables = read(text).unwrap();
+        assert_eq!(tables.iter().map(|t| &t.name[..]).collect::<Vec<_>>(),
+                   vec!["A", "B"]);
+        assert_eq!(tables[0].file.schema.data_columns().len(), 2);
+        assert_eq!(tables[0].file.data[1].data[2], ShnCell::UnsignedInteger(4));
+        let empty = ShnCell::StringZeroTerminated(String::new());
+        assert_eq!(tables[1].file.data[0].data[1..].to_vec(),
+                   vec![ShnCell::StringZeroTerminated("a".to_owned()), empty.clone()]);
+        assert_eq!(tables[1].file.data[1].data[1..].to_vec(),
+                   vec![empty.clone(), empty]);
+    }
+
+    #[test]
+    fn index_columns() {
+        // `index` has no type of its own and is written back as `string[32]`
+        let text = "#table\tT\n#columntype\tindex\n#columnname\tInxName\n\
+                    #record\tShortSword\n";
+        let tables = read(text).unwrap();
+        let column = &tables[0].file.schema.data_columns()[0];
+        assert_eq!((column.data_type.clone(), column.data_length),
+                   (ShnDataType::StringFixedLen, 32));
+        let written = write(&tables);
+        assert!(written.contains("#columntype\tstring[32]\n"));
+        assert_eq!(read(&written).unwrap(), tables);
+    }
+
+    #[test]
+    fn write_file() {
+        let dir = env::temp_dir().join(format!("shn-shine-{}", process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("Items.txt");
+        let enc: EncodingRef = ASCII;
+        let mut tables = read(TEXT).unwrap();
+        write_shine_file(&path, &tables, &enc).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), TEXT);
+
+        // A failed write leaves the previous file intact
+        tables[1].file.data[0].data[2] = ShnCell::StringZeroTerminated("a\tb".to_owned());
+        match write_shine_file(&path, &tables, &enc) {
+            Err(ShnError::InvalidValue(_)) => {},
+            r => panic!("{:?}", r),
+        }
+        assert_eq!(fs::read_to_string(&path).unwrap(), TEXT);
+        assert!(!dir.join("Items.txt.tmp").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn errors() {
+        let head = "#table\tT\n#columntype\tbyte\tstring[4]\n#columnname\tA\tB\n";
+        assert_eq!(error(&format!("{}#record\t1\n", head)),
+                   (4, "expected 2 values, found 1".to_owned()));
+        assert_eq!(error(&format!("{}#record 1 a b\n", head)),
+                   (4, "expected 2 values, found 3, strings holding spaces need \
+                        to be separated by tabs".to_owned()));
+        assert_eq!(error(&format!("{}#record\t300\ta\n", head)),
+                   (4, "`300` is not a valid Byte in column `A`".to_owned()));
+        assert_eq!(error(&format!("{}#record\t1\tabcde\n", head)),
+                   (4, "`abcde` does not fit into 4 bytes in column `B`".to_owned()));
+        assert_eq!(error("#record\t1\n"), (1, "`#record` outside of a table".to_owned()));
+        assert_eq!(error("#table\n"), (1, "missing table name".to_owned()));
+        assert_eq!(error("#table\tT\n#columntype\tqword\n"),
+                   (2, "unknown column type `qword`".to_owned()));
+        assert_eq!(error("#table\tT\n#columntype\tbyte\tbyte\n#columnname\tA\t\tB\n"),
+                   (3, "empty column name".to_owned()));
+        assert_eq!(error("#table\tT\n#columntype\tbyte\n#end\n"),
+                   (3, "`T` lacks #columntype or #columnname".to_owned()));
+        assert_eq!(error("#table\tT\n#columntype\tbyte\n#columnname\tA\tB\n"),
+                   (3, "`T` has 1 column types but 2 column names".to_owned()));
+        assert_eq!(error(&format!("{}#record\t1\ta\n#columnname\tC\tD\n", head)),
+                   (5, "unexpected `#columnname`".to_owned()));
+        assert_eq!(error("#table\tT\n#define\tX\n"),
+                   (2, "unexpected `#define`".to_owned()));
+        match read("#table\tT\n#columntype\tbyte\tbyte\n#columnname\tA\tA\n") {
+            Err(ShnError::DuplicateColumn(ref c)) => assert_eq!(c, "A"),
+            r => panic!("{:?}", r),
+        }
+
+        let mut tables = read(&format!("{}#record\t1\ta\n", head)).unwrap();
+        tables[0].file.data[0].data[2] = ShnCell::StringFixedLen("a\tb".to_owned());
+        let enc: EncodingRef = ASCII;
+        match write_shine_to(&mut Vec::new(), &tables, &enc) {
+            Err(ShnError::InvalidValue(_)) => {},
+            r => panic!("{:?}", r),
+        }
+    }
+}
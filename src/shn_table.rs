@@ -0,0 +1,548 @@
+use super::shn::{
+    SHN_CRYPT_HEADER_LEN,
+    Result,
+    ShnSchema,
+    ShnFile,
+    ShnRow,
+    ShnCell,
+    ShnDataType,
+    ShnError,
+};
+
+use ::std::sync::Arc;
+
+/// Reads a little endian `$t` from the first `$n` bytes of `$buf`. The bytes
+/// are copied first, as cells within a row are not aligned.
+macro_rules! read_le {
+    ($t:ty, $n:expr, $buf:expr) => {{
+        let mut bytes = [0; $n];
+        bytes.copy_from_slice(&$buf[..$n]);
+        <$t>::from_le_bytes(bytes)
+    }}
+}
+
+/// Where the cells of a column are stored within a `ShnTable`.
+#[derive(Clone, Copy, Debug)]
+enum Slot {
+    /// Offset within the fixed-size part of a row
+    Fixed(usize),
+    /// Index within the strings of a row
+    String(usize),
+}
+
+/// A borrowed view of a single cell within a `ShnTable`.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ShnCellRef<'a> {
+    /// A cell containing a `StringFixedLen` type value
+    StringFixedLen(&'a str),
+    /// A cell containing a `StringZeroTerminated` type value
+    StringZeroTerminated(&'a str),
+    /// A cell containing a `Byte` type value
+    Byte(u8),
+    /// A cell containing a `SignedByte` type value
+    SignedByte(i8),
+    /// A cell containing a `SignedShort` type value
+    SignedShort(i16),
+    /// A cell containing a `UnsignedShort` type value
+    UnsignedShort(u16),
+    /// A cell containing a `SignedInteger` type value
+    SignedInteger(i32),
+    /// A cell containing a `UnsignedInteger` type value
+    UnsignedInteger(u32),
+    /// A cell containing a `SingleFloatingPoint` type value
+    SingleFloatingPoint(f32),
+}
+
+impl<'a> ShnCellRef<'a> {
+    /// Returns an owned copy of the cell.
+    pub fn to_cell(&self) -> ShnCell {
+        match *self {
+            ShnCellRef::StringFixedLen(s)
+                => ShnCell::StringFixedLen(s.to_owned()),
+            ShnCellRef::StringZeroTerminated(s)
+                => ShnCell::StringZeroTerminated(s.to_owned()),
+            ShnCellRef::Byte(v)                 => ShnCell::Byte(v),
+            ShnCellRef::SignedByte(v)           => ShnCell::SignedByte(v),
+            ShnCellRef::SignedShort(v)          => ShnCell::SignedShort(v),
+            ShnCellRef::UnsignedShort(v)        => ShnCell::UnsignedShort(v),
+            ShnCellRef::SignedInteger(v)        => ShnCell::SignedInteger(v),
+            ShnCellRef::UnsignedInteger(v)      => ShnCell::UnsignedInteger(v),
+            ShnCellRef::SingleFloatingPoint(v)
+                => ShnCell::SingleFloatingPoint(v),
+        }
+    }
+}
+
+/// A compact in-memory representation of the rows of a `SHN` file.
+///
+/// Instead of a `Vec<ShnCell>` per row, all numeric cells are stored in a
+/// single flat buffer with a fixed offset per column, and strings in a
+/// separate buffer with a fixed index per column. The schema is only held
+/// once, rows are accessed through `ShnTableRow` handles borrowing the table.
+#[derive(Clone, Debug)]
+pub struct ShnTable {
+    /// The cryptographic header
+    pub crypt_header:   [u8; SHN_CRYPT_HEADER_LEN],
+    /// The header (unknown purpose)
+    pub header:         u32,
+    schema:             Arc<ShnSchema>,
+    slots:              Vec<Slot>,
+    /// Number of bytes of each row within `fixed`
+    stride:             usize,
+    /// Number of strings of each row within `strings`
+    string_count:       usize,
+    fixed:              Vec<u8>,
+    strings:            Vec<String>,
+    len:                usize,
+}
+
+impl ShnTable {
+    /// Constructs a new, empty `ShnTable`.
+    pub fn new(crypt_header: [u8; SHN_CRYPT_HEADER_LEN],
+               header: u32,
+               schema: Arc<ShnSchema>)
+               -> Self {
+        let mut slots = Vec::with_capacity(schema.columns.len());
+        let mut stride = 0;
+        let mut string_count = 0;
+        for column in &schema.columns {
+            match column.data_type {
+                ShnDataType::StringFixedLen |
+                ShnDataType::StringZeroTerminated => {
+                    slots.push(Slot::String(string_count));
+                    string_count += 1;
+                },
+                ref t => {
+                    slots.push(Slot::Fixed(stride));
+                    stride += t.default_length();
+                },
+            }
+        }
+        ShnTable {
+            crypt_header:   crypt_header,
+            header:         header,
+            schema:         schema,
+            slots:          slots,
+            stride:         stride,
+            string_count:   string_count,
+            fixed:          Vec::new(),
+            strings:        Vec::new(),
+            len:            0,
+        }
+    }
+
+    /// Constructs a `ShnTable` holding the same data as `file`.
+    pub fn from_file(file: &ShnFile) -> Result<Self> {
+        let mut table = ShnTable::new(file.crypt_header,
+                                      file.header,
+                                      file.schema.clone());
+        try!(table.reserve(file.data.len()));
+        for row in &file.data {
+            try!(table.push_row(row));
+        }
+        Ok(table)
+    }
+
+    /// Constructs a `ShnFile` holding the same data as this table.
+    pub fn to_file(&self) -> ShnFile {
+        let mut file = ShnFile::new(self.crypt_header,
+                                    self.header,
+                                    self.schema.clone());
+        file.data = self.rows().map(|r| r.to_row()).collect();
+        file
+    }
+
+    /// Returns the schema defining the table.
+    pub fn schema(&self) -> &Arc<ShnSchema> {
+        &self.schema
+    }
+
+    /// Returns the number of rows.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns whether the table contains no rows.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Reserves capacity for at least `additional` more rows. Fails with
+    /// `ShnError::CapacityOverflow` if the memory can not be allocated.
+    pub fn reserve(&mut self, additional: usize) -> Result<()> {
+        let fixed = try!(additional.checked_mul(self.stride)
+                         .ok_or(ShnError::CapacityOverflow));
+        let strings = try!(additional.checked_mul(self.string_count)
+                           .ok_or(ShnError::CapacityOverflow));
+        try!(self.fixed.try_reserve(fixed)
+             .map_err(|_| ShnError::CapacityOverflow));
+        self.strings.try_reserve(strings)
+            .map_err(|_| ShnError::CapacityOverflow)
+    }
+
+    /// Returns a handle to the row at `index`.
+    pub fn row<'a>(&'a self, index: usize) -> Option<ShnTableRow<'a>> {
+        if index < self.len {
+            Some(ShnTableRow {
+                table:  self,
+                index:  index,
+            })
+        } else {
+            None
+        }
+    }
+
+    /// Returns an iterator over handles to all rows.
+    pub fn rows<'a>(&'a self) -> ShnTableRows<'a> {
+        ShnTableRows {
+            table:  self,
+            index:  0,
+        }
+    }
+
+    /// Appends a row (and checks it to be conform to the schema)
+    pub fn push_row(&mut self, row: &ShnRow) -> Result<()> {
+        if !Arc::ptr_eq(&row.schema, &self.schema)
+            && row.schema != self.schema {
+            return Err(ShnError::InvalidSchema);
+        }
+        try!(self.check_cells(&row.data));
+        for cell in &row.data {
+            self.push_cell(cell.clone());
+        }
+        self.len += 1;
+        Ok(())
+    }
+
+    /// Appends a row consisting of `cells`, which need to match the types of
+    /// the columns of the schema and fit into their lengths.
+    pub fn push_cells(&mut self, cells: Vec<ShnCell>) -> Result<()> {
+        try!(self.check_cells(&cells));
+        for cell in cells {
+            self.push_cell(cell);
+        }
+        self.len += 1;
+        Ok(())
+    }
+
+    /// Replaces the cell at `row` and `column`, which needs to match the
+    /// type of the column and fit into its length.
+    pub fn set(&mut self, row: usize, column: usize, cell: ShnCell)
+               -> Result<()> {
+        if row >= self.len {
+            return Err(ShnError::InvalidSchema);
+        }
+        match self.schema.columns.get(column) {
+            Some(c) if c.data_type == cell.data_type()
+                => try!(c.check_length(&cell)),
+            _   => return Err(ShnError::InvalidSchema),
+        }
+        match (self.slots[column], cell) {
+            (Slot::String(i), ShnCell::StringFixedLen(s)) |
+            (Slot::String(i), ShnCell::StringZeroTerminated(s)) => {
+                self.strings[row * self.string_count + i] = s;
+            },
+            (Slot::Fixed(o), cell) => {
+                let start = row * self.stride + o;
+                ShnTable::encode(&mut self.fixed[start..], &cell);
+            },
+            _ => unreachable!(),
+        }
+        Ok(())
+    }
+
+    fn check_cells(&self, cells: &[ShnCell]) -> Result<()> {
+        if cells.len() != self.schema.columns.len() {
+            return Err(ShnError::InvalidSchema);
+        }
+        for (cell, column) in cells.iter().zip(self.schema.columns.iter()) {
+            if cell.data_type() != column.data_type {
+                return Err(ShnError::InvalidSchema);
+            }
+            try!(column.check_length(cell));
+        }
+        Ok(())
+    }
+
+    /// Appends a single, already type checked cell to the current row.
+    fn push_cell(&mut self, cell: ShnCell) {
+        match cell {
+            ShnCell::StringFixedLen(s) |
+            ShnCell::StringZeroTerminated(s) => self.strings.push(s),
+            cell => {
+                let start = self.fixed.len();
+                let len = cell.data_type().default_length();
+                self.fixed.resize(start + len, 0);
+                ShnTable::encode(&mut self.fixed[start..], &cell);
+            },
+        }
+    }
+
+    fn encode(buf: &mut [u8], cell: &ShnCell) {
+        match *cell {
+            ShnCell::Byte(v)                => buf[0] = v,
+            ShnCell::SignedByte(v)          => buf[0] = v as u8,
+            ShnCell::SignedShort(v)
+                => buf[..2].copy_from_slice(&v.to_le_bytes()),
+            ShnCell::UnsignedShort(v)
+                => buf[..2].copy_from_slice(&v.to_le_bytes()),
+            ShnCell::SignedInteger(v)
+                => buf[..4].copy_from_slice(&v.to_le_bytes()),
+            ShnCell::UnsignedInteger(v)
+                => buf[..4].copy_from_slice(&v.to_le_bytes()),
+            ShnCell::SingleFloatingPoint(v)
+                => buf[..4].copy_from_slice(&v.to_le_bytes()),
+            ShnCell::StringFixedLen(_) |
+            ShnCell::StringZeroTerminated(_) => unreachable!(),
+        }
+    }
+
+    fn get<'a>(&'a self, row: usize, column: usize) -> Option<ShnCellRef<'a>> {
+        let slot = match self.slots.get(column) {
+            Some(slot)  => *slot,
+            None        => return None,
+        };
+        let data_type = &self.schema.columns[column].data_type;
+        Some(match slot {
+            Slot::String(i) => {
+                let s = &self.strings[row * self.string_count + i][..];
+                match *data_type {
+                    ShnDataType::StringFixedLen => ShnCellRef::StringFixedLen(s),
+                    _ => ShnCellRef::StringZeroTerminated(s),
+                }
+            },
+            Slot::Fixed(o) => {
+                let buf = &self.fixed[row * self.stride + o..];
+                match *data_type {
+                    ShnDataType::Byte
+                        => ShnCellRef::Byte(buf[0]),
+                    ShnDataType::SignedByte
+                        => ShnCellRef::SignedByte(buf[0] as i8),
+                    ShnDataType::SignedShort
+                        => ShnCellRef::SignedShort(read_le!(i16, 2, buf)),
+                    ShnDataType::UnsignedShort
+                        => ShnCellRef::UnsignedShort(read_le!(u16, 2, buf)),
+                    ShnDataType::SignedInteger
+                        => ShnCellRef::SignedInteger(read_le!(i32, 4, buf)),
+                    ShnDataType::UnsignedInteger
+                        => ShnCellRef::UnsignedInteger(read_le!(u32, 4, buf)),
+                    ShnDataType::SingleFloatingPoint
+                        => ShnCellRef::SingleFloatingPoint(read_le!(f32, 4, buf)),
+                    ShnDataType::StringFixedLen |
+                    ShnDataType::StringZeroTerminated => unreachable!(),
+                }
+            },
+        })
+    }
+}
+
+/// A handle to a single row within a `ShnTable`.
+#[derive(Clone, Copy, Debug)]
+pub struct ShnTableRow<'a> {
+    table:  &'a ShnTable,
+    index:  usize,
+}
+
+impl<'a> ShnTableRow<'a> {
+    /// Returns the position of the row within the table.
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    /// Returns the cell in the column at `column`.
+    pub fn get(&self, column: usize) -> Option<ShnCellRef<'a>> {
+        self.table.get(self.index, column)
+    }
+
+    /// Returns the cell in the column named `name`.
+    pub fn get_by_name(&self, name: &str) -> Option<ShnCellRef<'a>> {
+        self.table.schema.columns.iter()
+            .position(|c| c.name == name)
+            .and_then(|i| self.get(i))
+    }
+
+    /// Returns an iterator over all cells of the row.
+    pub fn cells(&self) -> ShnTableCells<'a> {
+        ShnTableCells {
+            row:    *self,
+            column: 0,
+        }
+    }
+
+    /// Returns an owned copy of the row.
+    pub fn to_row(&self) -> ShnRow {
+        ShnRow {
+            schema: self.table.schema.clone(),
+            data:   self.cells().map(|c| c.to_cell()).collect(),
+        }
+    }
+}
+
+/// Iterator over the rows of a `ShnTable`, see `ShnTable::rows`.
+pub struct ShnTableRows<'a> {
+    table:  &'a ShnTable,
+    index:  usize,
+}
+
+impl<'a> Iterator for ShnTableRows<'a> {
+    type Item = ShnTableRow<'a>;
+
+    fn next(&mut self) -> Option<ShnTableRow<'a>> {
+        let row = self.table.row(self.index);
+        if row.is_some() {
+            self.index += 1;
+        }
+        row
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = self.table.len - self.index;
+        (remaining, Some(remaining))
+    }
+}
+
+/// Iterator over the cells of a `ShnTableRow`, see `ShnTableRow::cells`.
+pub struct ShnTableCells<'a> {
+    row:    ShnTableRow<'a>,
+    column: usize,
+}
+
+impl<'a> Iterator for ShnTableCells<'a> {
+    type Item = ShnCellRef<'a>;
+
+    fn next(&mut self) -> Option<ShnCellRef<'a>> {
+        let cell = self.row.get(self.column);
+        if cell.is_some() {
+            self.column += 1;
+        }
+        cell
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::shn::ShnColumn;
+
+    fn file() -> ShnFile {
+        let mut schema = ShnSchema::with_id_column();
+        schema.columns.push(ShnColumn::new_signed_byte("A"));
+        schema.columns.push(ShnColumn::new_string_fixed_len("B", 8));
+        schema.columns.push(ShnColumn::new_single_floating_point("C"));
+        schema.columns.push(ShnColumn::new_string_terminated("D"));
+        let schema = Arc::new(schema);
+        let mut file = ShnFile::new([1; SHN_CRYPT_HEADER_LEN], 5, schema.clone());
+        for i in 0..10 {
+            file.append_row(ShnRow {
+                schema: schema.clone(),
+                data:   vec![ShnCell::UnsignedShort(i),
+                             ShnCell::SignedByte(-(i as i8)),
+                             ShnCell::StringFixedLen(format!("b{}", i)),
+                             ShnCell::SingleFloatingPoint(i as f32 / 2.0),
+                             ShnCell::StringZeroTerminated(format!("d{}", i))],
+            }).unwrap();
+        }
+        file
+    }
+
+    #[test]
+    fn file_round_trip() {
+        let file = file();
+        let table = ShnTable::from_file(&file).unwrap();
+        assert_eq!(table.len(), 10);
+        assert_eq!(table.row(3).unwrap().get_by_name("C"),
+                   Some(ShnCellRef::SingleFloatingPoint(1.5)));
+        assert_eq!(table.row(4).unwrap().get(2),
+                   Some(ShnCellRef::StringFixedLen("b4")));
+        assert!(table.row(10).is_none());
+        assert_eq!(table.to_file(), file);
+    }
+
+    #[test]
+    fn set_cells() {
+        let mut table = ShnTable::from_file(&file()).unwrap();
+        table.set(2, 1, ShnCell::SignedByte(100)).unwrap();
+        table.set(2, 4, ShnCell::StringZeroTerminated("x".to_owned())).unwrap();
+        let row = table.row(2).unwrap();
+        assert_eq!(row.get(1), Some(ShnCellRef::SignedByte(100)));
+        assert_eq!(row.get(4), Some(ShnCellRef::StringZeroTerminated("x")));
+        assert!(table.set(2, 1, ShnCell::Byte(1)).is_err());
+        assert!(table.set(10, 1, ShnCell::SignedByte(1)).is_err());
+        assert!(table.set(2, 5, ShnCell::SignedByte(1)).is_err());
+    }
+
+    #[test]
+    fn push_checks_cells() {
+        let file = file();
+        let mut table = ShnTable::from_file(&file).unwrap();
+        assert!(table.push_cells(vec![ShnCell::UnsignedShort(0)]).is_err());
+        let mut cells = table.row(0).unwrap().to_row().data;
+        cells[3] = ShnCell::UnsignedInteger(1);
+        assert!(table.push_cells(cells).is_err());
+
+        // Strings need to fit into their columns
+        let mut cells = table.row(0).unwrap().to_row().data;
+        cells[2] = ShnCell::StringFixedLen("too long!".to_owned());
+        match table.push_cells(cells.clone()) {
+            Err(ShnError::StringTooLong(_, 8)) => {},
+            r => panic!("{:?}", r),
+        }
+        let mut row = file.data[0].clone();
+        row.data = cells;
+        assert!(table.push_row(&row).is_err());
+        match table.set(0, 2, ShnCell::StringFixedLen("123456789".to_owned())) {
+            Err(ShnError::StringTooLong(_, 8)) => {},
+            r => panic!("{:?}", r),
+        }
+        table.set(0, 2, ShnCell::StringFixedLen("12345678".to_owned())).unwrap();
+        assert_eq!(table.len(), 10);
+        assert_eq!(table.row(0).unwrap().get(2),
+                   Some(ShnCellRef::StringFixedLen("12345678")));
+    }
+
+    #[test]
+    fn unaligned_cells() {
+        // Every integer column starts at an odd offset within the row
+        let mut schema = ShnSchema::with_id_column();
+        schema.columns.push(ShnColumn::new_byte("A"));
+        schema.columns.push(ShnColumn::new_unsigned_integer("B"));
+        schema.columns.push(ShnColumn::new_signed_short("C"));
+        schema.columns.push(ShnColumn::new_signed_integer("D"));
+        schema.columns.push(ShnColumn::new_single_floating_point("E"));
+        let mut table = ShnTable::new([0; SHN_CRYPT_HEADER_LEN], 0,
+                                      Arc::new(schema));
+        for i in 0..5u16 {
+            table.push_cells(vec![ShnCell::UnsignedShort(i),
+                                  ShnCell::Byte(i as u8),
+                                  ShnCell::UnsignedInteger(0xdead_beef - i as u32),
+                                  ShnCell::SignedShort(-(i as i16) * 1000),
+                                  ShnCell::SignedInteger(-70000 * i as i32),
+                                  ShnCell::SingleFloatingPoint(i as f32 + 0.25)])
+                .unwrap();
+        }
+        table.set(3, 2, ShnCell::UnsignedInteger(7)).unwrap();
+        let cells: Vec<_> = table.row(3).unwrap().cells().collect();
+        assert_eq!(cells, vec![ShnCellRef::UnsignedShort(3), ShnCellRef::Byte(3),
+                               ShnCellRef::UnsignedInteger(7),
+                               ShnCellRef::SignedShort(-3000),
+                               ShnCellRef::SignedInteger(-210000),
+                               ShnCellRef::SingleFloatingPoint(3.25)]);
+        assert_eq!(table.row(4).unwrap().get(2),
+                   Some(ShnCellRef::UnsignedInteger(0xdead_beeb)));
+    }
+
+    #[test]
+    fn reserve_overflow() {
+        let mut table = ShnTable::from_file(&file()).unwrap();
+        table.reserve(100).unwrap();
+        match table.reserve(usize::max_value() / 2) {
+            Err(ShnError::CapacityOverflow) => {},
+            r => panic!("{:?}", r),
+        }
+        match table.reserve(usize::max_value() / 16) {
+            Err(ShnError::CapacityOverflow) => {},
+            r => panic!("{:?}", r),
+        }
+        assert_eq!(table.len(), 10);
+    }
+}
@@ -0,0 +1,22 @@
+use ::std::sync::Arc;
+
+use super::shn::{ SHN_CRYPT_HEADER_LEN, ShnCell, ShnColumn, ShnSchema, ShnRow,
+                  ShnFile };
+
+/// Constructs a table of `columns` after the `__ID__` pseudo-column, holding
+/// `rows`, which are numbered in order.
+pub fn table(columns: Vec<ShnColumn>, rows: Vec<Vec<ShnCell>>) -> ShnFile {
+    let mut schema = ShnSchema::with_id_column();
+    schema.columns.extend(columns);
+    let schema = Arc::new(schema);
+    let mut file = ShnFile::new([0; SHN_CRYPT_HEADER_LEN], 0, schema.clone());
+    for (i, cells) in rows.into_iter().enumerate() {
+        let mut data = vec![ShnCell::UnsignedShort(i as u16)];
+        data.extend(cells);
+        file.append_row(ShnRow {
+            schema: schema.clone(),
+            data:   data,
+        }).unwrap();
+    }
+    file
+}
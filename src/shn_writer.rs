@@ -1,14 +1,19 @@
 use super::shn::{
+    SHN_CRYPT_HEADER_LEN,
     Endianess,
     Result,
     ShnFile,
+    ShnSchema,
     ShnRow,
     ShnColumn,
     ShnCell,
     ShnError,
+    ShnCipher,
     decrypt
 };
-use ::std::io::{ Write, Cursor };
+use ::std::cmp;
+use ::std::io::{ Read, Write, Seek, SeekFrom, Cursor };
+use ::std::sync::Arc;
 
 use ::byteorder::WriteBytesExt;
 use ::encoding::{ EncoderTrap, EncodingRef };
@@ -30,47 +35,66 @@ impl ShnWriter {
         try!(writer.write_all(&crypt_header[..])
              .map_err(|_| ShnError::InvalidFile));
 
-        // TODO: Write data length.
         let mut buf_wrt = Cursor::new(Vec::<u8>::new());
-        // TODO: Add error enum for this kinda stuff!
-        try!(buf_wrt.write_u32::<Endianess>(header)
-             .map_err(|_| ShnError::InvalidFile));
-        try!(buf_wrt.write_u32::<Endianess>(data.len() as u32)
-             .map_err(|_| ShnError::InvalidFile));
-        try!(buf_wrt.write_i32::<Endianess>(schema.calculate_record_length())
-             .map_err(|_| ShnError::InvalidFile));
-        try!(buf_wrt.write_u32::<Endianess>(schema.columns.len() as u32)
-             .map_err(|_| ShnError::InvalidFile));
-
-        try!(ShnWriter::write_schema(file, enc, &mut buf_wrt));
+        try!(ShnWriter::write_preamble(header,
+                                       data.len() as u32,
+                                       &schema,
+                                       enc,
+                                       &mut buf_wrt));
         try!(ShnWriter::write_rows(file, enc, &mut buf_wrt));
 
         let mut buf = buf_wrt.into_inner();
         decrypt(&mut buf[..]);
 
+        try!(writer.write_i32::<Endianess>((buf.len() + 0x24) as i32)
+             .map_err(|_| ShnError::InvalidFile));
         try!(writer.write_all(&buf[..])
              .map_err(|_| ShnError::InvalidFile));
         Ok(())
     }
 
-    fn write_schema<T>(file: &ShnFile, enc: &EncodingRef, writer: &mut T)
+    /// Writes the unencrypted part preceding the rows, that is the header,
+    /// the record count, the record length and the column definitions.
+    fn write_preamble<T>(header: u32,
+                         record_count: u32,
+                         schema: &ShnSchema,
+                         enc: &EncodingRef,
+                         writer: &mut T)
+                         -> Result<()>
+                         where T: Write + WriteBytesExt {
+        try!(schema.validate());
+        // TODO: Add error enum for this kinda stuff!
+        try!(writer.write_u32::<Endianess>(header)
+             .map_err(|_| ShnError::InvalidFile));
+        try!(writer.write_u32::<Endianess>(record_count)
+             .map_err(|_| ShnError::InvalidFile));
+        let record_length = try!(schema.declared_record_length());
+        try!(writer.write_u32::<Endianess>(record_length)
+             .map_err(|_| ShnError::InvalidFile));
+        // The pseudo-column is not part of the column count either.
+        try!(writer.write_u32::<Endianess>(schema.data_columns().len() as u32)
+             .map_err(|_| ShnError::InvalidFile));
+        ShnWriter::write_schema(schema, enc, writer)
+    }
+
+    fn write_schema<T>(schema: &ShnSchema, enc: &EncodingRef, writer: &mut T)
                        -> Result<()>
                        where T: Write + WriteBytesExt {
-        let schema = file.schema.clone();
         /* We want to skip the first item as it is only a pseudo-column that
          * does not in fact appear within the specification of the file. Still
          * no idea as to why it is there. see ShnReader::read_schema in shn.rs
          * for more information
          */
-        let mut iter = schema.columns.iter();
-        iter.next();
-        for column in iter {
+        for column in schema.data_columns() {
             // TODO: Move max length into a constant
             let mut buf = Vec::with_capacity(48);
             try!(enc.encode_to(&column.name, EncoderTrap::Strict, &mut buf)
                  .map_err(|_| ShnError::InvalidEncoding));
+            if buf.len() > 48 {
+                return Err(ShnError::InvalidSchema);
+            }
             // Fill in the rest of the 48 bytes with 0's.
-            for _ in 48..buf.len() { buf.push(0); };
+            buf.resize(48, 0);
             let ctype = column.data_type.to_id();
             let clen = column.data_length;
             // TODO: Better enums!
@@ -96,9 +120,12 @@ impl ShnWriter {
     fn write_row<T>(row: &ShnRow, enc: &EncodingRef, writer: &mut T)
                     -> Result<()>
                     where T: Write + WriteBytesExt {
-        for i in 0..row.schema.columns.len() {
-            let cell: &ShnCell = row.data.get(i).unwrap();
-            let column: &ShnColumn = row.schema.columns.get(i).unwrap();
+        if row.data.len() != row.schema.columns.len() {
+            return Err(ShnError::InvalidSchema);
+        }
+        for (cell, column) in row.data.iter().zip(row.schema.columns.iter()) {
+            let cell: &ShnCell = cell;
+            let column: &ShnColumn = column;
             let data_len = column.data_length;
             try!(ShnWriter::write_cell(cell, data_len, enc, writer));
         }
@@ -137,8 +164,11 @@ impl ShnWriter {
                 let mut buf = Vec::with_capacity(data_length as usize);
                 try!(enc.encode_to(&st, EncoderTrap::Strict, &mut buf)
                      .map_err(|_| ShnError::InvalidEncoding));
+                if buf.len() > data_length as usize {
+                    return Err(ShnError::InvalidSchema);
+                }
                 // Fill remaining bytes with 0's.
-                for _ in 48..buf.len() { buf.push(0); }
+                buf.resize(data_length as usize, 0);
                 try!(writer.write_all(&buf[..])
                      .map_err(|_| ShnError::InvalidFile));
             },
@@ -146,7 +176,7 @@ impl ShnWriter {
                 let mut buf = Vec::new();
                 try!(enc.encode_to(&st, EncoderTrap::Strict, &mut buf)
                      .map_err(|_| ShnError::InvalidEncoding));
-                if buf[buf.len() - 1] != 0 { buf.push(0); }
+                if buf.last() != Some(&0) { buf.push(0); }
                 try!(writer.write_all(&buf[..])
                      .map_err(|_| ShnError::InvalidFile));
             }
@@ -154,3 +184,284 @@ impl ShnWriter {
         Ok(())
     }
 }
+
+/// Writes a `ShnFile` row by row, without keeping the rows in memory.
+///
+/// The cipher depends on the length of the data, which is only known once
+/// all rows have been written. Rows are therefore encrypted as if the data
+/// was empty and written to `dest` right away. `finish` patches in the data
+/// length and the record count and fixes up the encryption in place, which
+/// is why `dest` needs to be readable as well. To write to a sink that can't
+/// seek, write to a `Cursor<Vec<u8>>` and copy the result.
+pub struct ShnStreamWriter<D> {
+    dest:           D,
+    schema:         Arc<ShnSchema>,
+    enc:            EncodingRef,
+    header:         u32,
+    /// Position of the data length field within `dest`
+    start:          u64,
+    record_count:   u32,
+    /// Number of bytes written after the data length field
+    len:            usize,
+    /// Cipher keyed as if the data was `0` bytes long
+    cipher:         ShnCipher,
+}
+
+impl<D> ShnStreamWriter<D> where D: Read + Write + Seek {
+    /// Starts a new file at the current position of `dest` by writing the
+    /// crypt header and encoding the schema.
+    pub fn new(mut dest: D,
+               schema: Arc<ShnSchema>,
+               crypt_header: &[u8; SHN_CRYPT_HEADER_LEN],
+               header: u32,
+               enc: &EncodingRef)
+               -> Result<Self> {
+        try!(dest.write_all(&crypt_header[..])
+             .map_err(|_| ShnError::InvalidFile));
+        let start = try!(dest.stream_position()
+                         .map_err(|_| ShnError::InvalidFile));
+        // Placeholder until we know the actual length.
+        try!(dest.write_i32::<Endianess>(0)
+             .map_err(|_| ShnError::InvalidFile));
+
+        let mut writer = ShnStreamWriter {
+            dest:           dest,
+            schema:         schema,
+            enc:            *enc,
+            header:         header,
+            start:          start,
+            record_count:   0,
+            len:            0,
+            cipher:         ShnCipher::with_key(0),
+        };
+        let mut buf = Vec::new();
+        try!(ShnWriter::write_preamble(header, 0, &writer.schema, enc,
+                                       &mut buf));
+        try!(writer.write_encrypted(buf));
+        Ok(writer)
+    }
+
+    /// Appends a single row. The row has to be conform to the schema the
+    /// writer was created with.
+    pub fn write_row(&mut self, row: &ShnRow) -> Result<()> {
+        if !Arc::ptr_eq(&row.schema, &self.schema)
+            && row.schema != self.schema {
+            return Err(ShnError::InvalidSchema);
+        }
+        let mut buf = Vec::new();
+        try!(ShnWriter::write_row(row, &self.enc, &mut buf));
+        try!(self.write_encrypted(buf));
+        self.record_count += 1;
+        Ok(())
+    }
+
+    /// Returns the number of rows written so far.
+    pub fn record_count(&self) -> u32 {
+        self.record_count
+    }
+
+    /// Patches in the data length and the record count, finalizes the
+    /// encryption and returns the destination, positioned after the file.
+    pub fn finish(mut self) -> Result<D> {
+        let len = self.len;
+
+        /* Every byte has been encrypted with its key for a `0` byte long
+         * blob. The actual keys only differ from those by the key of the
+         * first byte, so we need to xor everything with it once more.
+         */
+        let key = ShnCipher::new(len).key();
+
+        // The record count is the second field of the encrypted data.
+        let mut count = Vec::new();
+        try!(count.write_u32::<Endianess>(self.header)
+             .map_err(|_| ShnError::InvalidFile));
+        try!(count.write_u32::<Endianess>(self.record_count)
+             .map_err(|_| ShnError::InvalidFile));
+        ShnCipher::with_key(key).apply(&mut count[..]);
+
+        try!(self.dest.seek(SeekFrom::Start(self.start))
+             .map_err(|_| ShnError::InvalidFile));
+        try!(self.dest.write_i32::<Endianess>((len + 0x24) as i32)
+             .map_err(|_| ShnError::InvalidFile));
+
+        let data_start = self.start + 4;
+        let mut buf = vec![0; 4096];
+        let mut pos = 0;
+        while pos < len {
+            let chunk = &mut buf[..cmp::min(4096, len - pos)];
+            try!(self.dest.seek(SeekFrom::Start(data_start + pos as u64))
+                 .map_err(|_| ShnError::InvalidFile));
+            try!(self.dest.read_exact(chunk).map_err(ShnError::Io));
+            for (i, b) in chunk.iter_mut().enumerate() {
+                let i = pos + i;
+                *b = if (4..8).contains(&i) { count[i] } else { *b ^ key };
+            }
+            try!(self.dest.seek(SeekFrom::Start(data_start + pos as u64))
+                 .map_err(|_| ShnError::InvalidFile));
+            try!(self.dest.write_all(chunk)
+                 .map_err(|_| ShnError::InvalidFile));
+            pos += chunk.len();
+        }
+        try!(self.dest.seek(SeekFrom::Start(data_start + len as u64))
+             .map_err(|_| ShnError::InvalidFile));
+        Ok(self.dest)
+    }
+
+    fn write_encrypted(&mut self, mut buf: Vec<u8>) -> Result<()> {
+        self.cipher.apply(&mut buf[..]);
+        try!(self.dest.write_all(&buf[..])
+             .map_err(|_| ShnError::InvalidFile));
+        self.len += buf.len();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::shn::{ SHN_ID_COLUMN_NAME, ShnDataType };
+    use super::super::shn_reader::ShnReader;
+
+    fn schema() -> Arc<ShnSchema> {
+        let mut schema = ShnSchema::with_id_column();
+        schema.columns.push(ShnColumn::new_unsigned_integer("ID"));
+        schema.columns.push(ShnColumn::new_string_fixed_len("Name", 16));
+        schema.columns.push(ShnColumn::new_string_terminated("Desc"));
+        Arc::new(schema)
+    }
+
+    fn row(schema: &Arc<ShnSchema>, i: u32) -> ShnRow {
+        ShnRow {
+            schema: schema.clone(),
+            data:   vec![ShnCell::UnsignedShort(i as u16),
+                         ShnCell::UnsignedInteger(i * 7),
+                         ShnCell::StringFixedLen(format!("item{}", i)),
+                         ShnCell::StringZeroTerminated("d".repeat(i as usize % 5))],
+        }
+    }
+
+    #[test]
+    fn stream_writer_matches_writer() {
+        let enc: EncodingRef = ::encoding::all::ASCII;
+        let schema = schema();
+        // Lengths around the 512 byte period of the cipher
+        for &count in &[0, 1, 2, 17, 40, 300] {
+            let mut file = ShnFile::new([3; SHN_CRYPT_HEADER_LEN], 42,
+                                        schema.clone());
+            let mut stream = ShnStreamWriter::new(Cursor::new(Vec::new()),
+                                                  schema.clone(),
+                                                  &[3; SHN_CRYPT_HEADER_LEN],
+                                                  42, &enc).unwrap();
+            for i in 0..count {
+                file.append_row(row(&schema, i)).unwrap();
+                stream.write_row(&row(&schema, i)).unwrap();
+            }
+            assert_eq!(stream.record_count(), count);
+            let streamed = stream.finish().unwrap().into_inner();
+
+            let mut written = Vec::new();
+            ShnWriter::write_to(&file, &enc, &mut written).unwrap();
+            assert_eq!(streamed, written);
+
+            let read = ShnReader::read_from(&streamed[..], &enc).unwrap();
+            assert_eq!(read.header, 42);
+            assert_eq!(read.data.len(), count as usize);
+            for (r, i) in read.data.iter().zip(0..) {
+                assert_eq!(r.data, row(&schema, i).data);
+            }
+        }
+    }
+
+    #[test]
+    fn stream_writer_at_offset() {
+        let enc: EncodingRef = ::encoding::all::ASCII;
+        let schema = schema();
+        let mut dest = Cursor::new(vec![9; 5]);
+        dest.set_position(5);
+        let mut stream = ShnStreamWriter::new(dest, schema.clone(),
+                                              &[0; SHN_CRYPT_HEADER_LEN],
+                                              1, &enc).unwrap();
+        stream.write_row(&row(&schema, 3)).unwrap();
+        let dest = stream.finish().unwrap();
+        let len = dest.get_ref().len() as u64;
+        assert_eq!(dest.position(), len);
+        let read = ShnReader::read_from(&dest.get_ref()[5..], &enc).unwrap();
+        assert_eq!(read.data[0].data, row(&schema, 3).data);
+    }
+
+    #[test]
+    fn stream_writer_writes_through() {
+        use ::std::{ env, fs, process };
+
+        let enc: EncodingRef = ::encoding::all::ASCII;
+        let schema = schema();
+        let path = env::temp_dir()
+            .join(format!("shn-stream-{}.shn", process::id()));
+        let dest = fs::OpenOptions::new().read(true).write(true)
+            .create(true).truncate(true)
+            .open(&path).unwrap();
+        let observer = dest.try_clone().unwrap();
+        let mut stream = ShnStreamWriter::new(dest, schema.clone(),
+                                              &[0; SHN_CRYPT_HEADER_LEN],
+                                              0, &enc).unwrap();
+        // Rows end up in the file right away rather than at `finish`
+        let mut sizes = Vec::new();
+        for i in 0..400 {
+            stream.write_row(&row(&schema, i)).unwrap();
+            sizes.push(observer.metadata().unwrap().len());
+        }
+        assert!(sizes.windows(2).all(|w| w[0] < w[1]));
+        stream.finish().unwrap();
+
+        let written = fs::read(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(written.len() as u64, *sizes.last().unwrap());
+        let read = ShnReader::read_from(&written[..], &enc).unwrap();
+        assert_eq!(read.data.len(), 400);
+        assert_eq!(read.data[399].data, row(&schema, 399).data);
+    }
+
+    #[test]
+    fn writers_require_id_column() {
+        let enc: EncodingRef = ::encoding::all::ASCII;
+        let mut schema = ShnSchema::new();
+        schema.columns.push(ShnColumn::new_unsigned_integer("ID"));
+        let schema = Arc::new(schema);
+        let file = ShnFile::new([0; SHN_CRYPT_HEADER_LEN], 0, schema.clone());
+        match ShnWriter::write_to(&file, &enc, &mut Vec::new()) {
+            Err(ShnError::MissingIdColumn) => {},
+            r => panic!("{:?}", r),
+        }
+        match ShnStreamWriter::new(Cursor::new(Vec::new()), schema,
+                                   &[0; SHN_CRYPT_HEADER_LEN], 0, &enc) {
+            Err(ShnError::MissingIdColumn) => {},
+            Err(e) => panic!("{:?}", e),
+            Ok(_) => panic!("accepted a schema without {}", SHN_ID_COLUMN_NAME),
+        }
+    }
+
+    #[test]
+    fn stream_writer_rejects_other_schema() {
+        let enc: EncodingRef = ::encoding::all::ASCII;
+        let mut other = ShnSchema::with_id_column();
+        other.columns.push(ShnColumn {
+            name:           "ID".to_owned(),
+            data_type:      ShnDataType::UnsignedShort,
+            data_length:    2,
+        });
+        let other = Arc::new(other);
+        let mut stream = ShnStreamWriter::new(Cursor::new(Vec::new()),
+                                              schema(),
+                                              &[0; SHN_CRYPT_HEADER_LEN],
+                                              0, &enc).unwrap();
+        let row = ShnRow {
+            schema: other,
+            data:   vec![ShnCell::UnsignedShort(0), ShnCell::UnsignedShort(1)],
+        };
+        match stream.write_row(&row) {
+            Err(ShnError::InvalidSchema) => {},
+            r => panic!("{:?}", r),
+        }
+        assert_eq!(stream.record_count(), 0);
+    }
+}
@@ -0,0 +1,20 @@
+// Generated from the schema of `2nd-Table` (fingerprint a18319a2fe7331df).
+// Regenerate it instead of editing it.
+
+shn_record! {
+    /// A row of `2nd-Table`
+    pub struct Table2ndTable = "2nd-Table" {
+        id: u16 = "ID", UnsignedShort(2),
+        inx_name: String = "InxName", StringFixedLen(32),
+        type_: u32 = "type", UnsignedInteger(4),
+        self_: u8 = "Self", Byte(1),
+        _2nd_class: i16 = "2ndClass", SignedShort(2),
+        max_hp: u32 = "MaxHP", UnsignedInteger(4),
+        max_hp_2: u32 = "max_hp", UnsignedInteger(4),
+        max_hp_3: u32 = "Max HP", UnsignedInteger(4),
+        is_wm_link: i8 = "IsWMLink", SignedByte(1),
+        column: i32 = "--", SignedInteger(4),
+        rate: f32 = "Rate \"%\"", SingleFloatingPoint(4),
+        desc: String = "Desc", StringZeroTerminated(0),
+    }
+}